@@ -4,18 +4,33 @@ use futures::channel::mpsc;
 use futures::executor::block_on;
 use futures::SinkExt;
 use log::trace;
-use rocksdb::{Direction, IteratorMode};
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+use std::collections::VecDeque;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use serde::Serialize;
+use spectrum_offchain::rocks;
+use spectrum_offchain::rocks::schema::{check, migrate, Migration, Schema};
+use spectrum_offchain::rocks::{RocksConfig, RocksDB};
 use tokio::task::spawn_blocking;
 
 use crate::client::Point;
+use crate::multi_era::{Era, BLOCK_ENVELOPE_HEADER};
 
-#[derive(serde::Serialize, serde::Deserialize)]
+pub mod inmemory;
+
+/// Cached block in its era-tagged encoding along with the point preceding it.
+/// Bytes of blocks beyond the rollback window are pruned.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct LinkedBlock(/*block bytes*/ pub Vec<u8>, /*prev point*/ pub Point);
 
+impl LinkedBlock {
+    pub fn is_pruned(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
 pub type Inclusive<T> = T;
 
 pub trait LedgerCache {
@@ -25,35 +40,235 @@ pub trait LedgerCache {
     async fn get_block(&self, point: Point) -> Option<LinkedBlock>;
     async fn delete(&self, point: Point) -> bool;
     fn replay<'a>(&self, from_point: Inclusive<Point>) -> impl Stream<Item = LinkedBlock> + Send + 'a;
+    /// Number of most recent blocks which are kept in full, so that they can be rolled back.
+    fn rollback_depth(&self) -> usize;
 }
 
+/// Default number of most recent blocks kept in full, which is the security parameter `k` of the mainnet.
+pub const DEFAULT_ROLLBACK_DEPTH: usize = 2160;
+
 pub struct LedgerCacheRocksDB {
-    pub db: Arc<rocksdb::OptimisticTransactionDB>,
+    pub db: Arc<RocksDB>,
+    /// Column family the blocks are kept in.
+    pub cf: String,
+    rollback_depth: usize,
+    pruner: Option<Pruner>,
+}
+
+/// Strips bytes of blocks which got deeper than `depth` from the tip in the background.
+/// Links between blocks are kept, so that the chain of points can still be traversed.
+struct Pruner {
+    /// Points of blocks within the rollback window, the most recent at the back.
+    window: Mutex<VecDeque<Point>>,
+    depth: usize,
+    /// Points to strip blocks at. Older blocks which are not stripped yet are pruned as well.
+    prune: std::sync::mpsc::Sender<Point>,
+}
+
+impl Pruner {
+    fn spawn(db: Arc<RocksDB>, cf: String, depth: usize) -> Self {
+        let mut window = VecDeque::with_capacity(depth + 1);
+        let (prune, to_prune) = std::sync::mpsc::channel();
+        {
+            let cf = db.cf_handle(&cf).unwrap();
+            let mut next_point = db
+                .get_cf(&cf, LATEST_POINT)
+                .unwrap()
+                .and_then(|raw| bincode::deserialize::<Point>(&raw).ok());
+            while let Some(point) = next_point {
+                let block = db
+                    .get_cf(&cf, point_key(POINT_PREFIX, &point))
+                    .unwrap()
+                    .and_then(|raw| bincode::deserialize::<LinkedBlock>(&raw).ok());
+                match block {
+                    Some(LinkedBlock(_, prev_point)) if window.len() < depth => {
+                        window.push_front(point);
+                        next_point = Some(prev_point);
+                    }
+                    // Blocks beyond the window which were not pruned before.
+                    Some(_) => {
+                        prune.send(point).unwrap();
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+        std::thread::spawn(move || {
+            for point in to_prune {
+                strip_blocks(&db, &cf, point);
+            }
+        });
+        Self {
+            window: Mutex::new(window),
+            depth,
+            prune,
+        }
+    }
+
+    fn on_block_added(&self, point: Point) {
+        let mut window = self.window.lock().unwrap();
+        window.push_back(point);
+        if window.len() > self.depth {
+            if let Some(point) = window.pop_front() {
+                let _ = self.prune.send(point);
+            }
+        }
+    }
+
+    fn on_block_deleted(&self, point: Point) {
+        let mut window = self.window.lock().unwrap();
+        if window.back() == Some(&point) {
+            window.pop_back();
+        } else {
+            window.retain(|pt| *pt != point);
+        }
+    }
+}
+
+/// Strip bytes of the block at `point` and all its ancestors up to the first block stripped before.
+fn strip_blocks(db: &RocksDB, cf: &str, point: Point) {
+    let cf = db.cf_handle(cf).unwrap();
+    let mut next_point = Some(point);
+    let mut counter = 0;
+    while let Some(point) = next_point {
+        let key = point_key(POINT_PREFIX, &point);
+        next_point = match db
+            .get_cf(&cf, &key)
+            .unwrap()
+            .and_then(|raw| bincode::deserialize::<LinkedBlock>(&raw).ok())
+        {
+            Some(LinkedBlock(blk_bytes, prev_point)) if !blk_bytes.is_empty() => {
+                db.put_cf(
+                    &cf,
+                    key,
+                    bincode::serialize(&LinkedBlock(vec![], prev_point)).unwrap(),
+                )
+                .unwrap();
+                counter += 1;
+                Some(prev_point)
+            }
+            _ => None,
+        };
+    }
+    trace!("{} blocks pruned", counter);
+}
+
+const SCHEMA: Schema = Schema {
+    store: "ledger_cache",
+    version: 2,
+    migrations: &[Migration {
+        from_version: 1,
+        description: "wrap cached blocks into era envelope",
+        apply: wrap_blocks_into_babbage_envelope,
+    }],
+};
+
+/// Number of blocks rewritten at once by [wrap_blocks_into_babbage_envelope].
+const MIGRATION_CHUNK_SIZE: usize = 1024;
+
+/// Blocks cached before multi-era support were stored without the era envelope
+/// and could only be Babbage blocks.
+/// The cache may hold the whole chain, so blocks are rewritten in bounded chunks
+/// instead of being staged in the migration batch. Blocks wrapped already are skipped,
+/// so that a migration interrupted midway is safely applied again on the next start.
+fn wrap_blocks_into_babbage_envelope(
+    db: &RocksDB,
+    cf: &str,
+    _batch: &mut WriteBatch,
+) -> Result<(), rocksdb::Error> {
+    let cf = db.cf_handle(cf).unwrap();
+    let mut chunk = WriteBatch::default();
+    for item in db.iterator_cf(
+        &cf,
+        IteratorMode::From(POINT_PREFIX.as_bytes(), Direction::Forward),
+    ) {
+        let (key, raw_blk) = item?;
+        if !key.starts_with(POINT_PREFIX.as_bytes()) {
+            break;
+        }
+        if let Ok(LinkedBlock(blk_bytes, prev_point)) = bincode::deserialize::<LinkedBlock>(&raw_blk) {
+            if blk_bytes.is_empty() || blk_bytes[0] == BLOCK_ENVELOPE_HEADER {
+                continue;
+            }
+            let mut tagged_bytes = vec![BLOCK_ENVELOPE_HEADER, Era::Babbage.tag()];
+            tagged_bytes.extend(blk_bytes);
+            chunk.put_cf(
+                &cf,
+                key,
+                bincode::serialize(&LinkedBlock(tagged_bytes, prev_point)).unwrap(),
+            );
+            if chunk.len() >= MIGRATION_CHUNK_SIZE {
+                db.write(std::mem::take(&mut chunk))?;
+            }
+        }
+    }
+    db.write(chunk)
 }
 
 impl LedgerCacheRocksDB {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self::from_config(RocksConfig::new(path.as_ref().to_string_lossy().into_owned()))
+    }
+
+    pub fn from_config(conf: RocksConfig) -> Self {
+        Self::with_rollback_depth(conf, DEFAULT_ROLLBACK_DEPTH)
+    }
+
+    /// Only the last `rollback_depth` blocks are kept in full,
+    /// only the points of the older ones are retained.
+    pub fn with_rollback_depth(conf: RocksConfig, rollback_depth: usize) -> Self {
+        let db = rocks::open(&conf);
+        let cf = conf.column_family().to_string();
+        migrate(&db, &cf, &SCHEMA).unwrap();
+        let pruner = Pruner::spawn(Arc::clone(&db), cf.clone(), rollback_depth);
         Self {
-            db: Arc::new(rocksdb::OptimisticTransactionDB::open_default(path).unwrap()),
+            db,
+            cf,
+            rollback_depth,
+            pruner: Some(pruner),
+        }
+    }
+
+    /// Open read-only secondary instance of the cache which may be in use by another process.
+    /// Writes through a secondary instance fail.
+    /// Run [rocks::catch_up_stream] on `db` to observe updates made by the primary instance.
+    /// Blocks are assumed to be pruned by the primary instance beyond [DEFAULT_ROLLBACK_DEPTH].
+    pub fn open_as_secondary(conf: RocksConfig, secondary_path: &str) -> Self {
+        let db = rocks::open_as_secondary(&conf, secondary_path);
+        let cf = conf.column_family().to_string();
+        check(&db, &cf, &SCHEMA).unwrap();
+        Self {
+            db,
+            cf,
+            rollback_depth: DEFAULT_ROLLBACK_DEPTH,
+            pruner: None,
         }
     }
 }
 
 const LATEST_POINT: &str = "a:";
-const POINT_PREFIX: &str = "b:";
+pub(crate) const POINT_PREFIX: &str = "b:";
 
 impl LedgerCache for LedgerCacheRocksDB {
     async fn set_tip(&self, point: Point) {
         let db = self.db.clone();
-        spawn_blocking(move || db.put(LATEST_POINT, bincode::serialize(&point).unwrap()).unwrap())
-            .await
-            .unwrap();
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            db.put_cf(&cf, LATEST_POINT, bincode::serialize(&point).unwrap())
+                .unwrap()
+        })
+        .await
+        .unwrap();
     }
 
     async fn get_tip(&self) -> Option<Point> {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         spawn_blocking(move || {
-            db.get(LATEST_POINT)
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, LATEST_POINT)
                 .unwrap()
                 .and_then(|raw| bincode::deserialize(&*raw).ok())
         })
@@ -63,8 +278,11 @@ impl LedgerCache for LedgerCacheRocksDB {
 
     async fn put_block(&self, point: Point, block: LinkedBlock) {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         spawn_blocking(move || {
-            db.put(
+            let cf = db.cf_handle(&cf).unwrap();
+            db.put_cf(
+                &cf,
                 point_key(POINT_PREFIX, &point),
                 bincode::serialize(&block).unwrap(),
             )
@@ -72,36 +290,54 @@ impl LedgerCache for LedgerCacheRocksDB {
         })
         .await
         .unwrap();
+        if let Some(pruner) = &self.pruner {
+            pruner.on_block_added(point);
+        }
     }
 
     async fn get_block(&self, point: Point) -> Option<LinkedBlock> {
         let db = self.db.clone();
-        spawn_blocking(move || db.get(point_key(POINT_PREFIX, &point)).unwrap())
-            .await
-            .unwrap()
-            .and_then(|raw| bincode::deserialize(raw.as_ref()).ok())
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, point_key(POINT_PREFIX, &point)).unwrap()
+        })
+        .await
+        .unwrap()
+        .and_then(|raw| bincode::deserialize(raw.as_ref()).ok())
     }
 
     async fn delete(&self, point: Point) -> bool {
         let db = self.db.clone();
-        spawn_blocking(move || db.delete(point_key(POINT_PREFIX, &point)).unwrap())
-            .await
-            .unwrap();
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            db.delete_cf(&cf, point_key(POINT_PREFIX, &point)).unwrap()
+        })
+        .await
+        .unwrap();
+        if let Some(pruner) = &self.pruner {
+            pruner.on_block_deleted(point);
+        }
         true
     }
 
     fn replay<'a>(&self, from_point: Inclusive<Point>) -> impl Stream<Item = LinkedBlock> + Send + 'a {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let (mut snd, recv) = mpsc::unbounded();
         spawn_blocking(move || {
             trace!("Replaying blocks from point {:?}", from_point);
+            let cf = db.cf_handle(&cf).unwrap();
             let key = point_key(POINT_PREFIX, &from_point);
-            let iter = db.iterator(IteratorMode::From(&key, Direction::Forward));
+            let iter = db.iterator_cf(&cf, IteratorMode::From(&key, Direction::Forward));
             let mut counter = 0;
             for item in iter {
                 if let Some(blk) = item
                     .ok()
                     .and_then(|(_, raw_blk)| bincode::deserialize::<LinkedBlock>(raw_blk.as_ref()).ok())
+                    // Pruned blocks cannot be replayed.
+                    .filter(|blk| !blk.is_pruned())
                 {
                     counter += 1;
                     block_on(snd.send(blk)).unwrap();
@@ -113,7 +349,7 @@ impl LedgerCache for LedgerCacheRocksDB {
     }
 }
 
-fn point_key(prefix: &str, point: &Point) -> Vec<u8> {
+pub(crate) fn point_key(prefix: &str, point: &Point) -> Vec<u8> {
     let mut key_bytes = Vec::from(prefix.as_bytes());
     let (slot, hash) = match point {
         Point::Origin => (0, vec![]),
@@ -123,3 +359,222 @@ fn point_key(prefix: &str, point: &Point) -> Vec<u8> {
     key_bytes.extend_from_slice(&hash);
     key_bytes
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use cml_crypto::BlockHeaderHash;
+    use futures::StreamExt;
+    use rand::{thread_rng, RngCore};
+    use spectrum_offchain::rocks;
+    use spectrum_offchain::rocks::RocksConfig;
+    use tokio::sync::Mutex;
+
+    use crate::cache::{
+        point_key, LedgerCache, LedgerCacheRocksDB, LinkedBlock, MIGRATION_CHUNK_SIZE, POINT_PREFIX,
+    };
+    use crate::client::Point;
+    use crate::event_source::{rollback, RollbackError};
+    use crate::multi_era::{Era, EraDecodingError, FromEraTaggedBytes, BLOCK_ENVELOPE_HEADER};
+
+    pub fn point(slot: u64) -> Point {
+        Point::Specific(slot, BlockHeaderHash::from([slot as u8; 32]))
+    }
+
+    /// Block of the chain where each slot is occupied, its only byte is the slot.
+    pub fn block(slot: u64) -> LinkedBlock {
+        let prev_point = if slot == 1 { Point::Origin } else { point(slot - 1) };
+        LinkedBlock(vec![slot as u8], prev_point)
+    }
+
+    pub async fn put_chain<Cache: LedgerCache>(cache: &Cache, slots: impl Iterator<Item = u64>) {
+        for slot in slots {
+            cache.put_block(point(slot), block(slot)).await;
+            cache.set_tip(point(slot)).await;
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct RawBlock(Vec<u8>);
+
+    impl FromEraTaggedBytes for RawBlock {
+        fn from_era_tagged_bytes(bytes: &[u8]) -> Result<Self, EraDecodingError> {
+            Ok(RawBlock(bytes.to_vec()))
+        }
+    }
+
+    pub async fn test_roll_forward<Cache: LedgerCache>(cache: Cache) {
+        assert_eq!(cache.get_tip().await, None);
+        put_chain(&cache, 1..=3).await;
+        assert_eq!(cache.get_tip().await, Some(point(3)));
+        assert_eq!(cache.get_block(point(2)).await, Some(block(2)));
+        assert_eq!(cache.get_block(point(4)).await, None);
+    }
+
+    pub async fn test_multi_block_rollback<Cache: LedgerCache>(cache: Cache) {
+        put_chain(&cache, 1..=5).await;
+        let cache = Arc::new(Mutex::new(cache));
+        let rolled_back = rollback::<_, RawBlock>(Arc::clone(&cache), point(2))
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            rolled_back,
+            vec![RawBlock(vec![5]), RawBlock(vec![4]), RawBlock(vec![3])]
+        );
+        let cache = cache.lock().await;
+        assert_eq!(cache.get_tip().await, Some(point(2)));
+        for slot in 3..=5 {
+            assert_eq!(cache.get_block(point(slot)).await, None);
+        }
+        assert_eq!(cache.get_block(point(2)).await, Some(block(2)));
+    }
+
+    pub async fn test_rollback_fails_at_pruned_blocks<Cache: LedgerCache>(cache: Cache) {
+        put_chain(&cache, 1..=5).await;
+        cache.put_block(point(3), LinkedBlock(vec![], point(2))).await;
+        let cache = Arc::new(Mutex::new(cache));
+        let mut rolled_back = rollback::<_, RawBlock>(Arc::clone(&cache), point(1))
+            .collect::<Vec<_>>()
+            .await;
+        assert!(matches!(
+            rolled_back.pop(),
+            Some(Err(RollbackError::BlockPruned { at, .. })) if at == point(3)
+        ));
+        let rolled_back = rolled_back.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(rolled_back, vec![RawBlock(vec![5]), RawBlock(vec![4])]);
+        let cache = cache.lock().await;
+        assert_eq!(cache.get_tip().await, Some(point(3)));
+        assert_eq!(
+            cache.get_block(point(3)).await,
+            Some(LinkedBlock(vec![], point(2)))
+        );
+    }
+
+    pub async fn test_replay_from_point<Cache: LedgerCache>(cache: Cache) {
+        for slot in [4, 1, 5, 3, 2] {
+            cache.put_block(point(slot), block(slot)).await;
+        }
+        cache.set_tip(point(5)).await;
+        let replayed = cache.replay(point(3)).collect::<Vec<_>>().await;
+        assert_eq!(replayed, vec![block(3), block(4), block(5)]);
+    }
+
+    fn rocksdb_cache() -> LedgerCacheRocksDB {
+        LedgerCacheRocksDB::new(format!("./tmp/{}", thread_rng().next_u32()))
+    }
+
+    #[tokio::test]
+    async fn rocksdb_cache_rolls_forward() {
+        test_roll_forward(rocksdb_cache()).await
+    }
+
+    #[tokio::test]
+    async fn rocksdb_cache_rolls_back_several_blocks() {
+        test_multi_block_rollback(rocksdb_cache()).await
+    }
+
+    #[tokio::test]
+    async fn rocksdb_cache_replays_from_point() {
+        test_replay_from_point(rocksdb_cache()).await
+    }
+
+    #[tokio::test]
+    async fn rocksdb_cache_rollback_fails_at_pruned_blocks() {
+        test_rollback_fails_at_pruned_blocks(rocksdb_cache()).await
+    }
+
+    async fn pruned_slots(cache: &LedgerCacheRocksDB, slots: impl Iterator<Item = u64>) -> Vec<u64> {
+        let mut pruned = vec![];
+        for slot in slots {
+            if cache.get_block(point(slot)).await.unwrap().is_pruned() {
+                pruned.push(slot);
+            }
+        }
+        pruned
+    }
+
+    async fn await_pruning(
+        cache: &LedgerCacheRocksDB,
+        slots: impl Iterator<Item = u64> + Clone,
+        expected: Vec<u64>,
+    ) {
+        for _ in 0..100 {
+            if pruned_slots(cache, slots.clone()).await == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(pruned_slots(cache, slots).await, expected);
+    }
+
+    #[tokio::test]
+    async fn blocks_beyond_rollback_depth_are_pruned() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let cache = LedgerCacheRocksDB::with_rollback_depth(conf, 3);
+        put_chain(&cache, 1..=10).await;
+        await_pruning(&cache, 1..=10, (1..=7).collect()).await;
+        // The chain of points is kept.
+        assert!(matches!(
+            cache.get_block(point(1)).await,
+            Some(LinkedBlock(_, Point::Origin))
+        ));
+        let replayed = cache
+            .replay(point(1))
+            .map(|LinkedBlock(blk_bytes, _)| blk_bytes[0] as u64)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(replayed, vec![8, 9, 10]);
+        // Rolled back blocks leave the window.
+        cache.delete(point(10)).await;
+        cache.set_tip(point(9)).await;
+        put_chain(&cache, 10..=11).await;
+        await_pruning(&cache, 1..=11, (1..=8).collect()).await;
+    }
+
+    #[tokio::test]
+    async fn blocks_left_unpruned_are_pruned_on_start() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let cache = LedgerCacheRocksDB::with_rollback_depth(conf.clone(), 100);
+        put_chain(&cache, 1..=10).await;
+        let cache = LedgerCacheRocksDB::with_rollback_depth(conf, 4);
+        await_pruning(&cache, 1..=10, (1..=6).collect()).await;
+    }
+
+    #[tokio::test]
+    async fn blocks_are_wrapped_into_era_envelope_on_upgrade() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let legacy_block = |slot: u64| LinkedBlock(vec![0x85, slot as u8], point(slot - 1));
+        let wrapped_block = |slot: u64| {
+            LinkedBlock(
+                vec![BLOCK_ENVELOPE_HEADER, Era::Babbage.tag(), 0x85, slot as u8],
+                point(slot - 1),
+            )
+        };
+        let num_blocks = 2 * MIGRATION_CHUNK_SIZE as u64 + 2;
+        {
+            let db = rocks::open(&conf);
+            let cf = db.cf_handle(conf.column_family()).unwrap();
+            for slot in 2..=num_blocks {
+                // Some blocks are wrapped already as if the migration was interrupted.
+                let block = if slot % 2 == 0 {
+                    wrapped_block(slot)
+                } else {
+                    legacy_block(slot)
+                };
+                db.put_cf(
+                    &cf,
+                    point_key(POINT_PREFIX, &point(slot)),
+                    bincode::serialize(&block).unwrap(),
+                )
+                .unwrap();
+            }
+        }
+        let cache = LedgerCacheRocksDB::from_config(conf);
+        for slot in 2..=num_blocks {
+            assert_eq!(cache.get_block(point(slot)).await, Some(wrapped_block(slot)));
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_std::stream::Stream;
+use futures::stream;
+
+use crate::cache::{point_key, Inclusive, LedgerCache, LinkedBlock, POINT_PREFIX};
+use crate::client::Point;
+
+/// Ledger cache kept in memory, blocks are never pruned.
+#[derive(Default)]
+pub struct InMemoryLedgerCache {
+    tip: Mutex<Option<Point>>,
+    /// Blocks ordered by point key, same as in [LedgerCacheRocksDB](crate::cache::LedgerCacheRocksDB).
+    blocks: Mutex<BTreeMap<Vec<u8>, LinkedBlock>>,
+}
+
+impl InMemoryLedgerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LedgerCache for InMemoryLedgerCache {
+    async fn set_tip(&self, point: Point) {
+        *self.tip.lock().unwrap() = Some(point);
+    }
+
+    async fn get_tip(&self) -> Option<Point> {
+        *self.tip.lock().unwrap()
+    }
+
+    async fn put_block(&self, point: Point, block: LinkedBlock) {
+        self.blocks
+            .lock()
+            .unwrap()
+            .insert(point_key(POINT_PREFIX, &point), block);
+    }
+
+    async fn get_block(&self, point: Point) -> Option<LinkedBlock> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .get(&point_key(POINT_PREFIX, &point))
+            .cloned()
+    }
+
+    async fn delete(&self, point: Point) -> bool {
+        self.blocks
+            .lock()
+            .unwrap()
+            .remove(&point_key(POINT_PREFIX, &point));
+        true
+    }
+
+    fn replay<'a>(&self, from_point: Inclusive<Point>) -> impl Stream<Item = LinkedBlock> + Send + 'a {
+        let blocks: Vec<_> = self
+            .blocks
+            .lock()
+            .unwrap()
+            .range(point_key(POINT_PREFIX, &from_point)..)
+            .map(|(_, blk)| blk.clone())
+            .collect();
+        stream::iter(blocks)
+    }
+
+    fn rollback_depth(&self) -> usize {
+        usize::MAX
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::inmemory::InMemoryLedgerCache;
+    use crate::cache::tests::*;
+
+    #[tokio::test]
+    async fn in_memory_cache_rolls_forward() {
+        test_roll_forward(InMemoryLedgerCache::new()).await
+    }
+
+    #[tokio::test]
+    async fn in_memory_cache_rolls_back_several_blocks() {
+        test_multi_block_rollback(InMemoryLedgerCache::new()).await
+    }
+
+    #[tokio::test]
+    async fn in_memory_cache_rollback_fails_at_pruned_blocks() {
+        test_rollback_fails_at_pruned_blocks(InMemoryLedgerCache::new()).await
+    }
+
+    #[tokio::test]
+    async fn in_memory_cache_replays_from_point() {
+        test_replay_from_point(InMemoryLedgerCache::new()).await
+    }
+}
@@ -2,23 +2,35 @@ use std::marker::PhantomData;
 use std::path::Path;
 use std::sync::Arc;
 
-use cml_core::serialization::Deserialize;
 use cml_core::Slot;
 use cml_crypto::BlockHeaderHash;
 use log::debug;
 use pallas_network::miniprotocols::chainsync::{BlockContent, NextResponse, State};
 use pallas_network::miniprotocols::handshake::RefuseReason;
-use pallas_network::miniprotocols::{chainsync, handshake, PROTOCOL_N2C_CHAIN_SYNC, PROTOCOL_N2C_HANDSHAKE};
+use pallas_network::miniprotocols::{
+    blockfetch, chainsync, handshake, PROTOCOL_N2C_CHAIN_SYNC, PROTOCOL_N2C_HANDSHAKE,
+};
 use pallas_network::multiplexer;
 use pallas_network::multiplexer::{Bearer, RunningPlexer};
 use tokio::sync::Mutex;
 
-use crate::cache::LedgerCache;
+use crate::cache::{LedgerCache, LinkedBlock};
 use crate::data::ChainUpgrade;
+use crate::multi_era::{EraDecodingError, FromEraTaggedBytes};
+
+/// Source of chain upgrades followed by [chain_sync_stream](crate::chain_sync_stream).
+pub trait ChainSync<Block> {
+    /// Pull next upgrade of the chain. `None` is returned once the tip is reached.
+    async fn try_pull_next(&mut self) -> Result<Option<ChainUpgrade<Block>>, Error>;
+    /// Close the connection to the node.
+    async fn close(self);
+}
 
 pub struct ChainSyncClient<Block> {
     plexer: RunningPlexer,
     chain_sync: chainsync::N2CClient,
+    /// Rollback to the intersection to be yielded before pulling from the node.
+    pending_rollback: Option<Point>,
     block: PhantomData<Block>,
 }
 
@@ -30,6 +42,21 @@ impl<Block> ChainSyncClient<Block> {
         magic: u64,
         starting_point: Point,
     ) -> Result<Self, Error>
+    where
+        Cache: LedgerCache,
+    {
+        Self::resume(cache, path, magic, starting_point, None).await
+    }
+
+    /// Connect to the node and continue sync right after `resume_from` point if it is given.
+    #[cfg(not(target_os = "windows"))]
+    pub async fn resume<Cache>(
+        cache: Arc<Mutex<Cache>>,
+        path: impl AsRef<Path>,
+        magic: u64,
+        starting_point: Point,
+        resume_from: Option<Point>,
+    ) -> Result<Self, Error>
     where
         Cache: LedgerCache,
     {
@@ -56,60 +83,128 @@ impl<Block> ChainSyncClient<Block> {
 
         let mut cs_client = chainsync::Client::new(cs_channel);
 
-        let best_point = cache.lock().await.get_tip().await.unwrap_or(starting_point);
+        let (tip, candidates) = local_chain_points(&*cache.lock().await, starting_point, resume_from).await;
 
-        debug!("Using {:?} as a starting point", best_point);
+        debug!("Looking for intersection at {:?}", candidates);
 
-        if let (None, _) = cs_client
-            .find_intersect(vec![best_point.into()])
+        let intersection: Point = match cs_client
+            .find_intersect(candidates.into_iter().map(Into::into).collect())
             .await
             .map_err(Error::ChainSyncProtocol)?
         {
-            return Err(Error::IntersectionNotFound);
-        }
+            (Some(point), _) => point.into(),
+            (None, _) => return Err(Error::IntersectionNotFound),
+        };
+
+        debug!("Found intersection at {:?}", intersection);
 
         Ok(Self {
             plexer,
             chain_sync: cs_client,
+            pending_rollback: rollback_to_intersection(tip, intersection),
             block: PhantomData::default(),
         })
     }
+}
 
-    pub async fn try_pull_next(&mut self) -> Option<ChainUpgrade<Block>>
-    where
-        Block: Deserialize,
-    {
+impl<Block> ChainSync<Block> for ChainSyncClient<Block>
+where
+    Block: FromEraTaggedBytes,
+{
+    async fn try_pull_next(&mut self) -> Result<Option<ChainUpgrade<Block>>, Error> {
+        if let Some(point) = self.pending_rollback.take() {
+            return Ok(Some(ChainUpgrade::RollBackward(point)));
+        }
         let response = match self.chain_sync.state() {
             State::MustReply => self.chain_sync.recv_while_can_await().await,
             _ => self.chain_sync.request_next().await,
         };
         match response {
-            Ok(NextResponse::RollForward(BlockContent(raw), _)) => {
-                let original_bytes = raw[BLK_START..].to_vec();
-                match Block::from_cbor_bytes(&original_bytes) {
-                    Ok(blk) => Some(ChainUpgrade::RollForward {
-                        blk,
-                        blk_bytes: original_bytes,
-                        replayed: false,
-                    }),
-                    Err(err) => panic!(
+            Ok(NextResponse::RollForward(BlockContent(raw), _)) => match Block::from_era_tagged_bytes(&raw) {
+                Ok(blk) => Ok(Some(ChainUpgrade::RollForward {
+                    blk,
+                    blk_bytes: raw,
+                    replayed: false,
+                })),
+                Err(err) => {
+                    debug!(
                         "Block deserialization failed: {}, bytes: {}",
                         err,
-                        hex::encode(original_bytes)
-                    ),
+                        hex::encode(raw)
+                    );
+                    Err(Error::BlockDecoding(err))
                 }
-            }
-            Ok(NextResponse::RollBackward(pt, _)) => Some(ChainUpgrade::RollBackward(pt.into())),
-            _ => None,
+            },
+            Ok(NextResponse::RollBackward(pt, _)) => Ok(Some(ChainUpgrade::RollBackward(pt.into()))),
+            Ok(NextResponse::Await) => Ok(None),
+            Err(err) => Err(Error::ChainSyncProtocol(err)),
         }
     }
 
-    pub async fn close(self) {
+    async fn close(self) {
         self.plexer.abort().await
     }
 }
 
-const BLK_START: usize = 2;
+/// Depth of the cached chain beyond which no intersection is looked for.
+const MAX_INTERSECTION_DEPTH: usize = 2160;
+
+/// Points to find intersection with the node at: the cached tip, its ancestors
+/// at exponentially growing depths and finally the `starting_point`.
+/// Only points the cache is able to roll back to are considered, i.e. ones within
+/// its rollback depth and above the first pruned block.
+pub async fn intersection_candidates<Cache: LedgerCache>(cache: &Cache, starting_point: Point) -> Vec<Point> {
+    let max_depth = cache.rollback_depth().min(MAX_INTERSECTION_DEPTH);
+    let mut candidates = vec![];
+    let mut next_point = cache.get_tip().await;
+    let mut depth = 0;
+    let mut next_candidate_depth = 0;
+    while let Some(point) = next_point {
+        if depth == next_candidate_depth {
+            candidates.push(point);
+            next_candidate_depth = (depth * 2).max(1);
+        }
+        if depth == max_depth {
+            break;
+        }
+        next_point = cache
+            .get_block(point)
+            .await
+            .filter(|blk| !blk.is_pruned())
+            .map(|LinkedBlock(_, prev_point)| prev_point);
+        depth += 1;
+    }
+    if !candidates.contains(&starting_point) {
+        candidates.push(starting_point);
+    }
+    candidates
+}
+
+/// Tip of the locally known chain along with points to find intersection with the node at.
+/// The point sync is resumed from is preferred over the cached chain if it is given.
+pub async fn local_chain_points<Cache: LedgerCache>(
+    cache: &Cache,
+    starting_point: Point,
+    resume_from: Option<Point>,
+) -> (Option<Point>, Vec<Point>) {
+    let mut candidates = intersection_candidates(cache, starting_point).await;
+    match resume_from {
+        Some(point) => {
+            candidates.retain(|pt| *pt != point);
+            candidates.insert(0, point);
+            (Some(point), candidates)
+        }
+        None => (cache.get_tip().await, candidates),
+    }
+}
+
+/// Cached blocks above the `intersection` are no longer on the chain followed by the node,
+/// so they have to be rolled back before new blocks are applied.
+pub fn rollback_to_intersection(cached_tip: Option<Point>, intersection: Point) -> Option<Point> {
+    cached_tip
+        .filter(|tip| *tip != intersection)
+        .map(|_| intersection)
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -122,11 +217,17 @@ pub enum Error {
     #[error("chain-sync protocol error")]
     ChainSyncProtocol(chainsync::ClientError),
 
+    #[error("block-fetch protocol error")]
+    BlockFetchProtocol(blockfetch::ClientError),
+
     #[error("handshake version not accepted")]
     HandshakeRefused(RefuseReason),
 
     #[error("intersection not found")]
     IntersectionNotFound,
+
+    #[error("block decoding error: {0}")]
+    BlockDecoding(EraDecodingError),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -165,3 +266,56 @@ impl Into<Point> for pallas_network::miniprotocols::Point {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, RngCore};
+    use spectrum_offchain::rocks::RocksConfig;
+
+    use crate::cache::inmemory::InMemoryLedgerCache;
+    use crate::cache::tests::{point, put_chain};
+    use crate::cache::{LedgerCache, LedgerCacheRocksDB, LinkedBlock};
+    use crate::client::{intersection_candidates, rollback_to_intersection, Point};
+
+    #[tokio::test]
+    async fn intersection_is_looked_for_at_exponential_depths() {
+        let cache = LedgerCacheRocksDB::new(format!("./tmp/{}", thread_rng().next_u32()));
+        put_chain(&cache, 1..=10).await;
+        let candidates = intersection_candidates(&cache, Point::Origin).await;
+        assert_eq!(
+            candidates,
+            vec![point(10), point(9), point(8), point(6), point(2), Point::Origin]
+        );
+    }
+
+    #[tokio::test]
+    async fn intersection_is_looked_for_within_rollback_depth() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let cache = LedgerCacheRocksDB::with_rollback_depth(conf, 5);
+        put_chain(&cache, 1..=10).await;
+        let candidates = intersection_candidates(&cache, Point::Origin).await;
+        assert_eq!(
+            candidates,
+            vec![point(10), point(9), point(8), point(6), Point::Origin]
+        );
+    }
+
+    #[tokio::test]
+    async fn intersection_is_not_looked_for_below_pruned_blocks() {
+        let cache = InMemoryLedgerCache::new();
+        put_chain(&cache, 1..=10).await;
+        cache.put_block(point(6), LinkedBlock(vec![], point(5))).await;
+        let candidates = intersection_candidates(&cache, Point::Origin).await;
+        assert_eq!(
+            candidates,
+            vec![point(10), point(9), point(8), point(6), Point::Origin]
+        );
+    }
+
+    #[test]
+    fn cached_blocks_above_intersection_are_rolled_back() {
+        assert_eq!(rollback_to_intersection(None, point(1)), None);
+        assert_eq!(rollback_to_intersection(Some(point(1)), point(1)), None);
+        assert_eq!(rollback_to_intersection(Some(point(8)), point(4)), Some(point(4)));
+    }
+}
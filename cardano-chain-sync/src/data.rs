@@ -1,3 +1,5 @@
+use cml_crypto::{BlockHeaderHash, TransactionHash};
+
 use crate::client::Point;
 
 #[derive(Clone)]
@@ -6,15 +8,32 @@ pub enum LedgerBlockEvent<Block> {
     RollBackward(Block),
 }
 
+/// Transaction applied to or unapplied from the ledger, along with the block it belongs to.
 #[derive(Clone, Debug)]
 pub enum LedgerTxEvent<Tx> {
-    TxApplied { tx: Tx, slot: u64 },
-    TxUnapplied(Tx),
+    TxApplied {
+        tx: Tx,
+        /// Canonical hash of the transaction body.
+        tx_hash: TransactionHash,
+        /// Index of the transaction in the block.
+        tx_ix: usize,
+        block_hash: BlockHeaderHash,
+        block_height: u64,
+        slot: u64,
+    },
+    TxUnapplied {
+        tx: Tx,
+        tx_hash: TransactionHash,
+        tx_ix: usize,
+        block_hash: BlockHeaderHash,
+        block_height: u64,
+        slot: u64,
+    },
 }
 
 #[derive(Clone)]
 pub enum ChainUpgrade<Block> {
-    /// Deserialized block and it's serialized representation.
+    /// Deserialized block and its serialized representation wrapped into era envelope.
     RollForward {
         blk: Block,
         blk_bytes: Vec<u8>,
@@ -1,23 +1,36 @@
-use std::collections::HashSet;
 use std::pin::Pin;
 use std::sync::Arc;
 
 use async_stream::stream;
-use cml_core::serialization::Deserialize;
 use cml_core::Slot;
-use cml_multi_era::babbage::{BabbageBlock, BabbageTransaction};
 use futures::stream::StreamExt;
 use futures::{stream, Stream};
-use log::{info, trace, warn};
+use log::{error, info, trace, warn};
 use tokio::sync::Mutex;
 
-use spectrum_cardano_lib::hash::hash_block_header_canonical;
-
 use crate::cache::{LedgerCache, LinkedBlock};
 use crate::client::Point;
 use crate::data::{ChainUpgrade, LedgerBlockEvent, LedgerTxEvent};
+use crate::multi_era::{EraDecodingError, FromEraTaggedBytes, IndexedTx, LedgerTx, MultiEraBlock};
+
+/// Rollback requested by the node which cannot be carried out with the cached blocks.
+#[derive(Debug, thiserror::Error)]
+pub enum RollbackError {
+    #[error("cannot roll back to {to:?}: block {at:?} is not cached")]
+    BlockMissing { to: Point, at: Point },
+    #[error("cannot roll back to {to:?}: block {at:?} is pruned")]
+    BlockPruned { to: Point, at: Point },
+    #[error("cannot roll back to {to:?}: block {at:?} is malformed: {err}")]
+    BlockMalformed {
+        to: Point,
+        at: Point,
+        err: EraDecodingError,
+    },
+}
 
 /// Stream ledger updates as individual transactions.
+/// The stream ends with an error once a rollback cannot be carried out,
+/// as the cache tip no longer matches the ledger state downstream.
 pub async fn ledger_transactions<'a, S, Cache>(
     cache: Arc<Mutex<Cache>>,
     upstream: S,
@@ -25,9 +38,9 @@ pub async fn ledger_transactions<'a, S, Cache>(
     handle_rollbacks_after: Slot,
     // Reapply known blocks before pulling new ones.
     replay_from: Option<Point>,
-) -> impl Stream<Item = LedgerTxEvent<BabbageTransaction>> + 'a
+) -> impl Stream<Item = Result<LedgerTxEvent<LedgerTx>, RollbackError>> + 'a
 where
-    S: Stream<Item = ChainUpgrade<BabbageBlock>> + 'a,
+    S: Stream<Item = ChainUpgrade<MultiEraBlock>> + 'a,
     Cache: LedgerCache + 'a,
 {
     let raw_replayed_blocks = match replay_from {
@@ -39,7 +52,7 @@ where
     };
     let replayed_blocks = raw_replayed_blocks
         .map(|LinkedBlock(raw_blk, _)| {
-            BabbageBlock::from_cbor_bytes(&raw_blk)
+            MultiEraBlock::from_era_tagged_bytes(&raw_blk)
                 .ok()
                 .map(|blk| ChainUpgrade::RollForward {
                     blk,
@@ -48,31 +61,47 @@ where
                 })
         })
         .filter_map(|result| async { result });
-    replayed_blocks
+    let events = replayed_blocks
         .chain(upstream)
         .then(move |u| process_upstream_by_txs(Arc::clone(&cache), u, handle_rollbacks_after))
-        .flatten()
+        .flatten();
+    until_failed(events)
 }
 
 /// Stream ledger updates as blocks.
+/// The stream ends with an error once a rollback cannot be carried out.
 pub fn ledger_blocks<'a, S, Cache>(
     cache: Arc<Mutex<Cache>>,
     upstream: S,
     // Rollbacks will not be handled until the specified slot is reached.
     handle_rollbacks_after: Slot,
-) -> impl Stream<Item = LedgerBlockEvent<BabbageBlock>> + 'a
+) -> impl Stream<Item = Result<LedgerBlockEvent<MultiEraBlock>, RollbackError>> + 'a
 where
-    S: Stream<Item = ChainUpgrade<BabbageBlock>> + 'a,
+    S: Stream<Item = ChainUpgrade<MultiEraBlock>> + 'a,
     Cache: LedgerCache + 'a,
 {
-    upstream.flat_map(move |u| process_upstream_by_blocks(Arc::clone(&cache), u, handle_rollbacks_after))
+    until_failed(
+        upstream.flat_map(move |u| process_upstream_by_blocks(Arc::clone(&cache), u, handle_rollbacks_after)),
+    )
+}
+
+/// End the stream after the first error.
+fn until_failed<S, T, E>(upstream: S) -> impl Stream<Item = Result<T, E>>
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    upstream.scan(false, |failed, item| {
+        let next = if *failed { None } else { Some(item) };
+        *failed = next.as_ref().map_or(true, Result::is_err);
+        async move { next }
+    })
 }
 
 async fn process_upstream_by_txs<'a, Cache>(
     cache: Arc<Mutex<Cache>>,
-    upgr: ChainUpgrade<BabbageBlock>,
+    upgr: ChainUpgrade<MultiEraBlock>,
     handle_rollbacks_after: Slot,
-) -> Pin<Box<dyn Stream<Item = LedgerTxEvent<BabbageTransaction>> + 'a>>
+) -> Pin<Box<dyn Stream<Item = Result<LedgerTxEvent<LedgerTx>, RollbackError>> + 'a>>
 where
     Cache: LedgerCache + 'a,
 {
@@ -83,30 +112,61 @@ where
             replayed,
         } => {
             if !replayed {
-                if blk.header.header_body.slot > handle_rollbacks_after {
+                if blk.slot() > handle_rollbacks_after {
                     cache_block(cache, &blk, blk_bytes).await;
                 } else {
                     cache_point(cache, &blk).await;
                 }
             }
-            info!(
-                "Scanning Block {}",
-                hash_block_header_canonical(&blk.header).to_hex()
-            );
-            let applied_txs: Vec<_> = unpack_valid_transactions(blk)
-                .map(|(tx, slot)| LedgerTxEvent::TxApplied { tx, slot })
+            let block_hash = blk.header_hash();
+            let block_height = blk.block_number();
+            let slot = blk.slot();
+            info!("Scanning {:?} Block {}", blk.era(), block_hash.to_hex());
+            let applied_txs: Vec<_> = blk
+                .into_valid_transactions()
+                .into_iter()
+                .map(|IndexedTx { ix, hash, tx }| {
+                    Ok(LedgerTxEvent::TxApplied {
+                        tx,
+                        tx_hash: hash,
+                        tx_ix: ix,
+                        block_hash,
+                        block_height,
+                        slot,
+                    })
+                })
                 .collect();
             Box::pin(stream::iter(applied_txs))
         }
         ChainUpgrade::RollBackward(point) if point.get_slot() > handle_rollbacks_after => {
             warn!("Node requested rollback to point {:?}", point);
-            Box::pin(rollback(cache, point.into()).flat_map(|blk| {
-                let unapplied_txs: Vec<_> = unpack_valid_transactions(blk)
-                    .map(|(tx, _)| LedgerTxEvent::TxUnapplied(tx))
-                    .rev()
-                    .collect();
-                stream::iter(unapplied_txs)
-            }))
+            Box::pin(
+                rollback(cache, point.into()).flat_map(|res: Result<MultiEraBlock, _>| {
+                    let unapplied_txs: Vec<_> = match res {
+                        Ok(blk) => {
+                            let block_hash = blk.header_hash();
+                            let block_height = blk.block_number();
+                            let slot = blk.slot();
+                            blk.into_valid_transactions()
+                                .into_iter()
+                                .map(|IndexedTx { ix, hash, tx }| {
+                                    Ok(LedgerTxEvent::TxUnapplied {
+                                        tx,
+                                        tx_hash: hash,
+                                        tx_ix: ix,
+                                        block_hash,
+                                        block_height,
+                                        slot,
+                                    })
+                                })
+                                .rev()
+                                .collect()
+                        }
+                        Err(err) => vec![Err(err)],
+                    };
+                    stream::iter(unapplied_txs)
+                }),
+            )
         }
         ChainUpgrade::RollBackward(_) => {
             warn!("Node requested rollback while rollbacks are disabled.");
@@ -115,61 +175,25 @@ where
     }
 }
 
-async fn cache_block<Cache: LedgerCache>(cache: Arc<Mutex<Cache>>, blk: &BabbageBlock, blk_bytes: Vec<u8>) {
+async fn cache_block<Cache: LedgerCache>(cache: Arc<Mutex<Cache>>, blk: &MultiEraBlock, blk_bytes: Vec<u8>) {
     let cache = cache.lock().await;
-    let point = Point::Specific(
-        blk.header.header_body.slot,
-        hash_block_header_canonical(&blk.header),
-    );
+    let point = Point::Specific(blk.slot(), blk.header_hash());
     let prev_point = cache.get_tip().await.unwrap_or(Point::Origin);
     cache.set_tip(point).await;
     cache.put_block(point, LinkedBlock(blk_bytes, prev_point)).await;
 }
 
-async fn cache_point<Cache: LedgerCache>(cache: Arc<Mutex<Cache>>, blk: &BabbageBlock) {
+async fn cache_point<Cache: LedgerCache>(cache: Arc<Mutex<Cache>>, blk: &MultiEraBlock) {
     let cache = cache.lock().await;
-    let point = Point::Specific(
-        blk.header.header_body.slot,
-        hash_block_header_canonical(&blk.header),
-    );
+    let point = Point::Specific(blk.slot(), blk.header_hash());
     cache.set_tip(point).await;
 }
 
-fn unpack_valid_transactions(
-    block: BabbageBlock,
-) -> impl DoubleEndedIterator<Item = (BabbageTransaction, u64)> {
-    let BabbageBlock {
-        header,
-        transaction_bodies,
-        transaction_witness_sets,
-        mut auxiliary_data_set,
-        invalid_transactions,
-        ..
-    } = block;
-    let invalid_indices: HashSet<u16> = HashSet::from_iter(invalid_transactions);
-    transaction_bodies
-        .into_iter()
-        .zip(transaction_witness_sets)
-        .enumerate()
-        .filter(move |(ix, _)| !invalid_indices.contains(&(*ix as u16)))
-        .map(move |(ix, (tb, tw))| {
-            let tx_ix = &(ix as u16);
-            let tx = BabbageTransaction {
-                body: tb,
-                witness_set: tw,
-                is_valid: true,
-                auxiliary_data: auxiliary_data_set.remove(tx_ix),
-                encodings: None,
-            };
-            (tx, header.header_body.slot)
-        })
-}
-
 fn process_upstream_by_blocks<'a, Cache>(
     cache: Arc<Mutex<Cache>>,
-    upgr: ChainUpgrade<BabbageBlock>,
+    upgr: ChainUpgrade<MultiEraBlock>,
     handle_rollbacks_after: Slot,
-) -> Pin<Box<dyn Stream<Item = LedgerBlockEvent<BabbageBlock>> + 'a>>
+) -> Pin<Box<dyn Stream<Item = Result<LedgerBlockEvent<MultiEraBlock>, RollbackError>> + 'a>>
 where
     Cache: LedgerCache + 'a,
 {
@@ -180,16 +204,16 @@ where
             replayed,
         } => Box::pin(stream::once(async move {
             if !replayed {
-                if blk.header.header_body.slot > handle_rollbacks_after {
+                if blk.slot() > handle_rollbacks_after {
                     cache_block(cache, &blk, blk_bytes).await;
                 } else {
                     cache_point(cache, &blk).await;
                 }
             }
-            LedgerBlockEvent::RollForward(blk)
+            Ok(LedgerBlockEvent::RollForward(blk))
         })),
         ChainUpgrade::RollBackward(point) if point.get_slot() > handle_rollbacks_after => {
-            Box::pin(rollback(cache, point.into()).map(LedgerBlockEvent::RollBackward))
+            Box::pin(rollback(cache, point.into()).map(|res| res.map(LedgerBlockEvent::RollBackward)))
         }
         ChainUpgrade::RollBackward(_) => {
             warn!("Node requested rollback while rollbacks are disabled.");
@@ -199,9 +223,15 @@ where
 }
 
 /// Handle rollback to a specific point in the past.
-fn rollback<Cache>(cache: Arc<Mutex<Cache>>, to_point: Point) -> impl Stream<Item = BabbageBlock>
+/// Rollback fails at the first block which is missing, pruned or cannot be decoded,
+/// as it cannot be unapplied. The stream ends with the error then.
+pub(crate) fn rollback<Cache, Block>(
+    cache: Arc<Mutex<Cache>>,
+    to_point: Point,
+) -> impl Stream<Item = Result<Block, RollbackError>>
 where
     Cache: LedgerCache,
+    Block: FromEraTaggedBytes,
 {
     stream! {
         loop {
@@ -212,13 +242,21 @@ where
                     trace!("Rolled back to point {:?}", to_point);
                     break;
                 }
-                if let Some(LinkedBlock(block_bytes, prev_point)) = cache.get_block(tip.clone()).await {
-                    cache.delete(tip).await;
-                    cache.set_tip(prev_point).await;
-                    let block = BabbageBlock::from_cbor_bytes(&block_bytes).expect("Block deserialization failed");
-                    yield block;
-                    continue;
-                }
+                let err = match cache.get_block(tip).await {
+                    Some(blk) if blk.is_pruned() => RollbackError::BlockPruned { to: to_point, at: tip },
+                    Some(LinkedBlock(block_bytes, prev_point)) => match Block::from_era_tagged_bytes(&block_bytes) {
+                        Ok(block) => {
+                            cache.delete(tip).await;
+                            cache.set_tip(prev_point).await;
+                            yield Ok(block);
+                            continue;
+                        }
+                        Err(err) => RollbackError::BlockMalformed { to: to_point, at: tip, err },
+                    },
+                    None => RollbackError::BlockMissing { to: to_point, at: tip },
+                };
+                error!("{}", err);
+                yield Err(err);
             }
             break;
         }
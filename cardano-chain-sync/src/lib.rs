@@ -1,27 +1,35 @@
 use std::time::Duration;
 
 use async_stream::stream;
-use cml_core::serialization::Deserialize;
 use futures::lock::Mutex;
 use futures::Stream;
 use futures_timer::Delay;
-use log::trace;
+use log::{error, trace};
 use tokio::sync::broadcast;
 
-use crate::client::ChainSyncClient;
+use crate::client::ChainSync;
 use crate::data::ChainUpgrade;
 
 pub mod cache;
 pub mod client;
 pub mod data;
 pub mod event_source;
+pub mod multi_era;
+pub mod n2n;
+pub mod offline;
+pub mod supervisor;
+pub mod utxo_index;
 
-pub fn chain_sync_stream<'a, Block>(
-    mut chain_sync: ChainSyncClient<Block>,
+/// Stream upgrades of the chain pulled by the given client.
+/// The stream ends once the connection is lost, unless the client is
+/// [supervised](crate::supervisor::SupervisedChainSync).
+pub fn chain_sync_stream<'a, Client, Block>(
+    mut chain_sync: Client,
     tip_reached_signal: broadcast::Sender<bool>,
 ) -> impl Stream<Item = ChainUpgrade<Block>> + 'a
 where
-    Block: Deserialize + 'a,
+    Client: ChainSync<Block> + 'a,
+    Block: 'a,
 {
     let delay_mux: Mutex<Option<Delay>> = Mutex::new(None);
     stream! {
@@ -30,12 +38,17 @@ where
             if let Some(delay) = delay {
                 delay.await;
             }
-            if let Some(upgr) = chain_sync.try_pull_next().await {
-                yield upgr;
-            } else {
-                trace!(target: "chain_sync", "Tip reached, waiting for new blocks ..");
-                *delay_mux.lock().await = Some(Delay::new(Duration::from_secs(THROTTLE_SECS)));
-                let _ = tip_reached_signal.send(true);
+            match chain_sync.try_pull_next().await {
+                Ok(Some(upgr)) => yield upgr,
+                Ok(None) => {
+                    trace!(target: "chain_sync", "Tip reached, waiting for new blocks ..");
+                    *delay_mux.lock().await = Some(Delay::new(Duration::from_secs(THROTTLE_SECS)));
+                    let _ = tip_reached_signal.send(true);
+                }
+                Err(err) => {
+                    error!(target: "chain_sync", "Chain-sync failed: {}", err);
+                    break;
+                }
             }
         }
     }
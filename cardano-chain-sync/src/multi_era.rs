@@ -0,0 +1,697 @@
+use std::collections::HashSet;
+
+use cml_chain::auxdata::{AuxiliaryData, Metadata};
+use cml_chain::block::{Block as ConwayBlock, Header as ConwayHeader};
+use cml_chain::transaction::{
+    TransactionBody as ConwayTransactionBody, TransactionInput, TransactionOutput, TransactionWitnessSet,
+};
+use cml_chain::Value;
+use cml_core::ordered_hash_map::OrderedHashMap;
+use cml_core::serialization::{Deserialize, Serialize};
+use cml_crypto::{BlockHeaderHash, TransactionHash};
+use cml_multi_era::allegra::{
+    AllegraAuxiliaryData, AllegraBlock, AllegraTransactionBody, AllegraTransactionWitnessSet,
+};
+use cml_multi_era::alonzo::{
+    AlonzoAuxiliaryData, AlonzoBlock, AlonzoTransactionBody, AlonzoTransactionWitnessSet,
+};
+use cml_multi_era::babbage::{
+    BabbageAuxiliaryData, BabbageBlock, BabbageHeader, BabbageTransactionBody, BabbageTransactionWitnessSet,
+};
+use cml_multi_era::mary::{MaryBlock, MaryTransactionBody};
+use cml_multi_era::shelley::{
+    ShelleyBlock, ShelleyHeader, ShelleyTransactionBody, ShelleyTransactionWitnessSet,
+};
+
+use spectrum_cardano_lib::hash::{hash_block_header_canonical, hash_transaction_bytes};
+use spectrum_cardano_lib::transaction::BabbageTransactionOutputExtension;
+
+use crate::client::Point;
+
+/// CBOR header of the two-element array `[era_tag, block]` blocks are wrapped into by the node.
+pub const BLOCK_ENVELOPE_HEADER: u8 = 0x82;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Era {
+    Byron,
+    Shelley,
+    Allegra,
+    Mary,
+    Alonzo,
+    Babbage,
+    Conway,
+}
+
+impl Era {
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 | 1 => Some(Era::Byron),
+            2 => Some(Era::Shelley),
+            3 => Some(Era::Allegra),
+            4 => Some(Era::Mary),
+            5 => Some(Era::Alonzo),
+            6 => Some(Era::Babbage),
+            7 => Some(Era::Conway),
+            _ => None,
+        }
+    }
+
+    /// Era of a header sent in node-to-node chain-sync, which is identified by its
+    /// index in the hard fork combinator rather than by the era tag of blocks.
+    pub fn from_header_variant(variant: u8) -> Option<Self> {
+        match variant {
+            0 => Some(Era::Byron),
+            1 => Some(Era::Shelley),
+            2 => Some(Era::Allegra),
+            3 => Some(Era::Mary),
+            4 => Some(Era::Alonzo),
+            5 => Some(Era::Babbage),
+            6 => Some(Era::Conway),
+            _ => None,
+        }
+    }
+
+    pub fn tag(&self) -> u8 {
+        match self {
+            Era::Byron => 1,
+            Era::Shelley => 2,
+            Era::Allegra => 3,
+            Era::Mary => 4,
+            Era::Alonzo => 5,
+            Era::Babbage => 6,
+            Era::Conway => 7,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EraDecodingError {
+    #[error("block is not wrapped into era envelope")]
+    MalformedEnvelope,
+    #[error("unknown era tag {0}")]
+    UnknownEra(u8),
+    #[error("blocks of {0:?} era are not supported")]
+    UnsupportedEra(Era),
+    #[error("{era:?} deserialization failed: {reason}")]
+    Cbor { era: Era, reason: String },
+}
+
+/// Block which can be decoded from the era-tagged form it is sent in by the node.
+pub trait FromEraTaggedBytes: Sized {
+    fn from_era_tagged_bytes(bytes: &[u8]) -> Result<Self, EraDecodingError>;
+}
+
+/// Block which knows its point on the chain.
+pub trait BlockPoint {
+    fn point(&self) -> Point;
+}
+
+/// Block of any era since Shelley.
+#[derive(Clone, Debug)]
+pub enum MultiEraBlock {
+    Shelley(ShelleyBlock),
+    Allegra(AllegraBlock),
+    Mary(MaryBlock),
+    Alonzo(AlonzoBlock),
+    Babbage(BabbageBlock),
+    Conway(ConwayBlock),
+}
+
+impl FromEraTaggedBytes for MultiEraBlock {
+    fn from_era_tagged_bytes(bytes: &[u8]) -> Result<Self, EraDecodingError> {
+        let (era, blk_bytes) = match bytes {
+            [BLOCK_ENVELOPE_HEADER, tag, blk_bytes @ ..] => (
+                Era::from_tag(*tag).ok_or(EraDecodingError::UnknownEra(*tag))?,
+                blk_bytes,
+            ),
+            _ => return Err(EraDecodingError::MalformedEnvelope),
+        };
+        match era {
+            Era::Byron => Err(EraDecodingError::UnsupportedEra(era)),
+            Era::Shelley => decode_in_era(era, blk_bytes).map(MultiEraBlock::Shelley),
+            Era::Allegra => decode_in_era(era, blk_bytes).map(MultiEraBlock::Allegra),
+            Era::Mary => decode_in_era(era, blk_bytes).map(MultiEraBlock::Mary),
+            Era::Alonzo => decode_in_era(era, blk_bytes).map(MultiEraBlock::Alonzo),
+            Era::Babbage => decode_in_era(era, blk_bytes).map(MultiEraBlock::Babbage),
+            Era::Conway => decode_in_era(era, blk_bytes).map(MultiEraBlock::Conway),
+        }
+    }
+}
+
+fn decode_in_era<T: Deserialize>(era: Era, bytes: &[u8]) -> Result<T, EraDecodingError> {
+    T::from_cbor_bytes(bytes).map_err(|err| EraDecodingError::Cbor {
+        era,
+        reason: err.to_string(),
+    })
+}
+
+/// Block header as it is sent in node-to-node chain-sync.
+pub trait BlockHeader: Sized {
+    fn from_header_content(variant: u8, bytes: &[u8]) -> Result<Self, EraDecodingError>;
+    /// Point of the block the header belongs to.
+    fn point(&self) -> Point;
+}
+
+/// Header of a block of any era since Shelley.
+#[derive(Clone, Debug)]
+pub enum MultiEraHeader {
+    /// Headers from Shelley to Alonzo share the same format.
+    ShelleyCompatible(Era, ShelleyHeader),
+    Babbage(BabbageHeader),
+    Conway(ConwayHeader),
+}
+
+impl BlockHeader for MultiEraHeader {
+    fn from_header_content(variant: u8, bytes: &[u8]) -> Result<Self, EraDecodingError> {
+        let era = Era::from_header_variant(variant).ok_or(EraDecodingError::UnknownEra(variant))?;
+        match era {
+            Era::Byron => Err(EraDecodingError::UnsupportedEra(era)),
+            Era::Shelley | Era::Allegra | Era::Mary | Era::Alonzo => {
+                decode_in_era(era, bytes).map(|hdr| MultiEraHeader::ShelleyCompatible(era, hdr))
+            }
+            Era::Babbage => decode_in_era(era, bytes).map(MultiEraHeader::Babbage),
+            Era::Conway => decode_in_era(era, bytes).map(MultiEraHeader::Conway),
+        }
+    }
+
+    fn point(&self) -> Point {
+        match self {
+            MultiEraHeader::ShelleyCompatible(_, hdr) => {
+                Point::Specific(hdr.body.slot, hash_block_header_canonical(hdr))
+            }
+            MultiEraHeader::Babbage(hdr) => {
+                Point::Specific(hdr.header_body.slot, hash_block_header_canonical(hdr))
+            }
+            MultiEraHeader::Conway(hdr) => {
+                Point::Specific(hdr.header_body.slot, hash_block_header_canonical(hdr))
+            }
+        }
+    }
+}
+
+impl BlockPoint for MultiEraBlock {
+    fn point(&self) -> Point {
+        Point::Specific(self.slot(), self.header_hash())
+    }
+}
+
+impl MultiEraBlock {
+    pub fn era(&self) -> Era {
+        match self {
+            MultiEraBlock::Shelley(_) => Era::Shelley,
+            MultiEraBlock::Allegra(_) => Era::Allegra,
+            MultiEraBlock::Mary(_) => Era::Mary,
+            MultiEraBlock::Alonzo(_) => Era::Alonzo,
+            MultiEraBlock::Babbage(_) => Era::Babbage,
+            MultiEraBlock::Conway(_) => Era::Conway,
+        }
+    }
+
+    pub fn slot(&self) -> u64 {
+        match self {
+            MultiEraBlock::Shelley(blk) => blk.header.body.slot,
+            MultiEraBlock::Allegra(blk) => blk.header.body.slot,
+            MultiEraBlock::Mary(blk) => blk.header.body.slot,
+            MultiEraBlock::Alonzo(blk) => blk.header.body.slot,
+            MultiEraBlock::Babbage(blk) => blk.header.header_body.slot,
+            MultiEraBlock::Conway(blk) => blk.header.header_body.slot,
+        }
+    }
+
+    pub fn block_number(&self) -> u64 {
+        match self {
+            MultiEraBlock::Shelley(blk) => blk.header.body.block_number,
+            MultiEraBlock::Allegra(blk) => blk.header.body.block_number,
+            MultiEraBlock::Mary(blk) => blk.header.body.block_number,
+            MultiEraBlock::Alonzo(blk) => blk.header.body.block_number,
+            MultiEraBlock::Babbage(blk) => blk.header.header_body.block_number,
+            MultiEraBlock::Conway(blk) => blk.header.header_body.block_number,
+        }
+    }
+
+    pub fn header_hash(&self) -> BlockHeaderHash {
+        match self {
+            MultiEraBlock::Shelley(blk) => hash_block_header_canonical(&blk.header),
+            MultiEraBlock::Allegra(blk) => hash_block_header_canonical(&blk.header),
+            MultiEraBlock::Mary(blk) => hash_block_header_canonical(&blk.header),
+            MultiEraBlock::Alonzo(blk) => hash_block_header_canonical(&blk.header),
+            MultiEraBlock::Babbage(blk) => hash_block_header_canonical(&blk.header),
+            MultiEraBlock::Conway(blk) => hash_block_header_canonical(&blk.header),
+        }
+    }
+
+    /// Unpack transactions which passed phase-2 validation, in the order they appear in the block.
+    pub fn into_valid_transactions(self) -> Vec<IndexedTx> {
+        match self {
+            MultiEraBlock::Shelley(blk) => index_valid_transactions(
+                blk.transaction_bodies,
+                blk.transaction_witness_sets,
+                blk.transaction_metadata_set,
+                vec![],
+                MultiEraWitnessSet::Shelley,
+                MultiEraAuxiliaryData::Shelley,
+            ),
+            MultiEraBlock::Allegra(blk) => index_valid_transactions(
+                blk.transaction_bodies,
+                blk.transaction_witness_sets,
+                blk.auxiliary_data_set,
+                vec![],
+                MultiEraWitnessSet::Allegra,
+                MultiEraAuxiliaryData::Allegra,
+            ),
+            MultiEraBlock::Mary(blk) => index_valid_transactions(
+                blk.transaction_bodies,
+                blk.transaction_witness_sets,
+                blk.auxiliary_data_set,
+                vec![],
+                MultiEraWitnessSet::Allegra,
+                MultiEraAuxiliaryData::Allegra,
+            ),
+            MultiEraBlock::Alonzo(blk) => index_valid_transactions(
+                blk.transaction_bodies,
+                blk.transaction_witness_sets,
+                blk.auxiliary_data_set,
+                blk.invalid_transactions,
+                MultiEraWitnessSet::Alonzo,
+                MultiEraAuxiliaryData::Alonzo,
+            ),
+            MultiEraBlock::Babbage(blk) => index_valid_transactions(
+                blk.transaction_bodies,
+                blk.transaction_witness_sets,
+                blk.auxiliary_data_set,
+                blk.invalid_transactions,
+                MultiEraWitnessSet::Babbage,
+                MultiEraAuxiliaryData::Babbage,
+            ),
+            MultiEraBlock::Conway(blk) => index_valid_transactions(
+                blk.transaction_bodies,
+                blk.transaction_witness_sets,
+                blk.auxiliary_data_set,
+                blk.invalid_transactions,
+                MultiEraWitnessSet::Conway,
+                MultiEraAuxiliaryData::Conway,
+            ),
+        }
+    }
+}
+
+/// Inputs, reference inputs and outputs of a transaction.
+type TxParts = (
+    Vec<TransactionInput>,
+    Vec<TransactionInput>,
+    Vec<TransactionOutput>,
+);
+
+/// Transaction body of a particular era.
+trait EraTxBody: Serialize {
+    /// Split the body into inputs, reference inputs and outputs upcast to the latest format.
+    fn into_parts(self) -> TxParts;
+}
+
+impl EraTxBody for ShelleyTransactionBody {
+    fn into_parts(self) -> TxParts {
+        let outputs = self
+            .outputs
+            .into_iter()
+            .map(|out| TransactionOutput::new(out.address, Value::from(out.amount), None, None))
+            .collect();
+        (self.inputs, vec![], outputs)
+    }
+}
+
+impl EraTxBody for AllegraTransactionBody {
+    fn into_parts(self) -> TxParts {
+        let outputs = self
+            .outputs
+            .into_iter()
+            .map(|out| TransactionOutput::new(out.address, Value::from(out.amount), None, None))
+            .collect();
+        (self.inputs, vec![], outputs)
+    }
+}
+
+impl EraTxBody for MaryTransactionBody {
+    fn into_parts(self) -> TxParts {
+        let outputs = self
+            .outputs
+            .into_iter()
+            .map(|out| TransactionOutput::new(out.address, out.amount, None, None))
+            .collect();
+        (self.inputs, vec![], outputs)
+    }
+}
+
+impl EraTxBody for AlonzoTransactionBody {
+    fn into_parts(self) -> TxParts {
+        let outputs = self
+            .outputs
+            .into_iter()
+            .map(TransactionOutput::AlonzoFormatTxOut)
+            .collect();
+        (self.inputs, vec![], outputs)
+    }
+}
+
+impl EraTxBody for BabbageTransactionBody {
+    fn into_parts(self) -> TxParts {
+        let outputs = self.outputs.into_iter().map(|out| out.upcast()).collect();
+        (self.inputs, self.reference_inputs.unwrap_or_default(), outputs)
+    }
+}
+
+impl EraTxBody for ConwayTransactionBody {
+    fn into_parts(self) -> TxParts {
+        (
+            self.inputs,
+            self.reference_inputs.unwrap_or_default(),
+            self.outputs,
+        )
+    }
+}
+
+/// Assemble transactions of a block of any era from its bodies, witness sets and auxiliary data,
+/// leaving out the ones which failed phase-2 validation.
+fn index_valid_transactions<Body, WitnessSet, AuxData>(
+    bodies: Vec<Body>,
+    witness_sets: Vec<WitnessSet>,
+    mut auxiliary_data_set: OrderedHashMap<u16, AuxData>,
+    invalid_transactions: Vec<u16>,
+    wrap_witness_set: fn(WitnessSet) -> MultiEraWitnessSet,
+    wrap_auxiliary_data: fn(AuxData) -> MultiEraAuxiliaryData,
+) -> Vec<IndexedTx>
+where
+    Body: EraTxBody,
+{
+    let txs = bodies.into_iter().zip(witness_sets).collect();
+    valid_only(txs, invalid_transactions)
+        .map(|(ix, (body, witness_set))| {
+            let body_bytes = body.to_cbor_bytes();
+            let hash = hash_transaction_bytes(&body_bytes);
+            let (inputs, reference_inputs, outputs) = body.into_parts();
+            IndexedTx {
+                ix,
+                hash,
+                tx: LedgerTx {
+                    inputs,
+                    reference_inputs,
+                    outputs,
+                    witness_set: wrap_witness_set(witness_set),
+                    auxiliary_data: auxiliary_data_set.remove(&(ix as u16)).map(wrap_auxiliary_data),
+                    body_bytes,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Filter out transactions which failed phase-2 validation. Valid ones keep their index in the block.
+fn valid_only<T>(txs: Vec<T>, invalid_transactions: Vec<u16>) -> impl Iterator<Item = (usize, T)> {
+    let invalid_indices: HashSet<u16> = HashSet::from_iter(invalid_transactions);
+    txs.into_iter()
+        .enumerate()
+        .filter(move |(ix, _)| !invalid_indices.contains(&(*ix as u16)))
+}
+
+/// Transaction along with its position in the block and canonical hash of its body.
+#[derive(Clone, Debug)]
+pub struct IndexedTx {
+    /// Index of the transaction in the block, invalid transactions included.
+    pub ix: usize,
+    pub hash: TransactionHash,
+    pub tx: LedgerTx,
+}
+
+/// Transaction in a form independent of the era of the block it was included in.
+/// Outputs of all eras are represented in the latest format.
+#[derive(Clone, Debug)]
+pub struct LedgerTx {
+    pub inputs: Vec<TransactionInput>,
+    /// Inputs referenced without being spent, available since Babbage.
+    pub reference_inputs: Vec<TransactionInput>,
+    pub outputs: Vec<TransactionOutput>,
+    /// Witnesses of the transaction (signatures, scripts, datums, redeemers) as they are encoded in its era.
+    pub witness_set: MultiEraWitnessSet,
+    /// Metadata and auxiliary scripts attached to the transaction as they are encoded in its era.
+    pub auxiliary_data: Option<MultiEraAuxiliaryData>,
+    /// Serialized body of the transaction in its original era-specific encoding.
+    pub body_bytes: Vec<u8>,
+}
+
+/// Witness set of a transaction of any era since Shelley.
+#[derive(Clone, Debug)]
+pub enum MultiEraWitnessSet {
+    Shelley(ShelleyTransactionWitnessSet),
+    /// Witness sets of Allegra and Mary share the same format.
+    Allegra(AllegraTransactionWitnessSet),
+    Alonzo(AlonzoTransactionWitnessSet),
+    Babbage(BabbageTransactionWitnessSet),
+    Conway(TransactionWitnessSet),
+}
+
+/// Auxiliary data of a transaction of any era since Shelley.
+#[derive(Clone, Debug)]
+pub enum MultiEraAuxiliaryData {
+    /// Shelley transactions carry metadata only.
+    Shelley(Metadata),
+    /// Auxiliary data of Allegra and Mary share the same format.
+    Allegra(AllegraAuxiliaryData),
+    Alonzo(AlonzoAuxiliaryData),
+    Babbage(BabbageAuxiliaryData),
+    Conway(AuxiliaryData),
+}
+
+#[cfg(test)]
+mod tests {
+    use cml_core::serialization::Serialize;
+    use cml_crypto::TransactionHash;
+
+    use crate::multi_era::{
+        valid_only, Era, EraDecodingError, FromEraTaggedBytes, IndexedTx, MultiEraAuxiliaryData,
+        MultiEraBlock, MultiEraWitnessSet,
+    };
+
+    #[test]
+    fn era_tags_round_trip() {
+        for era in [
+            Era::Byron,
+            Era::Shelley,
+            Era::Allegra,
+            Era::Mary,
+            Era::Alonzo,
+            Era::Babbage,
+            Era::Conway,
+        ] {
+            assert_eq!(Era::from_tag(era.tag()), Some(era));
+        }
+        assert_eq!(Era::from_tag(0), Some(Era::Byron));
+        assert_eq!(Era::from_tag(8), None);
+    }
+
+    #[test]
+    fn malformed_envelopes_are_rejected() {
+        assert!(matches!(
+            MultiEraBlock::from_era_tagged_bytes(&[0x83, 0x06, 0x80]),
+            Err(EraDecodingError::MalformedEnvelope)
+        ));
+        assert!(matches!(
+            MultiEraBlock::from_era_tagged_bytes(&[0x82, 0x09, 0x80]),
+            Err(EraDecodingError::UnknownEra(9))
+        ));
+        assert!(matches!(
+            MultiEraBlock::from_era_tagged_bytes(&[0x82, 0x01, 0x80]),
+            Err(EraDecodingError::UnsupportedEra(Era::Byron))
+        ));
+        assert!(matches!(
+            MultiEraBlock::from_era_tagged_bytes(&[0x82, 0x07, 0x80]),
+            Err(EraDecodingError::Cbor { era: Era::Conway, .. })
+        ));
+    }
+
+    #[test]
+    fn valid_transactions_keep_their_index_in_block() {
+        let valid = valid_only(vec!["a", "b", "c", "d"], vec![1, 2]).collect::<Vec<_>>();
+        assert_eq!(valid, vec![(0, "a"), (3, "d")]);
+    }
+
+    /// Blocks under `fixtures/blocks` are encoded by hand following the CDDL of each era.
+    /// Every block holds two transactions spending `[0xa0 + i; 32]#i` and paying
+    /// `1_000_000 + i` and `2_000_000 + i` lovelace to enterprise addresses `[0xb0 + i; 28]`
+    /// and `[0xc0 + i; 28]`. The first transaction carries metadata, since Alonzo it also carries
+    /// a datum in its witness set while the second one is marked invalid.
+    struct BlockFixture {
+        bytes: &'static [u8],
+        era: Era,
+        slot: u64,
+        block_number: u64,
+        header_hash: &'static str,
+        /// Hashes of transactions which passed phase-2 validation.
+        tx_hashes: &'static [&'static str],
+    }
+
+    const METADATA: &str = "a11902a268737065637472756d";
+    const EMPTY_WITNESS_SET: &str = "a0";
+    const WITNESS_SET_WITH_DATUM: &str = "a10481182a";
+
+    fn witness_set_bytes(witness_set: &MultiEraWitnessSet) -> Vec<u8> {
+        match witness_set {
+            MultiEraWitnessSet::Shelley(ws) => ws.to_cbor_bytes(),
+            MultiEraWitnessSet::Allegra(ws) => ws.to_cbor_bytes(),
+            MultiEraWitnessSet::Alonzo(ws) => ws.to_cbor_bytes(),
+            MultiEraWitnessSet::Babbage(ws) => ws.to_cbor_bytes(),
+            MultiEraWitnessSet::Conway(ws) => ws.to_cbor_bytes(),
+        }
+    }
+
+    fn auxiliary_data_bytes(auxiliary_data: &MultiEraAuxiliaryData) -> Vec<u8> {
+        match auxiliary_data {
+            MultiEraAuxiliaryData::Shelley(ad) => ad.to_cbor_bytes(),
+            MultiEraAuxiliaryData::Allegra(ad) => ad.to_cbor_bytes(),
+            MultiEraAuxiliaryData::Alonzo(ad) => ad.to_cbor_bytes(),
+            MultiEraAuxiliaryData::Babbage(ad) => ad.to_cbor_bytes(),
+            MultiEraAuxiliaryData::Conway(ad) => ad.to_cbor_bytes(),
+        }
+    }
+
+    fn address(byte: u8) -> Vec<u8> {
+        let mut bytes = vec![0x61];
+        bytes.extend([byte; 28]);
+        bytes
+    }
+
+    fn check_block(fixture: BlockFixture) {
+        let blk = MultiEraBlock::from_era_tagged_bytes(fixture.bytes).unwrap();
+        assert_eq!(blk.era(), fixture.era);
+        assert_eq!(blk.slot(), fixture.slot);
+        assert_eq!(blk.block_number(), fixture.block_number);
+        assert_eq!(blk.header_hash().to_hex(), fixture.header_hash);
+        let has_scripts = matches!(fixture.era, Era::Alonzo | Era::Babbage | Era::Conway);
+        let has_reference_inputs = matches!(fixture.era, Era::Babbage | Era::Conway);
+        let txs = blk.into_valid_transactions();
+        assert_eq!(
+            txs.iter().map(|tx| tx.hash.to_hex()).collect::<Vec<_>>(),
+            fixture.tx_hashes
+        );
+        for (i, IndexedTx { ix, tx, .. }) in txs.into_iter().enumerate() {
+            let n = i as u8;
+            assert_eq!(ix, i);
+            assert_eq!(tx.inputs.len(), 1);
+            assert_eq!(tx.inputs[0].transaction_id, TransactionHash::from([0xa0 + n; 32]));
+            assert_eq!(tx.inputs[0].index, i as u64);
+            if has_reference_inputs {
+                assert_eq!(tx.reference_inputs.len(), 1);
+                assert_eq!(
+                    tx.reference_inputs[0].transaction_id,
+                    TransactionHash::from([0xe0 + n; 32])
+                );
+            } else {
+                assert!(tx.reference_inputs.is_empty());
+            }
+            assert_eq!(
+                tx.outputs
+                    .iter()
+                    .map(|out| (out.address().to_raw_bytes(), out.amount().coin))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (address(0xb0 + n), 1_000_000 + i as u64),
+                    (address(0xc0 + n), 2_000_000 + i as u64)
+                ]
+            );
+            let expected_witness_set = if has_scripts && i == 0 {
+                WITNESS_SET_WITH_DATUM
+            } else {
+                EMPTY_WITNESS_SET
+            };
+            assert_eq!(
+                hex::encode(witness_set_bytes(&tx.witness_set)),
+                expected_witness_set
+            );
+            assert_eq!(
+                tx.auxiliary_data
+                    .as_ref()
+                    .map(|ad| hex::encode(auxiliary_data_bytes(ad))),
+                (i == 0).then(|| METADATA.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn shelley_block_is_decoded() {
+        check_block(BlockFixture {
+            bytes: include_bytes!("../fixtures/blocks/shelley.block"),
+            era: Era::Shelley,
+            slot: 4_500_000,
+            block_number: 100,
+            header_hash: "bad4f45d7516eeb0370ae2de141f06127bd8129cde3cb70fedff6bf27b8e00c8",
+            tx_hashes: &[
+                "e90bd0d2ab2a9881c2daad3572a4742f79c6bacdf3b806efaf9b73f4a4eedc15",
+                "edc918b8ee15cade16134404c1ed145ac4ad7cc2630abe92f62d34026b26b5b3",
+            ],
+        });
+    }
+
+    #[test]
+    fn allegra_block_is_decoded() {
+        check_block(BlockFixture {
+            bytes: include_bytes!("../fixtures/blocks/allegra.block"),
+            era: Era::Allegra,
+            slot: 16_588_800,
+            block_number: 200,
+            header_hash: "52bad56dbc54cfd8c322e6fc5b479c355b7cecef493bf2cb884987bf65a458be",
+            tx_hashes: &[
+                "6dbcba8f765010c610954625857a357cb442eebde1c7ffcb463fb6349c64b139",
+                "00919c6fc523a531d9538e4f91445128635be5b6b29b1e065ebfee031df45224",
+            ],
+        });
+    }
+
+    #[test]
+    fn mary_block_is_decoded() {
+        check_block(BlockFixture {
+            bytes: include_bytes!("../fixtures/blocks/mary.block"),
+            era: Era::Mary,
+            slot: 23_068_800,
+            block_number: 300,
+            header_hash: "5a365bc05c14af100eb99004cddb32d289fa4ae4fa8fe4ed2546bf1ac312a64f",
+            tx_hashes: &[
+                "033a848954a558485153eb9ff5dc7effc52127b6620a46bb2d30221dc5315288",
+                "c2126307763385aa1567de67a35fb2fc52932d7bb113a7b2b9239b46fd89614d",
+            ],
+        });
+    }
+
+    #[test]
+    fn alonzo_block_is_decoded() {
+        check_block(BlockFixture {
+            bytes: include_bytes!("../fixtures/blocks/alonzo.block"),
+            era: Era::Alonzo,
+            slot: 39_916_975,
+            block_number: 400,
+            header_hash: "4c5f9330488c951c97b0229fadf6bcb9b8cba3100b0f7c0b3f58741d69786086",
+            tx_hashes: &["42bd78a0457b817f8c51f60077ee4050349ae29a22473df193906939e1858522"],
+        });
+    }
+
+    #[test]
+    fn babbage_block_is_decoded() {
+        check_block(BlockFixture {
+            bytes: include_bytes!("../fixtures/blocks/babbage.block"),
+            era: Era::Babbage,
+            slot: 72_316_896,
+            block_number: 500,
+            header_hash: "ccdce3142c72411dfb78cfd09c4272956cc3efd0472665aaf573df7ad27e8961",
+            tx_hashes: &["b0fdc1e857f366ad60e29426e9d100df16df9c91bd90b94855a4073662e97e06"],
+        });
+    }
+
+    #[test]
+    fn conway_block_is_decoded() {
+        check_block(BlockFixture {
+            bytes: include_bytes!("../fixtures/blocks/conway.block"),
+            era: Era::Conway,
+            slot: 133_660_800,
+            block_number: 600,
+            header_hash: "6b6f90c04cb1f4d91fb033c55bd9cf7ba8fd8a56cdeea4f9acae773d1f8e3577",
+            tx_hashes: &["2bb311a5f8987a56bf42e5396a93ba04d0d4ea9607360ac390dfb33b67a89ad7"],
+        });
+    }
+}
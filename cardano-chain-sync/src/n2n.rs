@@ -0,0 +1,341 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use log::debug;
+use pallas_network::miniprotocols::chainsync::{HeaderContent, NextResponse, State};
+use pallas_network::miniprotocols::{
+    blockfetch, chainsync, handshake, PROTOCOL_N2N_BLOCK_FETCH, PROTOCOL_N2N_CHAIN_SYNC,
+    PROTOCOL_N2N_HANDSHAKE,
+};
+use pallas_network::multiplexer;
+use pallas_network::multiplexer::{Bearer, RunningPlexer};
+use tokio::net::ToSocketAddrs;
+use tokio::sync::Mutex;
+
+use crate::cache::LedgerCache;
+use crate::client::{local_chain_points, rollback_to_intersection, ChainSync, Error, Point};
+use crate::data::ChainUpgrade;
+use crate::multi_era::{BlockHeader, FromEraTaggedBytes};
+
+/// Chain-sync client following a remote node over TCP.
+/// Only headers are pulled through chain-sync, blocks are then requested via block-fetch.
+pub struct N2NChainSyncClient<Header, Block> {
+    plexer: RunningPlexer,
+    chain_sync: chainsync::N2NClient,
+    block_fetch: blockfetch::Client,
+    /// Point of the block announced by the node which is yet to be fetched.
+    pending_fetch: Option<Point>,
+    /// Rollback to the intersection to be yielded before pulling from the node.
+    pending_rollback: Option<Point>,
+    pd: PhantomData<(Header, Block)>,
+}
+
+impl<Header, Block> N2NChainSyncClient<Header, Block> {
+    pub async fn init<Cache>(
+        cache: Arc<Mutex<Cache>>,
+        addr: impl ToSocketAddrs,
+        magic: u64,
+        starting_point: Point,
+    ) -> Result<Self, Error>
+    where
+        Cache: LedgerCache,
+    {
+        Self::resume(cache, addr, magic, starting_point, None).await
+    }
+
+    /// Connect to the node and continue sync right after `resume_from` point if it is given.
+    pub async fn resume<Cache>(
+        cache: Arc<Mutex<Cache>>,
+        addr: impl ToSocketAddrs,
+        magic: u64,
+        starting_point: Point,
+        resume_from: Option<Point>,
+    ) -> Result<Self, Error>
+    where
+        Cache: LedgerCache,
+    {
+        let bearer = Bearer::connect_tcp(addr).await.map_err(Error::ConnectFailure)?;
+
+        let mut mplex = multiplexer::Plexer::new(bearer);
+
+        let hs_channel = mplex.subscribe_client(PROTOCOL_N2N_HANDSHAKE);
+        let cs_channel = mplex.subscribe_client(PROTOCOL_N2N_CHAIN_SYNC);
+        let bf_channel = mplex.subscribe_client(PROTOCOL_N2N_BLOCK_FETCH);
+
+        let plexer = mplex.spawn();
+
+        let versions = handshake::n2n::VersionTable::v7_and_above(magic);
+        let mut client = handshake::Client::new(hs_channel);
+
+        let handshake = client
+            .handshake(versions)
+            .await
+            .map_err(Error::HandshakeProtocol)?;
+
+        if let handshake::Confirmation::Rejected(reason) = handshake {
+            return Err(Error::HandshakeRefused(reason));
+        }
+
+        let mut cs_client = chainsync::Client::new(cs_channel);
+
+        let (tip, candidates) = local_chain_points(&*cache.lock().await, starting_point, resume_from).await;
+
+        debug!("Looking for intersection at {:?}", candidates);
+
+        let intersection: Point = match cs_client
+            .find_intersect(candidates.into_iter().map(Into::into).collect())
+            .await
+            .map_err(Error::ChainSyncProtocol)?
+        {
+            (Some(point), _) => point.into(),
+            (None, _) => return Err(Error::IntersectionNotFound),
+        };
+
+        debug!("Found intersection at {:?}", intersection);
+
+        Ok(Self {
+            plexer,
+            chain_sync: cs_client,
+            block_fetch: blockfetch::Client::new(bf_channel),
+            pending_fetch: None,
+            pending_rollback: rollback_to_intersection(tip, intersection),
+            pd: PhantomData,
+        })
+    }
+}
+
+impl<Header, Block> ChainSync<Block> for N2NChainSyncClient<Header, Block>
+where
+    Header: BlockHeader,
+    Block: FromEraTaggedBytes,
+{
+    async fn try_pull_next(&mut self) -> Result<Option<ChainUpgrade<Block>>, Error> {
+        if let Some(point) = self.pending_rollback.take() {
+            return Ok(Some(ChainUpgrade::RollBackward(point)));
+        }
+        let point = match self.pending_fetch.take() {
+            Some(point) => point,
+            None => {
+                let response = match self.chain_sync.state() {
+                    State::MustReply => self.chain_sync.recv_while_can_await().await,
+                    _ => self.chain_sync.request_next().await,
+                };
+                match response {
+                    Ok(NextResponse::RollForward(HeaderContent { variant, cbor, .. }, _)) => {
+                        match Header::from_header_content(variant, &cbor) {
+                            Ok(header) => header.point(),
+                            Err(err) => {
+                                debug!(
+                                    "Header deserialization failed: {}, bytes: {}",
+                                    err,
+                                    hex::encode(cbor)
+                                );
+                                return Err(Error::BlockDecoding(err));
+                            }
+                        }
+                    }
+                    Ok(NextResponse::RollBackward(pt, _)) => {
+                        return Ok(Some(ChainUpgrade::RollBackward(pt.into())))
+                    }
+                    Ok(NextResponse::Await) => return Ok(None),
+                    Err(err) => return Err(Error::ChainSyncProtocol(err)),
+                }
+            }
+        };
+        match self.block_fetch.fetch_single(point.into()).await {
+            Ok(raw) => match Block::from_era_tagged_bytes(&raw) {
+                Ok(blk) => Ok(Some(ChainUpgrade::RollForward {
+                    blk,
+                    blk_bytes: raw,
+                    replayed: false,
+                })),
+                Err(err) => {
+                    debug!(
+                        "Block deserialization failed: {}, bytes: {}",
+                        err,
+                        hex::encode(raw)
+                    );
+                    Err(Error::BlockDecoding(err))
+                }
+            },
+            Err(err) => {
+                // The header is already consumed, so the block has to be fetched on the next pull.
+                self.pending_fetch = Some(point);
+                Err(Error::BlockFetchProtocol(err))
+            }
+        }
+    }
+
+    async fn close(self) {
+        self.plexer.abort().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use cml_crypto::BlockHeaderHash;
+    use pallas_network::facades::PeerServer;
+    use pallas_network::miniprotocols::blockfetch::BlockRequest;
+    use pallas_network::miniprotocols::chainsync::{ClientRequest, HeaderContent, Tip};
+    use tokio::net::TcpListener;
+    use tokio::sync::Mutex;
+
+    use crate::cache::inmemory::InMemoryLedgerCache;
+    use crate::client::{ChainSync, Error, Point};
+    use crate::data::ChainUpgrade;
+    use crate::multi_era::{BlockHeader, EraDecodingError, FromEraTaggedBytes};
+    use crate::n2n::N2NChainSyncClient;
+
+    const MAGIC: u64 = 42;
+
+    /// Header encoded as slot followed by the header hash.
+    struct TestHeader(Point);
+
+    impl BlockHeader for TestHeader {
+        fn from_header_content(_: u8, bytes: &[u8]) -> Result<Self, EraDecodingError> {
+            let (slot, hash) = bytes.split_at(8);
+            Ok(TestHeader(Point::Specific(
+                u64::from_be_bytes(slot.try_into().unwrap()),
+                BlockHeaderHash::from(<[u8; 32]>::try_from(hash).unwrap()),
+            )))
+        }
+
+        fn point(&self) -> Point {
+            self.0
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct TestBlock(Vec<u8>);
+
+    impl FromEraTaggedBytes for TestBlock {
+        fn from_era_tagged_bytes(bytes: &[u8]) -> Result<Self, EraDecodingError> {
+            if bytes.is_empty() {
+                return Err(EraDecodingError::MalformedEnvelope);
+            }
+            Ok(TestBlock(bytes.to_vec()))
+        }
+    }
+
+    /// Accept a client, let it intersect at origin, announce the header of the block at `slot`
+    /// and serve the block once it is requested.
+    async fn serve_block(
+        listener: TcpListener,
+        slot: u64,
+        header_hash: [u8; 32],
+        block_bytes: Vec<u8>,
+    ) -> PeerServer {
+        let mut server = PeerServer::accept(&listener, MAGIC).await.unwrap();
+        let tip = Tip(pallas_network::miniprotocols::Point::Origin, 0);
+        let cs = server.chainsync();
+        match cs.recv_while_idle().await.unwrap() {
+            Some(ClientRequest::Intersect(_)) => cs
+                .send_intersect_found(pallas_network::miniprotocols::Point::Origin, tip.clone())
+                .await
+                .unwrap(),
+            _ => panic!("Intersection was expected"),
+        }
+        let mut header = slot.to_be_bytes().to_vec();
+        header.extend_from_slice(&header_hash);
+        match cs.recv_while_idle().await.unwrap() {
+            Some(ClientRequest::RequestNext) => cs
+                .send_roll_forward(
+                    HeaderContent {
+                        variant: 6,
+                        byron_prefix: None,
+                        cbor: header,
+                    },
+                    tip,
+                )
+                .await
+                .unwrap(),
+            _ => panic!("Next header was expected"),
+        }
+        let bf = server.blockfetch();
+        match bf.recv_while_idle().await.unwrap() {
+            Some(BlockRequest((from, to))) => {
+                assert_eq!(from, to);
+                assert_eq!(
+                    from,
+                    pallas_network::miniprotocols::Point::Specific(slot, header_hash.to_vec())
+                );
+                bf.send_start_batch().await.unwrap();
+                bf.send_block(block_bytes).await.unwrap();
+                bf.send_batch_done().await.unwrap();
+            }
+            _ => panic!("Block request was expected"),
+        }
+        server
+    }
+
+    #[tokio::test]
+    async fn blocks_are_fetched_for_announced_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let header_hash = [7u8; 32];
+        let slot = 100u64;
+        let block_bytes = vec![0x82, 0x07, 0x80];
+        let expected_block = block_bytes.clone();
+        let responder = tokio::spawn(async move {
+            let mut server = serve_block(listener, slot, header_hash, block_bytes).await;
+            let tip = Tip(pallas_network::miniprotocols::Point::Origin, 0);
+            let cs = server.chainsync();
+            match cs.recv_while_idle().await.unwrap() {
+                Some(ClientRequest::RequestNext) => cs
+                    .send_roll_backward(pallas_network::miniprotocols::Point::Origin, tip)
+                    .await
+                    .unwrap(),
+                _ => panic!("Next header was expected"),
+            }
+        });
+        let mut client = N2NChainSyncClient::<TestHeader, TestBlock>::init(
+            Arc::new(Mutex::new(InMemoryLedgerCache::new())),
+            addr,
+            MAGIC,
+            Point::Origin,
+        )
+        .await
+        .unwrap();
+        match client.try_pull_next().await.unwrap() {
+            Some(ChainUpgrade::RollForward {
+                blk,
+                blk_bytes,
+                replayed,
+            }) => {
+                assert_eq!(blk, TestBlock(expected_block.clone()));
+                assert_eq!(blk_bytes, expected_block);
+                assert!(!replayed);
+            }
+            _ => panic!("Roll forward was expected"),
+        }
+        assert!(matches!(
+            client.try_pull_next().await.unwrap(),
+            Some(ChainUpgrade::RollBackward(Point::Origin))
+        ));
+        responder.await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn undecodable_blocks_are_reported() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responder = tokio::spawn(serve_block(listener, 100, [7u8; 32], vec![]));
+        let mut client = N2NChainSyncClient::<TestHeader, TestBlock>::init(
+            Arc::new(Mutex::new(InMemoryLedgerCache::new())),
+            addr,
+            MAGIC,
+            Point::Origin,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            client.try_pull_next().await,
+            Err(Error::BlockDecoding(EraDecodingError::MalformedEnvelope))
+        ));
+        let _server = responder.await.unwrap();
+        client.close().await;
+    }
+}
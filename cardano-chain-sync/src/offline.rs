@@ -0,0 +1,417 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::thread::JoinHandle;
+
+use async_stream::stream;
+use cml_crypto::{BlockHeaderHash, RawBytesEncoding};
+use futures::{Stream, StreamExt};
+use log::error;
+
+use crate::client::Point;
+use crate::data::ChainUpgrade;
+use crate::multi_era::FromEraTaggedBytes;
+
+/// Chain upgrade as it is recorded. Blocks are kept in their era-tagged encoding.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum RecordedUpgrade {
+    RollForward(Vec<u8>),
+    RollBackward(Point),
+}
+
+impl<Block> From<&ChainUpgrade<Block>> for RecordedUpgrade {
+    fn from(upgr: &ChainUpgrade<Block>) -> Self {
+        match upgr {
+            ChainUpgrade::RollForward { blk_bytes, .. } => RecordedUpgrade::RollForward(blk_bytes.clone()),
+            ChainUpgrade::RollBackward(point) => RecordedUpgrade::RollBackward(*point),
+        }
+    }
+}
+
+/// Stream chain upgrades from recorded ones instead of a node.
+/// The stream ends once all records are consumed or a record fails to be read or decoded.
+pub fn offline_chain_sync_stream<'a, Records, Block>(
+    records: Records,
+) -> impl Stream<Item = ChainUpgrade<Block>> + 'a
+where
+    Records: IntoIterator<Item = io::Result<RecordedUpgrade>> + 'a,
+    Block: FromEraTaggedBytes + 'a,
+{
+    stream! {
+        for record in records {
+            match record {
+                Ok(RecordedUpgrade::RollForward(blk_bytes)) => match Block::from_era_tagged_bytes(&blk_bytes) {
+                    Ok(blk) => yield ChainUpgrade::RollForward {
+                        blk,
+                        blk_bytes,
+                        replayed: false,
+                    },
+                    Err(err) => {
+                        error!(
+                            target: "chain_sync",
+                            "Recorded block deserialization failed: {}, bytes: {}",
+                            err,
+                            hex::encode(blk_bytes)
+                        );
+                        break;
+                    }
+                },
+                Ok(RecordedUpgrade::RollBackward(point)) => yield ChainUpgrade::RollBackward(point),
+                Err(err) => {
+                    error!(target: "chain_sync", "Failed to read recorded chain: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+pub trait Recorder {
+    fn record(&mut self, upgr: RecordedUpgrade) -> io::Result<()>;
+}
+
+/// Record upgrades pulled from the `upstream`, which are passed through.
+/// Records are written in the background, so that the stream is not held up by disk writes.
+/// Pending records are flushed once the stream is dropped.
+pub fn recording<'a, S, Block, R>(upstream: S, recorder: R) -> impl Stream<Item = ChainUpgrade<Block>> + 'a
+where
+    S: Stream<Item = ChainUpgrade<Block>> + 'a,
+    Block: 'a,
+    R: Recorder + Send + 'static,
+{
+    let writer = RecordWriter::spawn(recorder);
+    upstream.inspect(move |upgr| {
+        if let ChainUpgrade::RollForward { replayed: true, .. } = upgr {
+            return;
+        }
+        writer.write(RecordedUpgrade::from(upgr));
+    })
+}
+
+/// Passes records to the `recorder` running in a dedicated thread.
+struct RecordWriter {
+    records: Option<mpsc::Sender<RecordedUpgrade>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl RecordWriter {
+    fn spawn<R: Recorder + Send + 'static>(mut recorder: R) -> Self {
+        let (records, to_record) = mpsc::channel::<RecordedUpgrade>();
+        let worker = thread::spawn(move || {
+            for upgr in to_record {
+                if let Err(err) = recorder.record(upgr) {
+                    error!(target: "chain_sync", "Failed to record chain upgrade: {}", err);
+                }
+            }
+        });
+        Self {
+            records: Some(records),
+            worker: Some(worker),
+        }
+    }
+
+    fn write(&self, upgr: RecordedUpgrade) {
+        if let Some(records) = &self.records {
+            if records.send(upgr).is_err() {
+                error!(target: "chain_sync", "Failed to record chain upgrade: recorder is gone");
+            }
+        }
+    }
+}
+
+impl Drop for RecordWriter {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker finish once pending records are written.
+        self.records.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+const BLOCK_EXT: &str = "block";
+const ROLLBACK_EXT: &str = "rollback";
+
+/// Directory with one file per record named after its sequence number:
+/// `<seq>.block` files hold raw era-tagged block CBOR,
+/// `<seq>.rollback` files hold the point to roll back to as `<slot> <hash hex>` or `origin`.
+pub struct BlockDirReader {
+    files: std::vec::IntoIter<PathBuf>,
+}
+
+impl BlockDirReader {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut files = record_files(dir.as_ref())?;
+        // Sequence numbers are compared as parsed, as they may be written without padding.
+        files.sort_by_key(|(seq, _)| *seq);
+        let files = files.into_iter().map(|(_, path)| path).collect::<Vec<_>>();
+        Ok(Self {
+            files: files.into_iter(),
+        })
+    }
+}
+
+impl Iterator for BlockDirReader {
+    type Item = io::Result<RecordedUpgrade>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = self.files.next()?;
+        Some(match path.extension().and_then(|ext| ext.to_str()) {
+            Some(BLOCK_EXT) => fs::read(&path).map(RecordedUpgrade::RollForward),
+            _ => fs::read_to_string(&path).and_then(|marker| parse_rollback_marker(&marker)),
+        })
+    }
+}
+
+pub struct BlockDirRecorder {
+    dir: PathBuf,
+    next_seq: u64,
+}
+
+impl BlockDirRecorder {
+    /// Records are appended to the ones already in the `dir`.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let next_seq = record_files(&dir)?
+            .into_iter()
+            .map(|(seq, _)| seq + 1)
+            .max()
+            .unwrap_or(0);
+        Ok(Self { dir, next_seq })
+    }
+}
+
+impl Recorder for BlockDirRecorder {
+    fn record(&mut self, upgr: RecordedUpgrade) -> io::Result<()> {
+        let (ext, contents) = match upgr {
+            RecordedUpgrade::RollForward(blk_bytes) => (BLOCK_EXT, blk_bytes),
+            RecordedUpgrade::RollBackward(point) => (ROLLBACK_EXT, rollback_marker(point).into_bytes()),
+        };
+        fs::write(self.dir.join(format!("{:010}.{}", self.next_seq, ext)), contents)?;
+        self.next_seq += 1;
+        Ok(())
+    }
+}
+
+/// Record files in the `dir` along with their sequence numbers.
+fn record_files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_record = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some(BLOCK_EXT | ROLLBACK_EXT)
+        );
+        if let Some(seq) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+            .filter(|_| is_record)
+        {
+            files.push((seq, path));
+        }
+    }
+    Ok(files)
+}
+
+fn rollback_marker(point: Point) -> String {
+    match point {
+        Point::Origin => "origin".to_string(),
+        Point::Specific(slot, hash) => format!("{} {}", slot, hex::encode(hash.to_raw_bytes())),
+    }
+}
+
+fn parse_rollback_marker(marker: &str) -> io::Result<RecordedUpgrade> {
+    let invalid = || {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("malformed rollback marker: {}", marker),
+        )
+    };
+    let point = match marker.split_whitespace().collect::<Vec<_>>().as_slice() {
+        ["origin"] => Point::Origin,
+        [slot, hash] => Point::Specific(
+            slot.parse().map_err(|_| invalid())?,
+            hex::decode(hash)
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .map(BlockHeaderHash::from)
+                .ok_or_else(invalid)?,
+        ),
+        _ => return Err(invalid()),
+    };
+    Ok(RecordedUpgrade::RollBackward(point))
+}
+
+/// Single file holding a sequence of bincode-encoded records.
+pub struct BlockArchiveReader {
+    reader: BufReader<File>,
+}
+
+impl BlockArchiveReader {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+        })
+    }
+}
+
+impl Iterator for BlockArchiveReader {
+    type Item = io::Result<RecordedUpgrade>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match bincode::deserialize_from(&mut self.reader) {
+            Ok(record) => Some(Ok(record)),
+            Err(err) => match *err {
+                bincode::ErrorKind::Io(err) if err.kind() == ErrorKind::UnexpectedEof => None,
+                bincode::ErrorKind::Io(err) => Some(Err(err)),
+                err => Some(Err(io::Error::new(ErrorKind::InvalidData, err))),
+            },
+        }
+    }
+}
+
+pub struct BlockArchiveRecorder {
+    writer: BufWriter<File>,
+}
+
+impl BlockArchiveRecorder {
+    /// Records are appended to the archive if it exists.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl Recorder for BlockArchiveRecorder {
+    fn record(&mut self, upgr: RecordedUpgrade) -> io::Result<()> {
+        bincode::serialize_into(&mut self.writer, &upgr).map_err(|err| match *err {
+            bincode::ErrorKind::Io(err) => err,
+            err => io::Error::new(ErrorKind::InvalidData, err),
+        })?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, StreamExt};
+    use rand::{thread_rng, RngCore};
+
+    use crate::cache::tests::point;
+    use crate::client::Point;
+    use crate::data::ChainUpgrade;
+    use crate::multi_era::{EraDecodingError, FromEraTaggedBytes};
+    use crate::offline::{
+        offline_chain_sync_stream, recording, BlockArchiveReader, BlockArchiveRecorder, BlockDirReader,
+        BlockDirRecorder, RecordedUpgrade,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct RawBlock(Vec<u8>);
+
+    impl FromEraTaggedBytes for RawBlock {
+        fn from_era_tagged_bytes(bytes: &[u8]) -> Result<Self, EraDecodingError> {
+            if bytes.is_empty() {
+                return Err(EraDecodingError::MalformedEnvelope);
+            }
+            Ok(RawBlock(bytes.to_vec()))
+        }
+    }
+
+    fn upgrades() -> Vec<ChainUpgrade<RawBlock>> {
+        let roll_forward = |bytes: Vec<u8>| ChainUpgrade::RollForward {
+            blk: RawBlock(bytes.clone()),
+            blk_bytes: bytes,
+            replayed: false,
+        };
+        vec![
+            roll_forward(vec![0x82, 0x06, 1]),
+            roll_forward(vec![0x82, 0x06, 2]),
+            ChainUpgrade::RollBackward(point(1)),
+            roll_forward(vec![0x82, 0x07, 3]),
+            ChainUpgrade::RollBackward(Point::Origin),
+        ]
+    }
+
+    fn recorded(upgrades: &[ChainUpgrade<RawBlock>]) -> Vec<RecordedUpgrade> {
+        upgrades.iter().map(RecordedUpgrade::from).collect()
+    }
+
+    #[tokio::test]
+    async fn chain_is_replayed_from_block_dir() {
+        let dir = format!("./tmp/{}", thread_rng().next_u32());
+        let recorder = BlockDirRecorder::open(&dir).unwrap();
+        recording(stream::iter(upgrades().into_iter().take(2)), recorder)
+            .collect::<Vec<_>>()
+            .await;
+        // Recording is resumed after the existing records.
+        let recorder = BlockDirRecorder::open(&dir).unwrap();
+        recording(stream::iter(upgrades().into_iter().skip(2)), recorder)
+            .collect::<Vec<_>>()
+            .await;
+        let replayed = offline_chain_sync_stream::<_, RawBlock>(BlockDirReader::open(&dir).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(recorded(&replayed), recorded(&upgrades()));
+    }
+
+    #[test]
+    fn block_dir_is_read_in_order_of_sequence_numbers() {
+        let dir = format!("./tmp/{}", thread_rng().next_u32());
+        std::fs::create_dir_all(&dir).unwrap();
+        for seq in [10, 9, 100] {
+            std::fs::write(format!("{}/{}.block", dir, seq), [seq as u8]).unwrap();
+        }
+        let read = BlockDirReader::open(&dir)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            read,
+            vec![
+                RecordedUpgrade::RollForward(vec![9]),
+                RecordedUpgrade::RollForward(vec![10]),
+                RecordedUpgrade::RollForward(vec![100]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn chain_is_replayed_from_block_archive() {
+        let path = format!("./tmp/{}.chain", thread_rng().next_u32());
+        std::fs::create_dir_all("./tmp").unwrap();
+        let recorder = BlockArchiveRecorder::open(&path).unwrap();
+        let passed_through = recording(stream::iter(upgrades()), recorder)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(recorded(&passed_through), recorded(&upgrades()));
+        let replayed = offline_chain_sync_stream::<_, RawBlock>(BlockArchiveReader::open(&path).unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(recorded(&replayed), recorded(&upgrades()));
+    }
+
+    #[tokio::test]
+    async fn replay_ends_at_malformed_block() {
+        let records = vec![
+            Ok(RecordedUpgrade::RollForward(vec![0x82, 0x06, 1])),
+            Ok(RecordedUpgrade::RollForward(vec![])),
+            Ok(RecordedUpgrade::RollForward(vec![0x82, 0x06, 2])),
+        ];
+        let replayed = offline_chain_sync_stream::<_, RawBlock>(records)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            recorded(&replayed),
+            vec![RecordedUpgrade::RollForward(vec![0x82, 0x06, 1])]
+        );
+    }
+}
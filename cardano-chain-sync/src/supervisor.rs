@@ -0,0 +1,178 @@
+use std::fmt::Display;
+use std::future::Future;
+
+use log::warn;
+
+use spectrum_offchain::reconnect::{connect_with_backoff, Backoff, BackoffConfig};
+
+use crate::client::{ChainSync, Error, Point};
+use crate::data::ChainUpgrade;
+use crate::multi_era::BlockPoint;
+
+/// Chain-sync client which reconnects to the node with backoff once the connection is lost.
+/// Sync is resumed right after the last point yielded, so that downstream sees no gap or duplicate.
+/// The backoff is reset once an upgrade is pulled over the new connection.
+pub struct SupervisedChainSync<Client, Connect> {
+    client: Option<Client>,
+    /// Establish new connection given the point to resume sync from.
+    connect: Connect,
+    /// Point of the last upgrade yielded downstream.
+    last_point: Option<Point>,
+    backoff: Backoff,
+}
+
+impl<Client, Connect, Fut, Err> SupervisedChainSync<Client, Connect>
+where
+    Connect: FnMut(Option<Point>) -> Fut,
+    Fut: Future<Output = Result<Client, Err>>,
+    Err: Display,
+{
+    pub fn new(connect: Connect, conf: BackoffConfig) -> Self {
+        Self {
+            client: None,
+            connect,
+            last_point: None,
+            backoff: Backoff::new(conf),
+        }
+    }
+}
+
+impl<Block, Client, Connect, Fut, Err> ChainSync<Block> for SupervisedChainSync<Client, Connect>
+where
+    Block: BlockPoint,
+    Client: ChainSync<Block>,
+    Connect: FnMut(Option<Point>) -> Fut,
+    Fut: Future<Output = Result<Client, Err>>,
+    Err: Display,
+{
+    async fn try_pull_next(&mut self) -> Result<Option<ChainUpgrade<Block>>, Error> {
+        loop {
+            if self.client.is_none() {
+                let last_point = self.last_point;
+                let connect = &mut self.connect;
+                let client = connect_with_backoff(|| connect(last_point), &mut self.backoff).await;
+                self.client = Some(client);
+            }
+            match self.client.as_mut().unwrap().try_pull_next().await {
+                Ok(upgr) => {
+                    self.backoff.reset();
+                    if let Some(upgr) = &upgr {
+                        self.last_point = Some(match upgr {
+                            ChainUpgrade::RollForward { blk, .. } => blk.point(),
+                            ChainUpgrade::RollBackward(point) => *point,
+                        });
+                    }
+                    return Ok(upgr);
+                }
+                Err(err) => {
+                    warn!(
+                        "Chain-sync connection lost: {}, resuming from {:?}",
+                        err, self.last_point
+                    );
+                    if let Some(client) = self.client.take() {
+                        client.close().await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn close(self) {
+        if let Some(client) = self.client {
+            client.close().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use cml_crypto::BlockHeaderHash;
+
+    use spectrum_offchain::reconnect::BackoffConfig;
+
+    use crate::client::{ChainSync, Error, Point};
+    use crate::data::ChainUpgrade;
+    use crate::multi_era::BlockPoint;
+    use crate::supervisor::SupervisedChainSync;
+
+    #[derive(Debug, Copy, Clone, PartialEq)]
+    struct TestBlock(u64);
+
+    impl BlockPoint for TestBlock {
+        fn point(&self) -> Point {
+            Point::Specific(self.0, BlockHeaderHash::from([self.0 as u8; 32]))
+        }
+    }
+
+    /// Client following chain of blocks with consecutive slots, which fails after a few pulls.
+    struct FlakyClient {
+        blocks: VecDeque<TestBlock>,
+        pulls_left: usize,
+        /// Number of clients closed so far.
+        closed: Rc<Cell<usize>>,
+    }
+
+    impl ChainSync<TestBlock> for FlakyClient {
+        async fn try_pull_next(&mut self) -> Result<Option<ChainUpgrade<TestBlock>>, Error> {
+            if self.pulls_left == 0 {
+                return Err(Error::IntersectionNotFound);
+            }
+            self.pulls_left -= 1;
+            Ok(self.blocks.pop_front().map(|blk| ChainUpgrade::RollForward {
+                blk,
+                blk_bytes: vec![],
+                replayed: false,
+            }))
+        }
+
+        async fn close(self) {
+            self.closed.set(self.closed.get() + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_is_resumed_from_last_yielded_point() {
+        let mut resumed_from = vec![];
+        let closed = Rc::new(Cell::new(0));
+        let mut client = SupervisedChainSync::new(
+            |resume_from: Option<Point>| {
+                resumed_from.push(resume_from);
+                let next_slot = resume_from.map(|pt| pt.get_slot() + 1).unwrap_or(1);
+                let closed = Rc::clone(&closed);
+                async move {
+                    Ok::<_, Error>(FlakyClient {
+                        blocks: (next_slot..=5).map(TestBlock).collect(),
+                        pulls_left: 2,
+                        closed,
+                    })
+                }
+            },
+            BackoffConfig {
+                initial_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+                multiplier: 2,
+            },
+        );
+        let mut pulled = vec![];
+        while let Some(upgr) = client.try_pull_next().await.unwrap() {
+            match upgr {
+                ChainUpgrade::RollForward { blk, .. } => pulled.push(blk.0),
+                ChainUpgrade::RollBackward(_) => panic!("No rollbacks expected"),
+            }
+        }
+        // Broken clients are closed before reconnecting.
+        assert_eq!(closed.get(), 2);
+        client.close().await;
+        assert_eq!(closed.get(), 3);
+        assert_eq!(pulled, vec![1, 2, 3, 4, 5]);
+        assert_eq!(
+            resumed_from,
+            vec![None, Some(TestBlock(2).point()), Some(TestBlock(4).point())]
+        );
+    }
+}
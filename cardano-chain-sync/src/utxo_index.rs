@@ -0,0 +1,671 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::thread::JoinHandle;
+
+use cml_chain::transaction::TransactionOutput;
+use cml_chain::PolicyId;
+use cml_core::serialization::{Deserialize, Serialize};
+use cml_crypto::{RawBytesEncoding, ScriptHash, TransactionHash};
+use futures::{Stream, StreamExt};
+use log::error;
+use rocksdb::{AsColumnFamilyRef, Direction, IteratorMode, WriteBatch};
+
+use spectrum_cardano_lib::transaction::TransactionOutputExtension;
+use spectrum_cardano_lib::OutputRef;
+use spectrum_offchain::rocks;
+use spectrum_offchain::rocks::schema::{migrate, Schema};
+use spectrum_offchain::rocks::{RocksConfig, RocksDB};
+
+use crate::cache::DEFAULT_ROLLBACK_DEPTH;
+use crate::client::Point;
+use crate::data::LedgerTxEvent;
+use crate::event_source::RollbackError;
+use crate::multi_era::LedgerTx;
+
+/// Outputs to be indexed: the ones at tracked script addresses or holding tokens of tracked policies.
+#[derive(Clone, Debug, Default)]
+pub struct UtxoFilter {
+    pub script_hashes: HashSet<ScriptHash>,
+    pub policies: HashSet<PolicyId>,
+}
+
+impl UtxoFilter {
+    pub fn tracks(&self, out: &TransactionOutput) -> bool {
+        out.script_hash()
+            .is_some_and(|sh| self.script_hashes.contains(&sh))
+            || out
+                .value()
+                .multiasset
+                .keys()
+                .any(|policy| self.policies.contains(policy))
+    }
+}
+
+/// Transaction along with the tracked outputs it consumed.
+#[derive(Clone, Debug)]
+pub struct ResolvedTx {
+    pub tx: LedgerTx,
+    pub consumed: Vec<(OutputRef, TransactionOutput)>,
+}
+
+/// Block whose transaction was processed by the index last.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum IndexTip {
+    /// Transaction of the block at the point was applied.
+    Applied(Point),
+    /// Transaction of the block at the point was unapplied,
+    /// so the index is at one of the points preceding it.
+    Unapplied(Point),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UtxoIndexError {
+    #[error("index tip {tip:?} doesn't match chain sync resumed from cache tip {cache_tip:?}, replay from {replay_from:?}")]
+    OutOfSync {
+        tip: IndexTip,
+        cache_tip: Option<Point>,
+        replay_from: Option<Point>,
+    },
+}
+
+/// Index of unspent tracked outputs maintained by applying and unapplying transactions.
+pub struct UtxoIndex {
+    filter: UtxoFilter,
+    utxos: HashMap<OutputRef, TransactionOutput>,
+    /// Outputs spent by recent transactions, kept to restore them on rollback.
+    spent: HashMap<TransactionHash, SpentOutputs>,
+    /// Transactions from [spent](Self::spent) by the height of the block they were applied in.
+    spent_at_height: BTreeMap<u64, Vec<TransactionHash>>,
+    rollback_depth: u64,
+    tip: Option<IndexTip>,
+    /// Writer of changes to the store, if the index is persistent.
+    store: Option<IndexWriter>,
+}
+
+/// Outputs consumed by a transaction applied at the given height.
+#[derive(Clone, Debug)]
+struct SpentOutputs {
+    height: u64,
+    outputs: Vec<(OutputRef, TransactionOutput)>,
+}
+
+impl UtxoIndex {
+    pub fn new(filter: UtxoFilter) -> Self {
+        Self::with_rollback_depth(filter, DEFAULT_ROLLBACK_DEPTH)
+    }
+
+    /// Spent outputs are only restored on rollbacks not deeper than `rollback_depth` blocks.
+    pub fn with_rollback_depth(filter: UtxoFilter, rollback_depth: usize) -> Self {
+        Self {
+            filter,
+            utxos: HashMap::new(),
+            spent: HashMap::new(),
+            spent_at_height: BTreeMap::new(),
+            rollback_depth: rollback_depth as u64,
+            tip: None,
+            store: None,
+        }
+    }
+
+    /// Index persisted in RocksDB. The index is restored from the store on startup,
+    /// outputs which are no longer tracked by the `filter` are left out.
+    /// Changes are written in the background along with the [IndexTip] they lead to,
+    /// pending ones are flushed once the index is dropped.
+    /// Use [UtxoIndex::check_tip] to make sure the restored index is in sync with the chain.
+    pub fn persistent(filter: UtxoFilter, rollback_depth: usize, conf: RocksConfig) -> Self {
+        let db = rocks::open(&conf);
+        let cf = conf.column_family().to_string();
+        migrate(&db, &cf, &SCHEMA).unwrap();
+        let mut index = Self::with_rollback_depth(filter, rollback_depth);
+        {
+            let cf = db.cf_handle(&cf).unwrap();
+            for (key, raw_out) in prefixed_entries(&db, &cf, UTXO_PREFIX) {
+                let utxo = output_ref_from_key(&key).zip(TransactionOutput::from_cbor_bytes(&raw_out).ok());
+                if let Some((oref, out)) = utxo.filter(|(_, out)| index.filter.tracks(out)) {
+                    index.utxos.insert(oref, out);
+                }
+            }
+            for (key, raw_spent) in prefixed_entries(&db, &cf, SPENT_PREFIX) {
+                let tx_hash = <[u8; 32]>::try_from(&key[..]).ok().map(TransactionHash::from);
+                let spent = bincode::deserialize::<StoredSpentOutputs>(&raw_spent)
+                    .ok()
+                    .and_then(SpentOutputs::try_from_stored);
+                if let Some((tx_hash, spent)) = tx_hash.zip(spent) {
+                    index
+                        .spent_at_height
+                        .entry(spent.height)
+                        .or_default()
+                        .push(tx_hash);
+                    index.spent.insert(tx_hash, spent);
+                }
+            }
+            index.tip = db
+                .get_cf(&cf, TIP_KEY)
+                .unwrap()
+                .and_then(|raw_tip| bincode::deserialize(&raw_tip).ok());
+        }
+        index.store = Some(IndexWriter::spawn(db, cf));
+        index
+    }
+
+    pub fn get(&self, oref: &OutputRef) -> Option<&TransactionOutput> {
+        self.utxos.get(oref)
+    }
+
+    pub fn tip(&self) -> Option<IndexTip> {
+        self.tip
+    }
+
+    /// Check that the index is in sync with chain sync resumed from the `cache_tip`,
+    /// or with cached blocks replayed from `replay_from` inclusive.
+    /// Index is out of sync if some of its changes were not persisted,
+    /// as well as if its tip is followed by blocks without transactions, which the index doesn't see.
+    /// Replaying blocks from the point of the index tip brings it back in sync.
+    pub fn check_tip(
+        &self,
+        cache_tip: Option<Point>,
+        replay_from: Option<Point>,
+    ) -> Result<(), UtxoIndexError> {
+        let in_sync = match (self.tip, replay_from) {
+            (None, _) => true,
+            // Index converges once transactions it has applied already are replayed.
+            (Some(IndexTip::Applied(pt) | IndexTip::Unapplied(pt)), Some(replay_from)) => {
+                replay_from.get_slot() <= pt.get_slot()
+            }
+            (Some(IndexTip::Applied(pt)), None) => cache_tip == Some(pt),
+            // Blocks are unapplied down to the cache tip.
+            (Some(IndexTip::Unapplied(pt)), None) => {
+                cache_tip.map_or(true, |cache_tip| cache_tip.get_slot() < pt.get_slot())
+            }
+        };
+        if in_sync {
+            Ok(())
+        } else {
+            Err(UtxoIndexError::OutOfSync {
+                tip: self.tip.unwrap(),
+                cache_tip,
+                replay_from,
+            })
+        }
+    }
+
+    /// Apply the transaction of the block at `point`, returning tracked outputs it consumed.
+    pub fn apply(
+        &mut self,
+        tx: &LedgerTx,
+        tx_hash: TransactionHash,
+        point: Point,
+        block_height: u64,
+    ) -> Vec<(OutputRef, TransactionOutput)> {
+        let mut changes = Vec::new();
+        let consumed: Vec<_> = tx
+            .inputs
+            .iter()
+            .filter_map(|input| {
+                let oref = OutputRef::from(input.clone());
+                self.utxos.remove(&oref).map(|out| (oref, out))
+            })
+            .collect();
+        for (oref, _) in &consumed {
+            changes.push((utxo_key(oref), None));
+        }
+        for (ix, out) in tx.outputs.iter().enumerate() {
+            if self.filter.tracks(out) {
+                let oref = OutputRef::new(tx_hash, ix as u64);
+                changes.push((utxo_key(&oref), Some(out.to_cbor_bytes())));
+                self.utxos.insert(oref, out.clone());
+            }
+        }
+        if !consumed.is_empty() {
+            let spent = SpentOutputs {
+                height: block_height,
+                outputs: consumed.clone(),
+            };
+            changes.push((
+                spent_key(&tx_hash),
+                Some(bincode::serialize(&spent.to_stored()).unwrap()),
+            ));
+            self.spent.insert(tx_hash, spent);
+            self.spent_at_height
+                .entry(block_height)
+                .or_default()
+                .push(tx_hash);
+        }
+        for forgotten in self.forget_spent_below(block_height.saturating_sub(self.rollback_depth)) {
+            changes.push((spent_key(&forgotten), None));
+        }
+        self.persist(changes, IndexTip::Applied(point));
+        consumed
+    }
+
+    /// Unapply the transaction of the block at `point`,
+    /// returning tracked outputs it consumed which are now restored.
+    pub fn unapply(
+        &mut self,
+        tx: &LedgerTx,
+        tx_hash: TransactionHash,
+        point: Point,
+    ) -> Vec<(OutputRef, TransactionOutput)> {
+        let mut changes = Vec::new();
+        for ix in 0..tx.outputs.len() {
+            let oref = OutputRef::new(tx_hash, ix as u64);
+            if self.utxos.remove(&oref).is_some() {
+                changes.push((utxo_key(&oref), None));
+            }
+        }
+        let restored = match self.spent.remove(&tx_hash) {
+            Some(SpentOutputs { height, outputs }) => {
+                if let Some(txs) = self.spent_at_height.get_mut(&height) {
+                    txs.retain(|h| *h != tx_hash);
+                    if txs.is_empty() {
+                        self.spent_at_height.remove(&height);
+                    }
+                }
+                changes.push((spent_key(&tx_hash), None));
+                outputs
+            }
+            None => Vec::new(),
+        };
+        for (oref, out) in &restored {
+            changes.push((utxo_key(oref), Some(out.to_cbor_bytes())));
+            self.utxos.insert(*oref, out.clone());
+        }
+        self.persist(changes, IndexTip::Unapplied(point));
+        restored
+    }
+
+    /// Forget outputs spent below the given height, returning hashes of the spending transactions.
+    fn forget_spent_below(&mut self, height: u64) -> Vec<TransactionHash> {
+        let retained = self.spent_at_height.split_off(&height);
+        let forgotten: Vec<_> = std::mem::replace(&mut self.spent_at_height, retained)
+            .into_values()
+            .flatten()
+            .collect();
+        for tx_hash in &forgotten {
+            self.spent.remove(tx_hash);
+        }
+        forgotten
+    }
+
+    /// Move the index to the `tip` persisting the `changes` leading to it in one batch.
+    fn persist(&mut self, mut changes: Vec<Change>, tip: IndexTip) {
+        self.tip = Some(tip);
+        if let Some(store) = &self.store {
+            changes.push((
+                TIP_KEY.as_bytes().to_vec(),
+                Some(bincode::serialize(&tip).unwrap()),
+            ));
+            store.write(changes);
+        }
+    }
+}
+
+const SCHEMA: Schema = Schema {
+    store: "utxo_index",
+    version: 1,
+    migrations: &[],
+};
+
+const UTXO_PREFIX: &str = "u:";
+const SPENT_PREFIX: &str = "s:";
+const TIP_KEY: &str = "tip";
+
+/// Key along with the value to put under it, the key is deleted if there is no value.
+type Change = (Vec<u8>, Option<Vec<u8>>);
+
+/// [SpentOutputs] as they are stored: output refs as keys of [UTXO_PREFIX] entries
+/// without the prefix, outputs as CBOR.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredSpentOutputs {
+    height: u64,
+    outputs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl SpentOutputs {
+    fn to_stored(&self) -> StoredSpentOutputs {
+        StoredSpentOutputs {
+            height: self.height,
+            outputs: self
+                .outputs
+                .iter()
+                .map(|(oref, out)| (output_ref_bytes(oref), out.to_cbor_bytes()))
+                .collect(),
+        }
+    }
+
+    fn try_from_stored(stored: StoredSpentOutputs) -> Option<Self> {
+        let outputs = stored
+            .outputs
+            .into_iter()
+            .map(|(oref, out)| output_ref_from_key(&oref).zip(TransactionOutput::from_cbor_bytes(&out).ok()))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            height: stored.height,
+            outputs,
+        })
+    }
+}
+
+fn output_ref_bytes(oref: &OutputRef) -> Vec<u8> {
+    let mut bytes = Vec::from(oref.tx_hash().to_raw_bytes());
+    bytes.extend_from_slice(&oref.index().to_be_bytes());
+    bytes
+}
+
+fn output_ref_from_key(key: &[u8]) -> Option<OutputRef> {
+    if key.len() != 40 {
+        return None;
+    }
+    let tx_hash = <[u8; 32]>::try_from(&key[..32]).ok()?;
+    let index = <[u8; 8]>::try_from(&key[32..]).ok()?;
+    Some(OutputRef::new(
+        TransactionHash::from(tx_hash),
+        u64::from_be_bytes(index),
+    ))
+}
+
+fn utxo_key(oref: &OutputRef) -> Vec<u8> {
+    let mut key = Vec::from(UTXO_PREFIX.as_bytes());
+    key.extend(output_ref_bytes(oref));
+    key
+}
+
+fn spent_key(tx_hash: &TransactionHash) -> Vec<u8> {
+    let mut key = Vec::from(SPENT_PREFIX.as_bytes());
+    key.extend_from_slice(tx_hash.to_raw_bytes());
+    key
+}
+
+/// Entries under the given prefix, keys are returned without the prefix.
+fn prefixed_entries(db: &RocksDB, cf: &impl AsColumnFamilyRef, prefix: &str) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut entries = Vec::new();
+    for item in db.iterator_cf(cf, IteratorMode::From(prefix.as_bytes(), Direction::Forward)) {
+        let (key, value) = item.unwrap();
+        if !key.starts_with(prefix.as_bytes()) {
+            break;
+        }
+        entries.push((key[prefix.len()..].to_vec(), value.to_vec()));
+    }
+    entries
+}
+
+/// Writes changes of the index to the store from a dedicated thread,
+/// so that the index is not held up by disk writes.
+struct IndexWriter {
+    changes: Option<mpsc::Sender<Vec<Change>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl IndexWriter {
+    fn spawn(db: Arc<RocksDB>, cf: String) -> Self {
+        let (changes, to_write) = mpsc::channel::<Vec<Change>>();
+        let worker = thread::spawn(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            for changes in to_write {
+                let mut batch = WriteBatch::default();
+                for (key, value) in changes {
+                    match value {
+                        Some(value) => batch.put_cf(&cf, key, value),
+                        None => batch.delete_cf(&cf, key),
+                    }
+                }
+                if let Err(err) = db.write(batch) {
+                    error!(target: "utxo_index", "Failed to persist UTxO index: {}", err);
+                }
+            }
+        });
+        Self {
+            changes: Some(changes),
+            worker: Some(worker),
+        }
+    }
+
+    fn write(&self, changes: Vec<Change>) {
+        if let Some(sender) = &self.changes {
+            if sender.send(changes).is_err() {
+                error!(target: "utxo_index", "Failed to persist UTxO index: writer is gone");
+            }
+        }
+    }
+}
+
+impl Drop for IndexWriter {
+    fn drop(&mut self) {
+        // Closing the channel lets the worker finish once pending changes are written.
+        self.changes.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Resolve tracked outputs consumed by transactions from the `upstream`,
+/// maintaining the `index` along the way.
+pub fn resolved_ledger_transactions<'a, S>(
+    mut index: UtxoIndex,
+    upstream: S,
+) -> impl Stream<Item = Result<LedgerTxEvent<ResolvedTx>, RollbackError>> + 'a
+where
+    S: Stream<Item = Result<LedgerTxEvent<LedgerTx>, RollbackError>> + 'a,
+{
+    upstream.map(move |event| {
+        event.map(|event| match event {
+            LedgerTxEvent::TxApplied {
+                tx,
+                tx_hash,
+                tx_ix,
+                block_hash,
+                block_height,
+                slot,
+            } => {
+                let consumed = index.apply(&tx, tx_hash, Point::Specific(slot, block_hash), block_height);
+                LedgerTxEvent::TxApplied {
+                    tx: ResolvedTx { tx, consumed },
+                    tx_hash,
+                    tx_ix,
+                    block_hash,
+                    block_height,
+                    slot,
+                }
+            }
+            LedgerTxEvent::TxUnapplied {
+                tx,
+                tx_hash,
+                tx_ix,
+                block_hash,
+                block_height,
+                slot,
+            } => {
+                let consumed = index.unapply(&tx, tx_hash, Point::Specific(slot, block_hash));
+                LedgerTxEvent::TxUnapplied {
+                    tx: ResolvedTx { tx, consumed },
+                    tx_hash,
+                    tx_ix,
+                    block_hash,
+                    block_height,
+                    slot,
+                }
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cml_chain::address::{Address, EnterpriseAddress};
+    use cml_chain::assets::MultiAsset;
+    use cml_chain::certs::StakeCredential;
+    use cml_chain::transaction::{TransactionInput, TransactionOutput, TransactionWitnessSet};
+    use cml_chain::{PolicyId, Value};
+    use cml_crypto::{Ed25519KeyHash, ScriptHash, TransactionHash};
+    use rand::{thread_rng, RngCore};
+
+    use spectrum_cardano_lib::OutputRef;
+    use spectrum_offchain::rocks::RocksConfig;
+
+    use crate::cache::tests::point;
+    use crate::multi_era::{LedgerTx, MultiEraWitnessSet};
+    use crate::utxo_index::{IndexTip, UtxoFilter, UtxoIndex};
+
+    const SCRIPT: [u8; 28] = [1u8; 28];
+
+    fn output(cred: StakeCredential) -> TransactionOutput {
+        TransactionOutput::new(
+            Address::Enterprise(EnterpriseAddress::new(0, cred)),
+            Value::new(1_000_000, MultiAsset::new()),
+            None,
+            None,
+        )
+    }
+
+    fn script_output() -> TransactionOutput {
+        output(StakeCredential::new_script(ScriptHash::from(SCRIPT)))
+    }
+
+    fn key_output() -> TransactionOutput {
+        output(StakeCredential::new_pub_key(Ed25519KeyHash::from([2u8; 28])))
+    }
+
+    fn tx(inputs: Vec<OutputRef>, outputs: Vec<TransactionOutput>) -> LedgerTx {
+        LedgerTx {
+            inputs: inputs.into_iter().map(TransactionInput::from).collect(),
+            reference_inputs: vec![],
+            outputs,
+            witness_set: MultiEraWitnessSet::Conway(TransactionWitnessSet::new()),
+            auxiliary_data: None,
+            body_bytes: vec![],
+        }
+    }
+
+    fn filter() -> UtxoFilter {
+        UtxoFilter {
+            script_hashes: [ScriptHash::from(SCRIPT)].into_iter().collect(),
+            policies: [PolicyId::from([3u8; 28])].into_iter().collect(),
+        }
+    }
+
+    fn index(rollback_depth: usize) -> UtxoIndex {
+        UtxoIndex::with_rollback_depth(filter(), rollback_depth)
+    }
+
+    #[test]
+    fn only_tracked_outputs_are_resolved() {
+        let mut index = index(10);
+        let h1 = TransactionHash::from([1u8; 32]);
+        let h2 = TransactionHash::from([2u8; 32]);
+        index.apply(&tx(vec![], vec![key_output(), script_output()]), h1, point(1), 1);
+        assert!(index.get(&OutputRef::new(h1, 0)).is_none());
+        let consumed = index.apply(
+            &tx(vec![OutputRef::new(h1, 0), OutputRef::new(h1, 1)], vec![]),
+            h2,
+            point(2),
+            2,
+        );
+        assert_eq!(consumed.len(), 1);
+        assert_eq!(consumed[0].0, OutputRef::new(h1, 1));
+        assert!(index.get(&OutputRef::new(h1, 1)).is_none());
+    }
+
+    #[test]
+    fn spent_outputs_are_restored_on_rollback() {
+        let mut index = index(10);
+        let h1 = TransactionHash::from([1u8; 32]);
+        let h2 = TransactionHash::from([2u8; 32]);
+        let spending_tx = tx(vec![OutputRef::new(h1, 0)], vec![script_output()]);
+        index.apply(&tx(vec![], vec![script_output()]), h1, point(1), 1);
+        index.apply(&spending_tx, h2, point(2), 2);
+        let restored = index.unapply(&spending_tx, h2, point(2));
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].0, OutputRef::new(h1, 0));
+        assert!(index.get(&OutputRef::new(h1, 0)).is_some());
+        assert!(index.get(&OutputRef::new(h2, 0)).is_none());
+    }
+
+    #[test]
+    fn spent_outputs_beyond_rollback_depth_are_forgotten() {
+        let mut index = index(2);
+        let h1 = TransactionHash::from([1u8; 32]);
+        let h2 = TransactionHash::from([2u8; 32]);
+        let h3 = TransactionHash::from([3u8; 32]);
+        let spending_tx = tx(vec![OutputRef::new(h1, 0)], vec![]);
+        index.apply(&tx(vec![], vec![script_output()]), h1, point(1), 1);
+        index.apply(&spending_tx, h2, point(2), 2);
+        index.apply(&tx(vec![], vec![]), h3, point(5), 5);
+        assert!(index.unapply(&spending_tx, h2, point(2)).is_empty());
+        assert!(index.get(&OutputRef::new(h1, 0)).is_none());
+    }
+
+    #[test]
+    fn unapplied_tx_is_forgotten_at_its_height() {
+        let mut index = index(10);
+        let h1 = TransactionHash::from([1u8; 32]);
+        let h2 = TransactionHash::from([2u8; 32]);
+        let spending_tx = tx(vec![OutputRef::new(h1, 0)], vec![]);
+        index.apply(&tx(vec![], vec![script_output()]), h1, point(1), 1);
+        index.apply(&spending_tx, h2, point(2), 2);
+        index.unapply(&spending_tx, h2, point(2));
+        assert!(index.spent.is_empty());
+        assert!(index.spent_at_height.is_empty());
+    }
+
+    #[test]
+    fn persistent_index_is_restored_on_startup() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let h1 = TransactionHash::from([1u8; 32]);
+        let h2 = TransactionHash::from([2u8; 32]);
+        let spending_tx = tx(vec![OutputRef::new(h1, 0)], vec![script_output()]);
+        {
+            let mut index = UtxoIndex::persistent(filter(), 10, conf.clone());
+            index.apply(
+                &tx(vec![], vec![script_output(), script_output()]),
+                h1,
+                point(1),
+                1,
+            );
+            index.apply(&spending_tx, h2, point(2), 2);
+        }
+        let mut index = UtxoIndex::persistent(filter(), 10, conf);
+        assert!(index.get(&OutputRef::new(h1, 0)).is_none());
+        assert!(index.get(&OutputRef::new(h1, 1)).is_some());
+        assert!(index.get(&OutputRef::new(h2, 0)).is_some());
+        let restored = index.unapply(&spending_tx, h2, point(2));
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].0, OutputRef::new(h1, 0));
+        assert!(index.get(&OutputRef::new(h1, 0)).is_some());
+        assert!(index.get(&OutputRef::new(h2, 0)).is_none());
+    }
+
+    #[test]
+    fn tip_is_restored_on_startup() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let h1 = TransactionHash::from([1u8; 32]);
+        let h2 = TransactionHash::from([2u8; 32]);
+        let spending_tx = tx(vec![OutputRef::new(h1, 0)], vec![]);
+        {
+            let mut index = UtxoIndex::persistent(filter(), 10, conf.clone());
+            index.apply(&tx(vec![], vec![script_output()]), h1, point(1), 1);
+            index.apply(&spending_tx, h2, point(2), 2);
+        }
+        {
+            let mut index = UtxoIndex::persistent(filter(), 10, conf.clone());
+            assert_eq!(index.tip(), Some(IndexTip::Applied(point(2))));
+            assert!(index.check_tip(Some(point(2)), None).is_ok());
+            index.unapply(&spending_tx, h2, point(2));
+        }
+        let index = UtxoIndex::persistent(filter(), 10, conf);
+        assert_eq!(index.tip(), Some(IndexTip::Unapplied(point(2))));
+        assert!(index.check_tip(Some(point(1)), None).is_ok());
+    }
+
+    #[test]
+    fn index_behind_or_ahead_of_chain_sync_is_out_of_sync() {
+        let mut index = index(10);
+        index.apply(&tx(vec![], vec![]), TransactionHash::from([1u8; 32]), point(5), 5);
+        assert!(index.check_tip(Some(point(6)), None).is_err());
+        assert!(index.check_tip(Some(point(4)), None).is_err());
+        assert!(index.check_tip(Some(point(6)), Some(point(6))).is_err());
+        assert!(index.check_tip(Some(point(6)), Some(point(5))).is_ok());
+        assert!(index.check_tip(Some(point(6)), Some(point(3))).is_ok());
+    }
+}
@@ -56,25 +56,31 @@ impl<Tx> LocalTxMonitorClient<Tx> {
         })
     }
 
+    /// Stream transactions accepted to the mempool.
+    /// The stream ends once the connection to the node is lost.
     pub fn stream_updates<'a>(&'a self) -> impl Stream<Item = MempoolUpdate<Tx>> + 'a
     where
         Tx: Deserialize,
     {
         stream! {
-            loop {
+            'session: loop {
                 let mut tx_monitor = self.tx_monitor.lock().await;
-                if let Ok(_) = tx_monitor.client.acquire().await {
-                    loop {
-                        if let Ok(Some(raw_tx)) = tx_monitor.client.query_next_tx().await {
+                if tx_monitor.client.acquire().await.is_err() {
+                    break;
+                }
+                loop {
+                    match tx_monitor.client.query_next_tx().await {
+                        Ok(Some(raw_tx)) => {
                             let bytes = &*raw_tx.1;
                             if !tx_monitor.filter.register(hash_tx_bytes(bytes)) {
                                 if let Ok(tx) = Tx::from_cbor_bytes(bytes) {
                                     yield MempoolUpdate::TxAccepted(tx);
                                 }
                             }
-                        } else {
-                            break;
                         }
+                        // Snapshot is exhausted, acquire the next one.
+                        Ok(None) => break,
+                        Err(_) => break 'session,
                     }
                 }
             }
@@ -1,9 +1,14 @@
+use std::future::Future;
+
+use async_stream::stream;
 use cml_core::serialization::Deserialize;
-use futures::FutureExt;
 use futures::Stream;
+use futures::{pin_mut, FutureExt, StreamExt};
 use tokio::sync::broadcast;
 
-use crate::client::LocalTxMonitorClient;
+use spectrum_offchain::reconnect::{connect_with_backoff, Backoff, BackoffConfig};
+
+use crate::client::{Error, LocalTxMonitorClient};
 use crate::data::MempoolUpdate;
 
 pub mod client;
@@ -21,3 +26,35 @@ where
     };
     wait_signal.map(move |_| client.stream_updates()).flatten_stream()
 }
+
+/// Stream mempool updates reconnecting to the node with backoff whenever the connection is lost.
+/// Transactions which are still in the mempool are reported again after reconnection.
+/// The backoff is reset only once a session yields an update, so that sessions
+/// which break right after connecting are retried with growing delays.
+pub fn supervised_mempool_stream<'a, Tx, Connect, Fut>(
+    mut connect: Connect,
+    conf: BackoffConfig,
+    mut tip_reached_signal: broadcast::Receiver<bool>,
+) -> impl Stream<Item = MempoolUpdate<Tx>> + 'a
+where
+    Tx: Deserialize + 'a,
+    Connect: FnMut() -> Fut + 'a,
+    Fut: Future<Output = Result<LocalTxMonitorClient<Tx>, Error>> + 'a,
+{
+    stream! {
+        let _ = tip_reached_signal.recv().await;
+        let mut backoff = Backoff::new(conf);
+        loop {
+            let client = connect_with_backoff(&mut connect, &mut backoff).await;
+            {
+                let updates = client.stream_updates();
+                pin_mut!(updates);
+                while let Some(upd) = updates.next().await {
+                    backoff.reset();
+                    yield upd;
+                }
+            }
+            client.close().await;
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::future::Future;
 use std::io::Read;
 use std::marker::PhantomData;
 use std::path::Path;
@@ -5,7 +6,7 @@ use std::path::Path;
 use cml_chain::crypto::hash::hash_transaction;
 use cml_core::serialization::Serialize;
 use cml_crypto::blake2b256;
-use log::trace;
+use log::{trace, warn};
 use pallas_network::miniprotocols::handshake::RefuseReason;
 use pallas_network::miniprotocols::localtxsubmission::{EraTx, RejectReason};
 use pallas_network::miniprotocols::{
@@ -14,6 +15,8 @@ use pallas_network::miniprotocols::{
 use pallas_network::multiplexer;
 use pallas_network::multiplexer::{Bearer, RunningPlexer};
 
+use spectrum_offchain::reconnect::{BackoffConfig, Supervised};
+
 pub struct LocalTxSubmissionClient<const ERA: u16, Tx> {
     plexer: RunningPlexer,
     tx_submission: localtxsubmission::Client,
@@ -78,6 +81,47 @@ impl<const ERA: u16, Tx> LocalTxSubmissionClient<ERA, Tx> {
     }
 }
 
+/// Tx submission client which is rebuilt with backoff once the connection to the node is lost.
+pub struct SupervisedTxSubmissionClient<const ERA: u16, Tx, Connect> {
+    client: Supervised<LocalTxSubmissionClient<ERA, Tx>, Connect>,
+}
+
+impl<const ERA: u16, Tx, Connect, Fut> SupervisedTxSubmissionClient<ERA, Tx, Connect>
+where
+    Connect: FnMut() -> Fut,
+    Fut: Future<Output = Result<LocalTxSubmissionClient<ERA, Tx>, Error>>,
+{
+    pub fn new(connect: Connect, conf: BackoffConfig) -> Self {
+        Self {
+            client: Supervised::new(connect, conf),
+        }
+    }
+
+    /// Submit the transaction. If the connection turns out to be broken,
+    /// the submission is retried once over a new connection.
+    pub async fn submit_tx(&mut self, tx: Tx) -> Result<(), Error>
+    where
+        Tx: Serialize + Clone,
+    {
+        let mut retried = false;
+        loop {
+            match self.client.get().await.submit_tx(tx.clone()).await {
+                Err(err) if !err.is_rejection() => {
+                    warn!("Tx submission failed: {}, reconnecting", err);
+                    if let Some(client) = self.client.reset() {
+                        client.close().await;
+                    }
+                    if retried {
+                        return Err(err);
+                    }
+                    retried = true;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("error connecting bearer")]
@@ -92,3 +136,13 @@ pub enum Error {
     #[error("handshake version not accepted")]
     HandshakeRefused(RefuseReason),
 }
+
+impl Error {
+    /// Whether the transaction was rejected by the node, as opposed to a failure of the connection.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            Error::TxSubmissionProtocol(localtxsubmission::Error::TxRejected(_))
+        )
+    }
+}
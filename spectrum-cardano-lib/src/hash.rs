@@ -2,7 +2,12 @@ use cml_core::serialization::Serialize;
 use cml_crypto::{blake2b256, BlockHeaderHash, TransactionHash};
 
 pub fn hash_transaction_canonical<TxBody: Serialize>(tx_body: &TxBody) -> TransactionHash {
-    TransactionHash::from(blake2b256(tx_body.to_cbor_bytes().as_ref()))
+    hash_transaction_bytes(tx_body.to_cbor_bytes().as_ref())
+}
+
+/// Hash of a transaction given its body already serialized in the original encoding.
+pub fn hash_transaction_bytes(tx_body_bytes: &[u8]) -> TransactionHash {
+    TransactionHash::from(blake2b256(tx_body_bytes))
 }
 
 pub fn hash_block_header_canonical<Header: Serialize>(header: &Header) -> BlockHeaderHash {
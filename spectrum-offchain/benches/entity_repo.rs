@@ -0,0 +1,102 @@
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use derive_more::Display;
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+
+use spectrum_offchain::box_resolver::persistence::cached::CachedEntityRepo;
+use spectrum_offchain::box_resolver::persistence::rocksdb::EntityRepoRocksDB;
+use spectrum_offchain::box_resolver::persistence::EntityRepo;
+use spectrum_offchain::box_resolver::resolve_entity_state;
+use spectrum_offchain::data::event::{Confirmed, Predicted, Traced};
+use spectrum_offchain::data::{EntitySnapshot, Stable};
+use spectrum_offchain::rocks::RocksConfig;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+struct PoolId(u64);
+
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+struct PoolVersion(u64);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Pool {
+    id: PoolId,
+    version: PoolVersion,
+}
+
+impl Stable for Pool {
+    type StableId = PoolId;
+    fn stable_id(&self) -> Self::StableId {
+        self.id
+    }
+    fn is_quasi_permanent(&self) -> bool {
+        true
+    }
+}
+
+impl EntitySnapshot for Pool {
+    type Version = PoolVersion;
+    fn version(&self) -> Self::Version {
+        self.version
+    }
+}
+
+const NUM_POOLS: u64 = 100;
+const PREDICTION_DEPTH: u64 = 4;
+
+/// Every pool gets a confirmed state followed by a chain of predicted states.
+async fn populate<R: EntityRepo<Pool>>(repo: &mut R) {
+    for i in 0..NUM_POOLS {
+        let id = PoolId(i);
+        let base = i * (PREDICTION_DEPTH + 1);
+        repo.put_confirmed(Confirmed(Pool {
+            id,
+            version: PoolVersion(base),
+        }))
+        .await;
+        for j in 1..=PREDICTION_DEPTH {
+            repo.put_predicted(Traced::new(
+                Predicted(Pool {
+                    id,
+                    version: PoolVersion(base + j),
+                }),
+                Some(PoolVersion(base + j - 1)),
+            ))
+            .await;
+        }
+    }
+}
+
+fn rocks_db_repo() -> EntityRepoRocksDB {
+    let rnd = thread_rng().next_u32();
+    EntityRepoRocksDB::new(RocksConfig::new(format!("./tmp/bench/{}", rnd)))
+}
+
+fn bench_resolve<R: EntityRepo<Pool>>(c: &mut Criterion, name: &str, rt: &Runtime, mut repo: R) {
+    rt.block_on(populate(&mut repo));
+    let repo = Arc::new(Mutex::new(repo));
+    c.bench_function(name, |b| {
+        b.to_async(rt).iter(|| {
+            let repo = Arc::clone(&repo);
+            async move {
+                for i in 0..NUM_POOLS {
+                    resolve_entity_state::<Pool, _>(PoolId(i), Arc::clone(&repo)).await;
+                }
+            }
+        })
+    });
+}
+
+fn resolve_entity_state_benchmark(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    bench_resolve(c, "resolve_entity_state/rocksdb", &rt, rocks_db_repo());
+    let cached = CachedEntityRepo::wrap(rocks_db_repo(), NonZeroUsize::new(1024).unwrap());
+    bench_resolve(c, "resolve_entity_state/cached_rocksdb", &rt, cached);
+}
+
+criterion_group!(benches, resolve_entity_state_benchmark);
+criterion_main!(benches);
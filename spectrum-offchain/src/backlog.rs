@@ -44,6 +44,11 @@ where
     fn remove<'a>(&mut self, ord_id: TOrd::TOrderId)
     where
         TOrd::TOrderId: 'a + Clone;
+    /// Check if order with the given id was removed since it was last put into backlog,
+    /// e.g. while it was held aside by an executor.
+    fn is_removed<'a>(&self, ord_id: TOrd::TOrderId) -> bool
+    where
+        TOrd::TOrderId: 'a;
     /// Check order later.
     fn soft_evict<'a>(&mut self, ord: TOrd::TOrderId)
     where
@@ -58,6 +63,8 @@ pub struct HotPriorityBacklog<TOrd: UniqueOrder> {
     queue: PriorityQueue<TOrd::TOrderId, OrderWeight>,
     store: HashMap<TOrd::TOrderId, TOrd>,
     soft_evicted_orders: CircularFilter<256, TOrd::TOrderId>,
+    /// Recently removed orders. Removals of orders put back since then are forgotten.
+    removed_orders: CircularFilter<256, TOrd::TOrderId>,
     capacity: u32,
 }
 
@@ -67,6 +74,7 @@ impl<TOrd: UniqueOrder> HotPriorityBacklog<TOrd> {
             queue: PriorityQueue::new(),
             store: HashMap::new(),
             soft_evicted_orders: CircularFilter::new(),
+            removed_orders: CircularFilter::new(),
             capacity: capacity.into(),
         }
     }
@@ -92,6 +100,7 @@ where
         TOrd: 'a,
     {
         let id = ord.get_self_ref();
+        self.removed_orders.remove(&id);
         if self.capacity > 0 && !self.store.contains_key(&id) && !self.soft_evicted_orders.contains(&id) {
             let wt = ord.weight();
             self.queue.push(id, wt);
@@ -122,11 +131,19 @@ where
         TOrd::TOrderId: 'a + Clone,
     {
         self.soft_evicted_orders.remove(&ord);
+        self.removed_orders.add(ord);
         if self.store.remove(&ord).is_some() {
             self.capacity += 1;
         }
     }
 
+    fn is_removed<'a>(&self, ord_id: TOrd::TOrderId) -> bool
+    where
+        TOrd::TOrderId: 'a,
+    {
+        self.removed_orders.contains(&ord_id)
+    }
+
     fn soft_evict<'a>(&mut self, ord: TOrd::TOrderId)
     where
         TOrd: 'a,
@@ -575,6 +592,7 @@ mod tests {
     use crate::backlog::persistence::{BacklogStore, BacklogStoreRocksDB};
     use crate::backlog::{BacklogConfig, PersistentPriorityBacklog, ResilientBacklog};
     use crate::data::order::{PendingOrder, ProgressingOrder, SuspendedOrder, UniqueOrder};
+    use crate::rocks::RocksConfig;
 
     #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Copy, Serialize, Deserialize)]
     struct MockOrderId(i64);
@@ -788,9 +806,7 @@ mod tests {
     #[tokio::test]
     async fn test_rocksdb_backlog() {
         let rnd = rand::thread_rng().next_u32();
-        let mut store = BacklogStoreRocksDB {
-            db: Arc::new(rocksdb::OptimisticTransactionDB::open_default(format!("./tmp/{}", rnd)).unwrap()),
-        };
+        let mut store = BacklogStoreRocksDB::new(RocksConfig::new(format!("./tmp/{}", rnd)));
         for i in 0..30 {
             store.put(make_order(i, i as u64)).await;
         }
@@ -7,7 +7,9 @@ use serde::Serialize;
 
 use crate::backlog::data::BacklogOrder;
 use crate::data::order::UniqueOrder;
-use crate::rocks::RocksConfig;
+use crate::rocks;
+use crate::rocks::schema::{check, is_schema_key, migrate, Schema};
+use crate::rocks::{RocksConfig, RocksDB};
 
 #[async_trait]
 pub trait BacklogStore<TOrd>
@@ -24,14 +26,33 @@ where
 }
 
 pub struct BacklogStoreRocksDB {
-    pub db: Arc<rocksdb::OptimisticTransactionDB>,
+    pub db: Arc<RocksDB>,
+    /// Column family the orders are kept in.
+    pub cf: String,
 }
 
+const SCHEMA: Schema = Schema {
+    store: "backlog",
+    version: 1,
+    migrations: &[],
+};
+
 impl BacklogStoreRocksDB {
     pub fn new(conf: RocksConfig) -> Self {
-        Self {
-            db: Arc::new(rocksdb::OptimisticTransactionDB::open_default(conf.db_path).unwrap()),
-        }
+        let db = rocks::open(&conf);
+        let cf = conf.column_family().to_string();
+        migrate(&db, &cf, &SCHEMA).unwrap();
+        Self { db, cf }
+    }
+
+    /// Open read-only secondary instance of the store which may be in use by another process.
+    /// Writes through a secondary instance fail.
+    /// Run [rocks::catch_up_stream] on `db` to observe updates made by the primary instance.
+    pub fn open_as_secondary(conf: RocksConfig, secondary_path: &str) -> Self {
+        let db = rocks::open_as_secondary(&conf, secondary_path);
+        let cf = conf.column_family().to_string();
+        check(&db, &cf, &SCHEMA).unwrap();
+        Self { db, cf }
     }
 }
 
@@ -43,8 +64,11 @@ where
 {
     async fn put(&self, ord: BacklogOrder<TOrd>) {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         spawn_blocking(move || {
-            db.put(
+            let cf = db.cf_handle(&cf).unwrap();
+            db.put_cf(
+                &cf,
                 bincode::serialize(&ord.order.get_self_ref()).unwrap(),
                 bincode::serialize(&ord).unwrap(),
             )
@@ -54,18 +78,32 @@ where
     }
     async fn exists(&self, ord_id: TOrd::TOrderId) -> bool {
         let db = self.db.clone();
-        spawn_blocking(move || db.get(bincode::serialize(&ord_id).unwrap()).unwrap().is_some()).await
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, bincode::serialize(&ord_id).unwrap())
+                .unwrap()
+                .is_some()
+        })
+        .await
     }
 
     async fn remove(&self, ord_id: TOrd::TOrderId) {
         let db = self.db.clone();
-        spawn_blocking(move || db.delete(bincode::serialize(&ord_id).unwrap()).unwrap()).await;
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            db.delete_cf(&cf, bincode::serialize(&ord_id).unwrap()).unwrap()
+        })
+        .await;
     }
 
     async fn get(&self, ord_id: TOrd::TOrderId) -> Option<BacklogOrder<TOrd>> {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         spawn_blocking(move || {
-            db.get(bincode::serialize(&ord_id).unwrap())
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, bincode::serialize(&ord_id).unwrap())
                 .unwrap()
                 .map(|b| bincode::deserialize(&b).unwrap())
         })
@@ -77,10 +115,15 @@ where
         F: Fn(&TOrd) -> bool + Send + 'static,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         spawn_blocking(move || {
-            db.iterator(rocksdb::IteratorMode::Start)
+            let cf = db.cf_handle(&cf).unwrap();
+            db.iterator_cf(&cf, rocksdb::IteratorMode::Start)
                 .filter_map(|i| {
-                    let (_, v) = i.unwrap();
+                    let (k, v) = i.unwrap();
+                    if is_schema_key(&k) {
+                        return None;
+                    }
                     if let Ok(b) = bincode::deserialize::<BacklogOrder<TOrd>>(&v) {
                         if f(&b.order) {
                             return Some(b);
@@ -0,0 +1,145 @@
+use std::f64::consts::LN_2;
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use crate::partitioning::SipHasher24;
+
+/// Keys of the second hash function, the first one uses [SipHasher24::DEFAULT_KEYS].
+const SECOND_HASH_KEYS: (u64, u64) = (0x1716151413121110, 0x1f1e1d1c1b1a1918);
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct BloomFilterConfig {
+    /// Number of items the filter is sized for.
+    pub expected_items: usize,
+    /// Target false-positive rate once `expected_items` are in the filter.
+    pub false_positive_rate: f64,
+}
+
+impl Default for BloomFilterConfig {
+    fn default() -> Self {
+        Self {
+            expected_items: 1_000_000,
+            false_positive_rate: 0.01,
+        }
+    }
+}
+
+/// Bloom filter over a bit array. Items can't be removed.
+#[derive(Clone, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    num_items: usize,
+}
+
+impl BloomFilter {
+    pub fn new(conf: BloomFilterConfig) -> Self {
+        let n = conf.expected_items.max(1) as f64;
+        let p = conf.false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let num_bits = (-n * p.ln() / (LN_2 * LN_2)).ceil().max(1.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * LN_2).round().max(1.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+            num_items: 0,
+        }
+    }
+
+    /// Insert an item. Returns `false` if the item may have been inserted already,
+    /// such items are not counted again.
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) -> bool {
+        let mut is_new = false;
+        for ix in self.indexes(item) {
+            let (word, mask) = (ix / 64, 1u64 << (ix % 64));
+            is_new |= self.bits[word] & mask == 0;
+            self.bits[word] |= mask;
+        }
+        if is_new {
+            self.num_items += 1;
+        }
+        is_new
+    }
+
+    /// Check whether the item may be in the filter. Never gives false negatives.
+    pub fn may_contain<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        self.indexes(item)
+            .all(|ix| self.bits[ix / 64] & (1u64 << (ix % 64)) != 0)
+    }
+
+    /// Number of distinct items in the filter.
+    pub fn len(&self) -> usize {
+        self.num_items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_items == 0
+    }
+
+    /// Estimated false-positive rate for the current number of items.
+    pub fn false_positive_rate(&self) -> f64 {
+        let k = self.num_hashes as f64;
+        let fill = -k * self.num_items as f64 / self.num_bits as f64;
+        (1.0 - fill.exp()).powf(k)
+    }
+
+    /// Positions of the item's bits derived from two hashes (Kirsch-Mitzenmacher).
+    fn indexes<T: Hash + ?Sized>(&self, item: &T) -> impl Iterator<Item = usize> {
+        let mut h1 = SipHasher24::default();
+        item.hash(&mut h1);
+        let h1 = h1.finish();
+        let mut h2 = SipHasher24::new_with_keys(SECOND_HASH_KEYS.0, SECOND_HASH_KEYS.1);
+        item.hash(&mut h2);
+        let h2 = h2.finish();
+        let m = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bloom_filter::{BloomFilter, BloomFilterConfig};
+
+    fn filter(expected_items: usize) -> BloomFilter {
+        BloomFilter::new(BloomFilterConfig {
+            expected_items,
+            false_positive_rate: 0.01,
+        })
+    }
+
+    #[test]
+    fn inserted_items_are_found() {
+        let mut filter = filter(1000);
+        for i in 0..1000u64 {
+            filter.insert(&i);
+        }
+        assert!((0..1000u64).all(|i| filter.may_contain(&i)));
+        assert_eq!(filter.len(), 1000);
+    }
+
+    #[test]
+    fn false_positive_rate_is_close_to_configured() {
+        let mut filter = filter(1000);
+        for i in 0..1000u64 {
+            filter.insert(&i);
+        }
+        let false_positives = (1000..11000u64).filter(|i| filter.may_contain(i)).count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+        let estimated = filter.false_positive_rate();
+        assert!(
+            estimated > 0.005 && estimated < 0.02,
+            "estimated rate {}",
+            estimated
+        );
+    }
+
+    #[test]
+    fn repeated_items_are_counted_once() {
+        let mut filter = filter(1000);
+        assert!(filter.insert(&1u64));
+        assert!(!filter.insert(&1u64));
+        assert_eq!(filter.len(), 1);
+    }
+}
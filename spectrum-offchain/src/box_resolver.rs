@@ -1,16 +1,29 @@
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 
+use log::{trace, warn};
 use tokio::sync::Mutex;
 
-use crate::box_resolver::persistence::EntityRepo;
-use crate::data::event::{Confirmed, Predicted, Traced, Unconfirmed};
+use crate::box_resolver::persistence::{unix_time_millis, EntityRepo};
+use crate::data::event::{AnyMod, Predicted, Traced, Unconfirmed};
 use crate::data::EntitySnapshot;
 
 pub mod blacklist;
+pub mod history;
+pub mod pair_index;
 pub mod persistence;
 pub mod process;
 
+/// Max number of prediction links followed while resolving an entity state.
+pub const MAX_PREDICTION_DEPTH: usize = 1024;
+
+/// Default time after which unconfirmed and predicted states which didn't make it to the ledger are discarded.
+pub const DEFAULT_STATE_TTL: Duration = Duration::from_secs(600);
+
 /// Get latest state of an on-chain entity `TEntity`.
+/// Unconfirmed and predicted states older than [DEFAULT_STATE_TTL] are discarded,
+/// so resolving may write to the `repo`.
 pub async fn resolve_entity_state<TEntity, TRepo>(
     id: TEntity::StableId,
     repo: Arc<Mutex<TRepo>>,
@@ -20,64 +33,338 @@ where
     TEntity: EntitySnapshot,
     TEntity::StableId: Copy,
 {
-    let states = {
-        let repo_guard = repo.lock().await;
-        let confirmed = repo_guard.get_last_confirmed(id).await;
-        let unconfirmed = repo_guard.get_last_unconfirmed(id).await;
-        let predicted = repo_guard.get_last_predicted(id).await;
-        (confirmed, unconfirmed, predicted)
-    };
-    match states {
-        (Some(Confirmed(conf)), unconf, Some(Predicted(pred))) => {
-            let anchoring_point = unconf.map(|Unconfirmed(e)| e).unwrap_or(conf);
-            let anchoring_sid = anchoring_point.version();
+    resolve_entity_state_with_ttl(id, DEFAULT_STATE_TTL, repo).await
+}
+
+/// Get latest state of an on-chain entity `TEntity`.
+/// Unconfirmed and predicted states older than `ttl` are discarded.
+pub async fn resolve_entity_state_with_ttl<TEntity, TRepo>(
+    id: TEntity::StableId,
+    ttl: Duration,
+    repo: Arc<Mutex<TRepo>>,
+) -> Option<TEntity>
+where
+    TRepo: EntityRepo<TEntity>,
+    TEntity: EntitySnapshot,
+    TEntity::StableId: Copy,
+{
+    resolve_entity_state_traced_with_ttl(id, ttl, repo)
+        .await
+        .map(|resolved| resolved.state.erased())
+}
+
+/// Latest state of an on-chain entity along with its provenance.
+#[derive(Clone)]
+pub struct ResolvedState<TEntity: EntitySnapshot> {
+    /// Resolved state in the modality it was obtained from.
+    pub state: AnyMod<TEntity>,
+    /// Versions from the anchoring point (last confirmed or unconfirmed state)
+    /// to the resolved state inclusive.
+    pub chain: Vec<TEntity::Version>,
+}
+
+impl<TEntity: EntitySnapshot> ResolvedState<TEntity> {
+    fn anchored(state: AnyMod<TEntity>) -> Self {
+        let chain = vec![state.as_erased().version()];
+        Self { state, chain }
+    }
+
+    /// Number of predicted states on top of the anchoring point.
+    pub fn depth(&self) -> usize {
+        self.chain.len().saturating_sub(1)
+    }
+}
+
+/// Get latest state of an on-chain entity `TEntity` along with its modality
+/// and the chain of versions leading to it.
+/// Unconfirmed and predicted states older than [DEFAULT_STATE_TTL] are discarded,
+/// so resolving may write to the `repo`.
+pub async fn resolve_entity_state_traced<TEntity, TRepo>(
+    id: TEntity::StableId,
+    repo: Arc<Mutex<TRepo>>,
+) -> Option<ResolvedState<TEntity>>
+where
+    TRepo: EntityRepo<TEntity>,
+    TEntity: EntitySnapshot,
+    TEntity::StableId: Copy,
+{
+    resolve_entity_state_traced_with_ttl(id, DEFAULT_STATE_TTL, repo).await
+}
+
+/// Get latest state of an on-chain entity `TEntity` along with its modality
+/// and the chain of versions leading to it.
+/// Unconfirmed and predicted states older than `ttl` are discarded.
+pub async fn resolve_entity_state_traced_with_ttl<TEntity, TRepo>(
+    id: TEntity::StableId,
+    ttl: Duration,
+    repo: Arc<Mutex<TRepo>>,
+) -> Option<ResolvedState<TEntity>>
+where
+    TRepo: EntityRepo<TEntity>,
+    TEntity: EntitySnapshot,
+    TEntity::StableId: Copy,
+{
+    let mut repo = repo.lock().await;
+    resolve_locked(id, ttl, &mut *repo).await
+}
+
+/// Get latest states of several on-chain entities `TEntity`, e.g. all entities an order touches.
+/// States are resolved while holding the lock on the `repo`, so that they form a consistent set.
+/// States are returned in the order of `ids`. Returns `None` if any of the entities is not found.
+/// Unconfirmed and predicted states older than [DEFAULT_STATE_TTL] are discarded,
+/// so resolving may write to the `repo`.
+pub async fn resolve_entity_states<TEntity, TRepo>(
+    ids: Vec<TEntity::StableId>,
+    repo: Arc<Mutex<TRepo>>,
+) -> Option<Vec<TEntity>>
+where
+    TRepo: EntityRepo<TEntity>,
+    TEntity: EntitySnapshot,
+    TEntity::StableId: Copy,
+{
+    resolve_entity_states_with_ttl(ids, DEFAULT_STATE_TTL, repo).await
+}
+
+/// Get latest states of several on-chain entities `TEntity` as [resolve_entity_states] does.
+/// Unconfirmed and predicted states older than `ttl` are discarded.
+pub async fn resolve_entity_states_with_ttl<TEntity, TRepo>(
+    ids: Vec<TEntity::StableId>,
+    ttl: Duration,
+    repo: Arc<Mutex<TRepo>>,
+) -> Option<Vec<TEntity>>
+where
+    TRepo: EntityRepo<TEntity>,
+    TEntity: EntitySnapshot,
+    TEntity::StableId: Copy,
+{
+    let mut repo = repo.lock().await;
+    let mut states = Vec::with_capacity(ids.len());
+    for id in ids {
+        let resolved = resolve_locked(id, ttl, &mut *repo).await?;
+        states.push(resolved.state.erased());
+    }
+    Some(states)
+}
+
+async fn resolve_locked<TEntity, TRepo>(
+    id: TEntity::StableId,
+    ttl: Duration,
+    repo: &mut TRepo,
+) -> Option<ResolvedState<TEntity>>
+where
+    TRepo: EntityRepo<TEntity>,
+    TEntity: EntitySnapshot,
+    TEntity::StableId: Copy,
+{
+    discard_expired_states_locked(id, ttl, repo).await;
+    let confirmed = repo.get_last_confirmed(id).await;
+    let unconfirmed = repo.get_last_unconfirmed(id).await;
+    let predicted = repo.get_last_predicted(id).await;
+    match (confirmed, unconfirmed, predicted) {
+        (Some(conf), unconf, Some(Predicted(pred))) => {
+            let anchoring_point = unconf.map(AnyMod::Unconfirmed).unwrap_or(AnyMod::Confirmed(conf));
+            let anchoring_sid = anchoring_point.as_erased().version();
             let predicted_sid = pred.version();
-            let prediction_is_anchoring_point = predicted_sid == anchoring_sid;
-            let prediction_is_valid = prediction_is_anchoring_point
-                || is_linking(predicted_sid, anchoring_sid, Arc::clone(&repo)).await;
-            let safe_point = if prediction_is_valid {
-                pred
-            } else {
-                anchoring_point
-            };
-            Some(safe_point)
+            if predicted_sid == anchoring_sid {
+                trace!(
+                    target: "box_resolver",
+                    "resolve({}): prediction {} is the anchoring point",
+                    id,
+                    predicted_sid
+                );
+                return Some(ResolvedState::anchored(anchoring_point));
+            }
+            let link =
+                trace_prediction_link_locked(id, predicted_sid, anchoring_sid, MAX_PREDICTION_DEPTH, repo)
+                    .await;
+            match link {
+                PredictionLink::Linked(chain) => {
+                    trace!(
+                        target: "box_resolver",
+                        "resolve({}): prediction {} is linked to {} at depth {}",
+                        id,
+                        predicted_sid,
+                        anchoring_sid,
+                        chain.len() - 1
+                    );
+                    let prev_state_id = chain.get(chain.len() - 2).copied();
+                    Some(ResolvedState {
+                        state: AnyMod::Predicted(Traced::new(Predicted(pred), prev_state_id)),
+                        chain,
+                    })
+                }
+                _ => {
+                    trace!(
+                        target: "box_resolver",
+                        "resolve({}): prediction {} is not linked to {}, falling back",
+                        id,
+                        predicted_sid,
+                        anchoring_sid
+                    );
+                    Some(ResolvedState::anchored(anchoring_point))
+                }
+            }
         }
-        (_, Some(Unconfirmed(unconf)), None) => Some(unconf),
-        (Some(Confirmed(conf)), _, _) => Some(conf),
+        (_, Some(unconf), None) => Some(ResolvedState::anchored(AnyMod::Unconfirmed(unconf))),
+        (Some(conf), _, _) => Some(ResolvedState::anchored(AnyMod::Confirmed(conf))),
         _ => None,
     }
 }
 
-async fn is_linking<TEntity, TRepo>(
+/// Discard last unconfirmed and last predicted states of entity `eid` observed `ttl` or more ago,
+/// so that they are not preferred over the confirmed state forever if their transactions
+/// never make it to the ledger. States with unknown observation time are considered expired.
+/// Returns `true` if any state was discarded.
+pub async fn discard_expired_states<TEntity, TRepo>(
+    eid: TEntity::StableId,
+    ttl: Duration,
+    repo: Arc<Mutex<TRepo>>,
+) -> bool
+where
+    TEntity: EntitySnapshot,
+    TRepo: EntityRepo<TEntity>,
+{
+    let mut repo = repo.lock().await;
+    discard_expired_states_locked(eid, ttl, &mut *repo).await
+}
+
+async fn discard_expired_states_locked<TEntity, TRepo>(
+    eid: TEntity::StableId,
+    ttl: Duration,
+    repo: &mut TRepo,
+) -> bool
+where
+    TEntity: EntitySnapshot,
+    TRepo: EntityRepo<TEntity>,
+{
+    let now = unix_time_millis();
+    let is_expired = |observed_at: Option<u64>| {
+        observed_at.map_or(true, |ts| now >= ts.saturating_add(ttl.as_millis() as u64))
+    };
+    let mut discarded = false;
+    if let Some(Unconfirmed(st)) = repo.get_last_unconfirmed(eid).await {
+        let sid = st.version();
+        if is_expired(repo.get_observed_at(sid).await) {
+            warn!(target: "box_resolver", "Unconfirmed state {} of {} expired", sid, eid);
+            repo.drop_unconfirmed(eid, sid).await;
+            discarded = true;
+        }
+    }
+    if let Some(Predicted(st)) = repo.get_last_predicted(eid).await {
+        let sid = st.version();
+        if is_expired(repo.get_observed_at(sid).await) {
+            warn!(target: "box_resolver", "Predicted state {} of {} expired", sid, eid);
+            repo.prune_predictions(eid, vec![sid]).await;
+            discarded = true;
+        }
+    }
+    discarded
+}
+
+/// Outcome of following prediction links from a predicted state towards its anchoring point.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PredictionLink<TVersion> {
+    /// Predicted state is linked to the anchoring point through the given versions
+    /// (anchoring point first, predicted state last).
+    Linked(Vec<TVersion>),
+    /// Links end at the given version without reaching the anchoring point.
+    Broken(TVersion),
+    /// Links lead back to the given, already visited, version.
+    CycleDetected(TVersion),
+    /// Anchoring point is not reached within the max depth.
+    DepthExceeded,
+}
+
+/// Follow prediction links from predicted `sid` of entity `eid` to `anchoring_sid`
+/// making at most `max_depth` steps.
+/// Links found to be broken or cyclic are pruned from the `repo`, so that they are not followed again.
+/// Last predicted state of the entity is dropped as well unless it has moved past `sid` meanwhile.
+pub async fn trace_prediction_link<TEntity, TRepo>(
+    eid: TEntity::StableId,
     sid: TEntity::Version,
     anchoring_sid: TEntity::Version,
+    max_depth: usize,
     repo: Arc<Mutex<TRepo>>,
-) -> bool
+) -> PredictionLink<TEntity::Version>
 where
     TEntity: EntitySnapshot,
     TRepo: EntityRepo<TEntity>,
 {
+    let mut repo = repo.lock().await;
+    trace_prediction_link_locked(eid, sid, anchoring_sid, max_depth, &mut *repo).await
+}
+
+async fn trace_prediction_link_locked<TEntity, TRepo>(
+    eid: TEntity::StableId,
+    sid: TEntity::Version,
+    anchoring_sid: TEntity::Version,
+    max_depth: usize,
+    repo: &mut TRepo,
+) -> PredictionLink<TEntity::Version>
+where
+    TEntity: EntitySnapshot,
+    TRepo: EntityRepo<TEntity>,
+{
+    let mut chain = vec![sid];
+    let mut visited = HashSet::from([sid]);
     let mut head_sid = sid;
-    let repo = repo.lock().await;
-    loop {
+    let link = loop {
+        if chain.len() > max_depth {
+            break PredictionLink::DepthExceeded;
+        }
         match repo.get_prediction_predecessor(head_sid).await {
-            None => return false,
-            Some(prev_state_id) if prev_state_id == anchoring_sid => return true,
-            Some(prev_state_id) => head_sid = prev_state_id,
+            None => break PredictionLink::Broken(head_sid),
+            Some(prev_state_id) if prev_state_id == anchoring_sid => {
+                chain.push(prev_state_id);
+                chain.reverse();
+                return PredictionLink::Linked(chain);
+            }
+            Some(prev_state_id) if !visited.insert(prev_state_id) => {
+                break PredictionLink::CycleDetected(prev_state_id)
+            }
+            Some(prev_state_id) => {
+                chain.push(prev_state_id);
+                head_sid = prev_state_id;
+            }
         }
+    };
+    if link == PredictionLink::DepthExceeded {
+        warn!(
+            target: "box_resolver",
+            "Prediction {} of {} is not linked to {} within {} steps",
+            sid,
+            eid,
+            anchoring_sid,
+            max_depth
+        );
+    } else {
+        warn!(
+            target: "box_resolver",
+            "Pruning {} prediction links of {}: {} is not linked to {}",
+            chain.len(),
+            eid,
+            sid,
+            anchoring_sid
+        );
+        repo.prune_predictions(eid, chain).await;
     }
+    link
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
+    use std::time::Duration;
 
     use tokio::sync::Mutex;
 
     use crate::box_resolver::persistence::tests::*;
     use crate::box_resolver::persistence::EntityRepo;
-    use crate::box_resolver::resolve_entity_state;
-    use crate::data::event::Confirmed;
+    use crate::box_resolver::{
+        discard_expired_states, resolve_entity_state, resolve_entity_state_traced, resolve_entity_states,
+        trace_prediction_link, PredictionLink,
+    };
+    use crate::data::event::{AnyMod, Confirmed, Predicted, Traced, Unconfirmed};
     use crate::data::Stable;
 
     #[tokio::test]
@@ -93,4 +380,281 @@ mod tests {
         let resolved = resolve_entity_state::<TestEntity, _>(entity.0.stable_id(), client).await;
         assert_eq!(resolved, Some(entity.0));
     }
+
+    #[tokio::test]
+    async fn test_resolve_states_of_several_entities() {
+        let mut client = rocks_db_client();
+        let (token_a, token_b) = (TokenId::random(), TokenId::random());
+        let confirmed_a = TestEntity {
+            token_id: token_a,
+            box_id: BoxId::random(),
+        };
+        let predicted_a = TestEntity {
+            token_id: token_a,
+            box_id: BoxId::random(),
+        };
+        let confirmed_b = TestEntity {
+            token_id: token_b,
+            box_id: BoxId::random(),
+        };
+        client.put_confirmed(Confirmed(confirmed_a.clone())).await;
+        client
+            .put_predicted(Traced::new(
+                Predicted(predicted_a.clone()),
+                Some(confirmed_a.box_id),
+            ))
+            .await;
+        client.put_confirmed(Confirmed(confirmed_b.clone())).await;
+
+        let client = Arc::new(Mutex::new(client));
+        let resolved =
+            resolve_entity_states::<TestEntity, _>(vec![token_b, token_a], Arc::clone(&client)).await;
+        assert_eq!(resolved, Some(vec![confirmed_b, predicted_a]));
+        let resolved = resolve_entity_states::<TestEntity, _>(vec![token_a, TokenId::random()], client).await;
+        assert_eq!(resolved, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_state_traced_predicted() {
+        let mut client = rocks_db_client();
+        let token_id = TokenId::random();
+        let states: Vec<_> = (0..3)
+            .map(|_| TestEntity {
+                token_id,
+                box_id: BoxId::random(),
+            })
+            .collect();
+        client.put_confirmed(Confirmed(states[0].clone())).await;
+        client
+            .put_predicted(Traced::new(Predicted(states[1].clone()), Some(states[0].box_id)))
+            .await;
+        client
+            .put_predicted(Traced::new(Predicted(states[2].clone()), Some(states[1].box_id)))
+            .await;
+
+        let client = Arc::new(Mutex::new(client));
+        let resolved = resolve_entity_state_traced::<TestEntity, _>(token_id, client)
+            .await
+            .unwrap();
+        assert_eq!(resolved.depth(), 2);
+        assert_eq!(
+            resolved.chain,
+            states.iter().map(|st| st.box_id).collect::<Vec<_>>()
+        );
+        match resolved.state {
+            AnyMod::Predicted(Traced {
+                state: Predicted(st),
+                prev_state_id,
+            }) => {
+                assert_eq!(st, states[2]);
+                assert_eq!(prev_state_id, Some(states[1].box_id));
+            }
+            _ => panic!("Expected predicted state"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_state_traced_broken_prediction() {
+        let mut client = rocks_db_client();
+        let token_id = TokenId::random();
+        let confirmed = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let unconfirmed = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let predicted = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        client.put_confirmed(Confirmed(confirmed.clone())).await;
+        client.put_unconfirmed(Unconfirmed(unconfirmed.clone())).await;
+        // Prediction is linked to the confirmed state, while the unconfirmed one is the anchoring point.
+        client
+            .put_predicted(Traced::new(Predicted(predicted), Some(confirmed.box_id)))
+            .await;
+
+        let client = Arc::new(Mutex::new(client));
+        let resolved = resolve_entity_state_traced::<TestEntity, _>(token_id, client)
+            .await
+            .unwrap();
+        assert_eq!(resolved.depth(), 0);
+        assert_eq!(resolved.chain, vec![unconfirmed.box_id]);
+        assert!(matches!(resolved.state, AnyMod::Unconfirmed(Unconfirmed(st)) if st == unconfirmed));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_state_prunes_cyclic_links() {
+        let mut client = rocks_db_client();
+        let token_id = TokenId::random();
+        let states: Vec<_> = (0..3)
+            .map(|_| TestEntity {
+                token_id,
+                box_id: BoxId::random(),
+            })
+            .collect();
+        client.put_confirmed(Confirmed(states[0].clone())).await;
+        client
+            .put_predicted(Traced::new(Predicted(states[1].clone()), Some(states[2].box_id)))
+            .await;
+        client
+            .put_predicted(Traced::new(Predicted(states[2].clone()), Some(states[1].box_id)))
+            .await;
+
+        let client = Arc::new(Mutex::new(client));
+        let link = trace_prediction_link::<TestEntity, _>(
+            token_id,
+            states[2].box_id,
+            states[0].box_id,
+            16,
+            Arc::clone(&client),
+        )
+        .await;
+        assert_eq!(link, PredictionLink::CycleDetected(states[2].box_id));
+        let repo = client.lock().await;
+        let pred: Option<Predicted<TestEntity>> = repo.get_last_predicted(token_id).await;
+        assert!(pred.is_none());
+        assert_eq!(
+            EntityRepo::<TestEntity>::get_prediction_predecessor(&*repo, states[1].box_id).await,
+            None
+        );
+        assert_eq!(
+            EntityRepo::<TestEntity>::get_prediction_predecessor(&*repo, states[2].box_id).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_state_prunes_broken_links() {
+        let mut client = rocks_db_client();
+        let token_id = TokenId::random();
+        let confirmed = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let predicted = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let dangling = BoxId::random();
+        client.put_confirmed(Confirmed(confirmed.clone())).await;
+        client
+            .put_predicted(Traced::new(Predicted(predicted.clone()), Some(dangling)))
+            .await;
+
+        let client = Arc::new(Mutex::new(client));
+        let resolved = resolve_entity_state_traced::<TestEntity, _>(token_id, Arc::clone(&client))
+            .await
+            .unwrap();
+        assert!(matches!(resolved.state, AnyMod::Confirmed(Confirmed(st)) if st == confirmed));
+        let repo = client.lock().await;
+        let pred: Option<Predicted<TestEntity>> = repo.get_last_predicted(token_id).await;
+        assert!(pred.is_none());
+        assert_eq!(
+            EntityRepo::<TestEntity>::get_prediction_predecessor(&*repo, predicted.box_id).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trace_prediction_link_depth_exceeded() {
+        let mut client = rocks_db_client();
+        let token_id = TokenId::random();
+        let states: Vec<_> = (0..4)
+            .map(|_| TestEntity {
+                token_id,
+                box_id: BoxId::random(),
+            })
+            .collect();
+        client.put_confirmed(Confirmed(states[0].clone())).await;
+        for pair in states.windows(2) {
+            client
+                .put_predicted(Traced::new(Predicted(pair[1].clone()), Some(pair[0].box_id)))
+                .await;
+        }
+
+        let client = Arc::new(Mutex::new(client));
+        let (tip, anchor) = (states[3].box_id, states[0].box_id);
+        let link =
+            trace_prediction_link::<TestEntity, _>(token_id, tip, anchor, 2, Arc::clone(&client)).await;
+        assert_eq!(link, PredictionLink::DepthExceeded);
+        // Links are kept intact as they may be valid.
+        let link =
+            trace_prediction_link::<TestEntity, _>(token_id, tip, anchor, 3, Arc::clone(&client)).await;
+        assert_eq!(
+            link,
+            PredictionLink::Linked(states.iter().map(|st| st.box_id).collect())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_state_discards_expired_states() {
+        let mut client = rocks_db_client();
+        let token_id = TokenId::random();
+        let states: Vec<_> = (0..3)
+            .map(|_| TestEntity {
+                token_id,
+                box_id: BoxId::random(),
+            })
+            .collect();
+        client.put_confirmed(Confirmed(states[0].clone())).await;
+        client.put_unconfirmed(Unconfirmed(states[1].clone())).await;
+        client
+            .put_predicted(Traced::new(Predicted(states[2].clone()), Some(states[1].box_id)))
+            .await;
+
+        let client = Arc::new(Mutex::new(client));
+        let ttl = Duration::from_secs(3600);
+        assert!(!discard_expired_states::<TestEntity, _>(token_id, ttl, Arc::clone(&client)).await);
+        let resolved = resolve_entity_state::<TestEntity, _>(token_id, Arc::clone(&client)).await;
+        assert_eq!(resolved, Some(states[2].clone()));
+
+        assert!(discard_expired_states::<TestEntity, _>(token_id, Duration::ZERO, Arc::clone(&client)).await);
+        let resolved = resolve_entity_state::<TestEntity, _>(token_id, Arc::clone(&client)).await;
+        assert_eq!(resolved, Some(states[0].clone()));
+        let repo = client.lock().await;
+        let unconf: Option<Unconfirmed<TestEntity>> = repo.get_last_unconfirmed(token_id).await;
+        assert!(unconf.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_state_falls_back_to_confirmed_once_tx_is_gone() {
+        let mut client = rocks_db_client();
+        let token_id = TokenId::random();
+        let confirmed = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let unconfirmed = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        client.put_confirmed(Confirmed(confirmed.clone())).await;
+        client.put_unconfirmed(Unconfirmed(unconfirmed.clone())).await;
+        EntityRepo::<TestEntity>::drop_unconfirmed(&mut client, token_id, unconfirmed.box_id).await;
+
+        let client = Arc::new(Mutex::new(client));
+        let resolved = resolve_entity_state::<TestEntity, _>(token_id, client).await;
+        assert_eq!(resolved, Some(confirmed));
+    }
+
+    #[cfg(feature = "send")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_resolve_state_spawned() {
+        let mut client = rocks_db_client();
+        let entity = Confirmed(TestEntity {
+            token_id: TokenId::random(),
+            box_id: BoxId::random(),
+        });
+        client.put_confirmed(entity.clone()).await;
+
+        let client = Arc::new(Mutex::new(client));
+        let id = entity.0.stable_id();
+        let resolved = tokio::spawn(resolve_entity_state::<TestEntity, _>(id, client))
+            .await
+            .unwrap();
+        assert_eq!(resolved, Some(entity.0));
+    }
 }
@@ -1,12 +1,31 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
+use futures::{stream, Stream};
+use futures_timer::Delay;
+use log::{info, warn};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 use crate::data::EntitySnapshot;
+use crate::maybe_send::MaybeSend;
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 pub trait EntityBlacklist<T: EntitySnapshot> {
     async fn is_blacklisted(&self, id: &T::StableId) -> bool;
+    /// Account failed execution against the given entity.
+    fn report_failure(&self, _id: T::StableId) {}
+    /// Account successful execution against the given entity.
+    fn report_success(&self, _id: T::StableId) {}
 }
 
 pub struct StaticBlacklist<T: EntitySnapshot> {
@@ -19,12 +38,360 @@ impl<T: EntitySnapshot> StaticBlacklist<T> {
     }
 }
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 impl<T> EntityBlacklist<T> for StaticBlacklist<T>
 where
     T: EntitySnapshot,
+    T::StableId: MaybeSend,
 {
     async fn is_blacklisted(&self, id: &T::StableId) -> bool {
         self.entries.contains(id)
     }
 }
+
+/// Reloadable settings of [DynamicBlacklist].
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct DynamicBlacklistConfig<TId: Eq + Hash> {
+    /// Number of consecutive failed executions after which an entity is quarantined.
+    pub max_failures: u32,
+    /// How long a quarantined entity stays blacklisted.
+    pub quarantine_secs: u64,
+    /// Entities which are always blacklisted.
+    pub entries: HashSet<TId>,
+}
+
+/// Part of the [DynamicBlacklist] state which survives restarts.
+#[derive(Serialize, Deserialize)]
+struct PersistentEntries<TId: Eq + Hash> {
+    /// Entities blacklisted manually.
+    manual: HashSet<TId>,
+    /// Quarantined entities along with unix time (in seconds) their quarantine expires at.
+    quarantined: HashMap<TId, u64>,
+}
+
+impl<TId: Eq + Hash> Default for PersistentEntries<TId> {
+    fn default() -> Self {
+        Self {
+            manual: HashSet::new(),
+            quarantined: HashMap::new(),
+        }
+    }
+}
+
+struct BlacklistState<TId: Eq + Hash> {
+    conf: DynamicBlacklistConfig<TId>,
+    entries: PersistentEntries<TId>,
+    failures: HashMap<TId, u32>,
+    writer: EntriesWriter,
+}
+
+impl<TId> BlacklistState<TId>
+where
+    TId: Eq + Hash + Serialize,
+{
+    /// Snapshot persistent entries to be written to disk in background.
+    fn persist(&self) {
+        match bincode::serialize(&self.entries) {
+            Ok(snapshot) => self.writer.write(snapshot),
+            Err(err) => warn!(target: "blacklist", "Failed to persist blacklist entries: {}", err),
+        }
+    }
+}
+
+/// Writes snapshots of persistent entries to disk on a dedicated thread,
+/// so that file IO doesn't happen while the state is locked.
+/// Pending snapshots are flushed when the writer is dropped.
+struct EntriesWriter {
+    snapshots: Option<Sender<Vec<u8>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl EntriesWriter {
+    fn spawn(store_path: PathBuf) -> Self {
+        let (snapshots, pending) = channel::<Vec<u8>>();
+        let worker = thread::spawn(move || {
+            while let Ok(mut snapshot) = pending.recv() {
+                // Only the latest snapshot is worth writing.
+                while let Ok(newer_snapshot) = pending.try_recv() {
+                    snapshot = newer_snapshot;
+                }
+                let tmp_path = store_path.with_extension("tmp");
+                let res = fs::write(&tmp_path, snapshot).and_then(|_| fs::rename(&tmp_path, &store_path));
+                if let Err(err) = res {
+                    warn!(target: "blacklist", "Failed to persist blacklist entries: {}", err);
+                }
+            }
+        });
+        Self {
+            snapshots: Some(snapshots),
+            worker: Some(worker),
+        }
+    }
+
+    fn write(&self, snapshot: Vec<u8>) {
+        if let Some(snapshots) = &self.snapshots {
+            let _ = snapshots.send(snapshot);
+        }
+    }
+}
+
+impl Drop for EntriesWriter {
+    fn drop(&mut self) {
+        drop(self.snapshots.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Blacklist which quarantines an entity for a while after a number of consecutive failed executions.
+/// Entries can also be added and removed manually at runtime.
+/// Manual and quarantined entries are persisted to disk, so that they survive restarts.
+/// Handles are cheap to clone and share the same state.
+pub struct DynamicBlacklist<T: EntitySnapshot> {
+    state: Arc<Mutex<BlacklistState<T::StableId>>>,
+}
+
+impl<T: EntitySnapshot> Clone for DynamicBlacklist<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<T> DynamicBlacklist<T>
+where
+    T: EntitySnapshot,
+    T::StableId: Serialize + DeserializeOwned,
+{
+    /// Create blacklist restoring persisted entries from `store_path` if there are any.
+    pub fn new(conf: DynamicBlacklistConfig<T::StableId>, store_path: PathBuf) -> Self {
+        let entries = match fs::read(&store_path) {
+            Ok(bytes) => bincode::deserialize(&bytes).unwrap_or_else(|err| {
+                warn!(target: "blacklist", "Failed to restore blacklist entries: {}", err);
+                PersistentEntries::default()
+            }),
+            Err(_) => PersistentEntries::default(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(BlacklistState {
+                conf,
+                entries,
+                failures: HashMap::new(),
+                writer: EntriesWriter::spawn(store_path),
+            })),
+        }
+    }
+
+    /// Blacklist the given entity until it is removed manually.
+    pub fn add(&self, id: T::StableId) {
+        let mut state = self.state.lock();
+        if state.entries.manual.insert(id) {
+            info!(target: "blacklist", "Entity {} is blacklisted manually", id);
+            state.persist();
+        }
+    }
+
+    /// Remove the given entity from blacklist, lifting its quarantine as well.
+    pub fn remove(&self, id: T::StableId) {
+        let mut state = self.state.lock();
+        state.failures.remove(&id);
+        let removed_manual = state.entries.manual.remove(&id);
+        let removed_quarantined = state.entries.quarantined.remove(&id).is_some();
+        if removed_manual || removed_quarantined {
+            info!(target: "blacklist", "Entity {} is removed from blacklist", id);
+            state.persist();
+        }
+    }
+
+    /// Apply new settings. Entities quarantined already keep their expiration time.
+    pub fn reload(&self, conf: DynamicBlacklistConfig<T::StableId>) {
+        self.state.lock().conf = conf;
+    }
+
+    /// Reload settings from the JSON file at `path`.
+    pub fn reload_from_file(&self, path: &Path) -> Result<(), String> {
+        let conf = fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|err| err.to_string()))?;
+        self.reload(conf);
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<T> EntityBlacklist<T> for DynamicBlacklist<T>
+where
+    T: EntitySnapshot,
+    T::StableId: Serialize + DeserializeOwned + MaybeSend,
+{
+    async fn is_blacklisted(&self, id: &T::StableId) -> bool {
+        let mut state = self.state.lock();
+        if state.conf.entries.contains(id) || state.entries.manual.contains(id) {
+            return true;
+        }
+        match state.entries.quarantined.get(id) {
+            Some(expires_at) if *expires_at > unix_time_secs() => true,
+            Some(_) => {
+                info!(target: "blacklist", "Quarantine of entity {} expired", id);
+                state.entries.quarantined.remove(id);
+                state.persist();
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn report_failure(&self, id: T::StableId) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        let failures = state.failures.entry(id).or_insert(0);
+        *failures += 1;
+        if *failures >= state.conf.max_failures {
+            state.failures.remove(&id);
+            let quarantine_secs = state.conf.quarantine_secs;
+            warn!(
+                target: "blacklist",
+                "Entity {} is quarantined for {}s after {} consecutive failures",
+                id,
+                quarantine_secs,
+                state.conf.max_failures
+            );
+            state
+                .entries
+                .quarantined
+                .insert(id, unix_time_secs() + quarantine_secs);
+            state.persist();
+        }
+    }
+
+    fn report_success(&self, id: T::StableId) {
+        self.state.lock().failures.remove(&id);
+    }
+}
+
+/// Reload settings of the `blacklist` from the JSON file at `path` every time it is modified.
+/// The file is checked for modifications every `poll_interval`.
+pub fn config_reload_stream<'a, T>(
+    blacklist: DynamicBlacklist<T>,
+    path: PathBuf,
+    poll_interval: Duration,
+) -> impl Stream<Item = ()> + 'a
+where
+    T: EntitySnapshot + 'a,
+    T::StableId: Serialize + DeserializeOwned,
+{
+    stream::unfold(None, move |last_modified| {
+        let blacklist = blacklist.clone();
+        let path = path.clone();
+        async move {
+            Delay::new(poll_interval).await;
+            let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+            if modified.is_some() && modified != last_modified {
+                match blacklist.reload_from_file(&path) {
+                    Ok(()) => info!(target: "blacklist", "Blacklist config reloaded from {:?}", path),
+                    Err(err) => warn!(target: "blacklist", "Failed to reload blacklist config: {}", err),
+                }
+            }
+            Some(((), modified))
+        }
+    })
+}
+
+fn unix_time_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use rand::{thread_rng, RngCore};
+
+    use crate::box_resolver::blacklist::{
+        config_reload_stream, DynamicBlacklist, DynamicBlacklistConfig, EntityBlacklist,
+    };
+    use crate::box_resolver::persistence::tests::{TestEntity, TokenId};
+
+    fn conf(max_failures: u32, quarantine_secs: u64) -> DynamicBlacklistConfig<TokenId> {
+        DynamicBlacklistConfig {
+            max_failures,
+            quarantine_secs,
+            entries: HashSet::new(),
+        }
+    }
+
+    fn store_path() -> PathBuf {
+        fs::create_dir_all("./tmp").unwrap();
+        PathBuf::from(format!("./tmp/blacklist-{}", thread_rng().next_u32()))
+    }
+
+    #[tokio::test]
+    async fn quarantine_after_consecutive_failures() {
+        let blacklist = DynamicBlacklist::<TestEntity>::new(conf(3, 3600), store_path());
+        let id = TokenId::random();
+        blacklist.report_failure(id);
+        blacklist.report_failure(id);
+        blacklist.report_success(id);
+        blacklist.report_failure(id);
+        blacklist.report_failure(id);
+        assert!(!blacklist.is_blacklisted(&id).await);
+        blacklist.report_failure(id);
+        assert!(blacklist.is_blacklisted(&id).await);
+        blacklist.remove(id);
+        assert!(!blacklist.is_blacklisted(&id).await);
+    }
+
+    #[tokio::test]
+    async fn quarantine_expires() {
+        let blacklist = DynamicBlacklist::<TestEntity>::new(conf(1, 0), store_path());
+        let id = TokenId::random();
+        blacklist.report_failure(id);
+        assert!(!blacklist.is_blacklisted(&id).await);
+    }
+
+    #[tokio::test]
+    async fn entries_survive_restart() {
+        let path = store_path();
+        let manual = TokenId::random();
+        let quarantined = TokenId::random();
+        {
+            let blacklist = DynamicBlacklist::<TestEntity>::new(conf(1, 3600), path.clone());
+            blacklist.add(manual);
+            blacklist.report_failure(quarantined);
+        }
+        let blacklist = DynamicBlacklist::<TestEntity>::new(conf(1, 3600), path);
+        assert!(blacklist.is_blacklisted(&manual).await);
+        assert!(blacklist.is_blacklisted(&quarantined).await);
+        assert!(!blacklist.is_blacklisted(&TokenId::random()).await);
+    }
+
+    #[tokio::test]
+    async fn config_is_reloaded_on_change() {
+        let conf_path = store_path().with_extension("json");
+        let blacklist = DynamicBlacklist::<TestEntity>::new(conf(1, 3600), store_path());
+        let id = TokenId::random();
+        let mut reload = Box::pin(config_reload_stream(
+            blacklist.clone(),
+            conf_path.clone(),
+            Duration::from_millis(1),
+        ));
+        reload.next().await;
+        assert!(!blacklist.is_blacklisted(&id).await);
+        let mut new_conf = conf(1, 3600);
+        new_conf.entries.insert(id);
+        fs::write(&conf_path, serde_json::to_vec(&new_conf).unwrap()).unwrap();
+        reload.next().await;
+        assert!(blacklist.is_blacklisted(&id).await);
+    }
+}
@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::data::EntitySnapshot;
+
+/// Confirmed version of an entity along with the point it was confirmed at.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct VersionRecord<TVersion, TTxHash> {
+    pub version: TVersion,
+    /// Slot of the block the version was confirmed in.
+    pub slot: u64,
+    /// Hash of the transaction which produced the version.
+    pub tx_hash: TTxHash,
+}
+
+/// Point of the ledger a confirmed update was observed at.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LedgerOrigin<TTxHash> {
+    /// Slot of the block the update was observed in.
+    pub slot: u64,
+    /// Hash of the transaction which made the update.
+    pub tx_hash: TTxHash,
+}
+
+/// Ordered history of confirmed versions of on-chain entities.
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait EntityHistory<TEntity: EntitySnapshot, TTxHash> {
+    /// Append confirmed version to the history of the entity `eid`.
+    /// Versions which are in the history already are ignored.
+    async fn record_version(
+        &mut self,
+        eid: TEntity::StableId,
+        record: VersionRecord<TEntity::Version, TTxHash>,
+    );
+    /// Get versions of the entity `eid` from `from` to `to` inclusive, oldest first.
+    /// Nothing is returned if any of the two versions is not in the history or `to` precedes `from`.
+    async fn get_history(
+        &self,
+        eid: TEntity::StableId,
+        from: TEntity::Version,
+        to: TEntity::Version,
+    ) -> Vec<VersionRecord<TEntity::Version, TTxHash>>;
+    /// Get up to `n` latest versions of the entity `eid`, oldest first.
+    async fn get_last_versions(
+        &self,
+        eid: TEntity::StableId,
+        n: usize,
+    ) -> Vec<VersionRecord<TEntity::Version, TTxHash>>;
+    /// Remove `version` and all versions following it from the history of the entity `eid`,
+    /// e.g. when the block it was confirmed in is rolled back.
+    async fn rollback_history(&mut self, eid: TEntity::StableId, version: TEntity::Version);
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, RngCore};
+
+    use crate::box_resolver::history::{EntityHistory, VersionRecord};
+    use crate::box_resolver::persistence::inmemory::InMemoryEntityRepo;
+    use crate::box_resolver::persistence::tests::*;
+
+    type TxHash = [u8; 32];
+
+    fn gen_records(n: usize) -> Vec<VersionRecord<BoxId, TxHash>> {
+        (0..n)
+            .map(|i| {
+                let mut tx_hash = [0u8; 32];
+                thread_rng().fill_bytes(&mut tx_hash);
+                VersionRecord {
+                    version: BoxId::random(),
+                    slot: 100 + i as u64,
+                    tx_hash,
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_inmem_history() {
+        let client = InMemoryEntityRepo::default();
+        test_entity_history(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_rocksdb_history() {
+        let client = rocks_db_client();
+        test_entity_history(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_inmem_history_rollback() {
+        let client = InMemoryEntityRepo::default();
+        test_entity_history_rollback(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_rocksdb_history_rollback() {
+        let client = rocks_db_client();
+        test_entity_history_rollback(client).await;
+    }
+
+    async fn test_entity_history<C: EntityHistory<TestEntity, TxHash>>(mut client: C) {
+        let token_id = TokenId::random();
+        let other_token_id = TokenId::random();
+        let records = gen_records(5);
+        for record in &records {
+            client.record_version(token_id, record.clone()).await;
+        }
+        // Recording the same version again has no effect.
+        client.record_version(token_id, records[2].clone()).await;
+        client
+            .record_version(other_token_id, gen_records(1).remove(0))
+            .await;
+
+        let history = client
+            .get_history(token_id, records[1].version, records[3].version)
+            .await;
+        assert_eq!(history, records[1..=3].to_vec());
+        let history = client
+            .get_history(token_id, records[3].version, records[1].version)
+            .await;
+        assert!(history.is_empty());
+        let history = client
+            .get_history(token_id, records[0].version, BoxId::random())
+            .await;
+        assert!(history.is_empty());
+
+        assert_eq!(client.get_last_versions(token_id, 2).await, records[3..].to_vec());
+        assert_eq!(client.get_last_versions(token_id, 10).await, records);
+        assert_eq!(client.get_last_versions(other_token_id, 10).await.len(), 1);
+        // Histories of different entities are independent.
+        client.record_version(other_token_id, records[0].clone()).await;
+        assert_eq!(client.get_last_versions(other_token_id, 10).await.len(), 2);
+        assert_eq!(client.get_last_versions(token_id, 10).await, records);
+        assert!(client.get_last_versions(TokenId::random(), 10).await.is_empty());
+    }
+
+    async fn test_entity_history_rollback<C: EntityHistory<TestEntity, TxHash>>(mut client: C) {
+        let token_id = TokenId::random();
+        let records = gen_records(4);
+        for record in &records {
+            client.record_version(token_id, record.clone()).await;
+        }
+        client.rollback_history(token_id, records[2].version).await;
+        assert_eq!(
+            client.get_last_versions(token_id, 10).await,
+            records[..2].to_vec()
+        );
+        let history = client
+            .get_history(token_id, records[0].version, records[3].version)
+            .await;
+        assert!(history.is_empty());
+
+        // History grows from the point of rollback.
+        let fork = gen_records(1).remove(0);
+        client.record_version(token_id, fork.clone()).await;
+        let history = client
+            .get_history(token_id, records[0].version, fork.version)
+            .await;
+        assert_eq!(history, vec![records[0].clone(), records[1].clone(), fork]);
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use futures::{Stream, StreamExt};
+use log::trace;
+use parking_lot::Mutex;
+
+use crate::box_resolver::persistence::EntityRepo;
+use crate::combinators::Ior;
+use crate::data::event::{Channel, Confirmed, StateUpdate};
+use crate::data::{EntitySnapshot, Tradable};
+
+/// Index of pools by the pair they trade.
+/// Clones share the same index.
+#[derive(Debug)]
+pub struct PairIndex<TPair, TPool> {
+    pools: Arc<Mutex<HashMap<TPair, HashSet<TPool>>>>,
+}
+
+impl<TPair, TPool> Clone for PairIndex<TPair, TPool> {
+    fn clone(&self) -> Self {
+        Self {
+            pools: Arc::clone(&self.pools),
+        }
+    }
+}
+
+impl<TPair, TPool> Default for PairIndex<TPair, TPool> {
+    fn default() -> Self {
+        Self {
+            pools: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<TPair, TPool> PairIndex<TPair, TPool>
+where
+    TPair: Copy + Eq + Hash + Display,
+    TPool: Copy + Eq + Hash + Display,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, pair: TPair, pool: TPool) {
+        self.pools.lock().entry(pair).or_default().insert(pool);
+    }
+
+    pub fn remove(&self, pair: TPair, pool: TPool) {
+        let mut pools = self.pools.lock();
+        if let Some(pair_pools) = pools.get_mut(&pair) {
+            pair_pools.remove(&pool);
+            if pair_pools.is_empty() {
+                pools.remove(&pair);
+            }
+        }
+    }
+
+    /// Get all known pools trading the given `pair`.
+    pub fn get(&self, pair: TPair) -> Vec<TPool> {
+        self.pools
+            .lock()
+            .get(&pair)
+            .map(|pools| pools.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Add all pools confirmed in the `repo`.
+    /// Used to seed the index with pools persisted before startup,
+    /// as [pair_index_tracking] only observes pools updated afterwards.
+    pub async fn load_confirmed<Pool, Repo>(&self, repo: &Repo)
+    where
+        Pool: EntitySnapshot<StableId = TPool> + Tradable<PairId = TPair>,
+        Repo: EntityRepo<Pool>,
+    {
+        let pools = repo.get_all_confirmed().await;
+        trace!(target: "offchain", "Loading {} confirmed pools into pair index", pools.len());
+        for Confirmed(pool) in pools {
+            self.add(pool.pair_id(), pool.stable_id());
+        }
+    }
+
+    /// Account update of a pool.
+    /// Pools are added once any of their states is observed and
+    /// removed once they are eliminated or rolled back in the ledger.
+    pub fn apply_update<Pool>(&self, upd: &Channel<StateUpdate<Pool>>)
+    where
+        Pool: EntitySnapshot<StableId = TPool> + Tradable<PairId = TPair>,
+    {
+        match upd {
+            Channel::Ledger(Confirmed(
+                StateUpdate::Transition(Ior::Left(st)) | StateUpdate::TransitionRollback(Ior::Left(st)),
+            )) => {
+                trace!(
+                    target: "offchain",
+                    "Removing pool {} from index of pair {}",
+                    st.stable_id(),
+                    st.pair_id()
+                );
+                self.remove(st.pair_id(), st.stable_id())
+            }
+            _ => match upd.erased() {
+                StateUpdate::Transition(Ior::Right(st) | Ior::Both(_, st))
+                | StateUpdate::TransitionRollback(Ior::Right(st) | Ior::Both(_, st)) => {
+                    self.add(st.pair_id(), st.stable_id())
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+/// Keep `index` up to date with updates of pools from the `upstream`.
+/// Updates are passed through, so that the resulting stream can be fed to
+/// [pool_tracking_stream](crate::box_resolver::process::pool_tracking_stream).
+pub fn pair_index_tracking<'a, S, Pool>(
+    upstream: S,
+    index: PairIndex<Pool::PairId, Pool::StableId>,
+) -> impl Stream<Item = Channel<StateUpdate<Pool>>> + 'a
+where
+    S: Stream<Item = Channel<StateUpdate<Pool>>> + 'a,
+    Pool: EntitySnapshot + Tradable + 'a,
+{
+    upstream.inspect(move |upd| index.apply_update(upd))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, StreamExt};
+
+    use crate::box_resolver::pair_index::{pair_index_tracking, PairIndex};
+    use crate::box_resolver::persistence::inmemory::InMemoryEntityRepo;
+    use crate::box_resolver::persistence::tests::*;
+    use crate::box_resolver::persistence::EntityRepo;
+    use crate::combinators::Ior;
+    use crate::data::event::{Channel, Confirmed, StateUpdate, Unconfirmed};
+
+    #[tokio::test]
+    async fn index_follows_pool_updates() {
+        let index = PairIndex::new();
+        let pool = |token_id| TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let (a, b) = (pool(TokenId::random()), pool(TokenId::random()));
+        let a_next = pool(a.token_id);
+        let updates = vec![
+            Channel::ledger(StateUpdate::Transition(Ior::Right(a.clone()))),
+            Channel::mempool(StateUpdate::Transition(Ior::Right(b.clone()))),
+            Channel::ledger(StateUpdate::Transition(Ior::Both(a.clone(), a_next.clone()))),
+        ];
+        pair_index_tracking(stream::iter(updates), index.clone())
+            .collect::<Vec<_>>()
+            .await;
+        let mut pools = index.get(TestPair);
+        pools.sort();
+        let mut expected = vec![a.token_id, b.token_id];
+        expected.sort();
+        assert_eq!(pools, expected);
+
+        // Pools consumed outside of the ledger stay in the index.
+        let updates = vec![
+            Channel::mempool(StateUpdate::Transition(Ior::Left(b.clone()))),
+            Channel::ledger(StateUpdate::Transition(Ior::Left(a_next))),
+        ];
+        pair_index_tracking(stream::iter(updates), index.clone())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(index.get(TestPair), vec![b.token_id]);
+        index.remove(TestPair, b.token_id);
+        assert!(index.get(TestPair).is_empty());
+    }
+
+    #[tokio::test]
+    async fn index_is_seeded_with_confirmed_pools() {
+        let mut repo = InMemoryEntityRepo::new();
+        let confirmed = TestEntity {
+            token_id: TokenId::random(),
+            box_id: BoxId::random(),
+        };
+        let unconfirmed = TestEntity {
+            token_id: TokenId::random(),
+            box_id: BoxId::random(),
+        };
+        repo.put_confirmed(Confirmed(confirmed.clone())).await;
+        repo.put_unconfirmed(Unconfirmed(unconfirmed)).await;
+        let index = PairIndex::new();
+        index.load_confirmed(&repo).await;
+        assert_eq!(index.get(TestPair), vec![confirmed.token_id]);
+    }
+}
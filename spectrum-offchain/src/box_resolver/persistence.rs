@@ -1,4 +1,5 @@
 use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use log::trace;
@@ -6,14 +7,17 @@ use log::trace;
 use crate::box_resolver::{Predicted, Traced};
 use crate::data::event::{Confirmed, Unconfirmed};
 use crate::data::{EntitySnapshot, Stable};
+use crate::maybe_send::MaybeSend;
 
+pub mod cached;
 pub mod inmemory;
 pub mod noop;
 pub mod rocksdb;
 
 /// Stores on-chain entities.
 /// Operations are atomic.
-#[async_trait(? Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 pub trait EntityRepo<TEntity: EntitySnapshot> {
     /// Get state id preceding given predicted state.
     async fn get_prediction_predecessor<'a>(&self, id: TEntity::Version) -> Option<TEntity::Version>
@@ -31,6 +35,12 @@ pub trait EntityRepo<TEntity: EntitySnapshot> {
     async fn get_last_unconfirmed<'a>(&self, id: TEntity::StableId) -> Option<Unconfirmed<TEntity>>
     where
         <TEntity as Stable>::StableId: 'a;
+    /// Get last confirmed states of all known entities.
+    async fn get_all_confirmed(&self) -> Vec<Confirmed<TEntity>>;
+    /// Get time (unix millis) at which the given unconfirmed or predicted state was observed.
+    async fn get_observed_at<'a>(&self, sid: TEntity::Version) -> Option<u64>
+    where
+        <TEntity as EntitySnapshot>::Version: 'a;
     /// Persist predicted state of the entity.
     async fn put_predicted<'a>(&mut self, entity: Traced<Predicted<TEntity>>)
     where
@@ -52,6 +62,19 @@ pub trait EntityRepo<TEntity: EntitySnapshot> {
     async fn eliminate<'a>(&mut self, entity: TEntity)
     where
         TEntity: 'a;
+    /// Discard unconfirmed state `sid` of the entity, e.g. once its transaction is gone from mempool,
+    /// so that the entity falls back to its last confirmed state.
+    /// Does nothing if `sid` is not the last unconfirmed state of the entity.
+    async fn drop_unconfirmed<'a>(&mut self, eid: TEntity::StableId, sid: TEntity::Version)
+    where
+        <TEntity as EntitySnapshot>::Version: 'a,
+        <TEntity as Stable>::StableId: 'a;
+    /// Drop prediction links of the given states, head of the predictions first.
+    /// Last predicted state of the entity is dropped as well if it is still the head.
+    async fn prune_predictions<'a>(&mut self, eid: TEntity::StableId, sids: Vec<TEntity::Version>)
+    where
+        <TEntity as EntitySnapshot>::Version: 'a,
+        <TEntity as Stable>::StableId: 'a;
     /// False-positive analog of `exists()`.
     async fn may_exist<'a>(&self, sid: TEntity::Version) -> bool
     where
@@ -61,6 +84,14 @@ pub trait EntityRepo<TEntity: EntitySnapshot> {
         <TEntity as EntitySnapshot>::Version: 'a;
 }
 
+/// Current unix time in milliseconds, used to stamp observed states.
+pub(crate) fn unix_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 pub struct EntityRepoTracing<R> {
     inner: R,
 }
@@ -71,13 +102,14 @@ impl<R> EntityRepoTracing<R> {
     }
 }
 
-#[async_trait(? Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 impl<TEntity, R> EntityRepo<TEntity> for EntityRepoTracing<R>
 where
-    TEntity: EntitySnapshot,
-    TEntity::StableId: Debug + Copy,
-    TEntity::Version: Debug + Copy,
-    R: EntityRepo<TEntity>,
+    TEntity: EntitySnapshot + MaybeSend,
+    TEntity::StableId: Debug + Copy + MaybeSend,
+    TEntity::Version: Debug + Copy + MaybeSend,
+    R: EntityRepo<TEntity> + MaybeSend,
 {
     async fn get_prediction_predecessor<'a>(&self, id: TEntity::Version) -> Option<TEntity::Version>
     where
@@ -119,6 +151,23 @@ where
         res
     }
 
+    async fn get_all_confirmed(&self) -> Vec<Confirmed<TEntity>> {
+        trace!(target: "box_resolver", "get_all_confirmed()");
+        let res = self.inner.get_all_confirmed().await;
+        trace!(target: "box_resolver", "get_all_confirmed() -> <{} entities>", res.len());
+        res
+    }
+
+    async fn get_observed_at<'a>(&self, sid: TEntity::Version) -> Option<u64>
+    where
+        <TEntity as EntitySnapshot>::Version: 'a,
+    {
+        trace!(target: "box_resolver", "get_observed_at({})", sid);
+        let res = self.inner.get_observed_at(sid).await;
+        trace!(target: "box_resolver", "get_observed_at({}) -> {:?}", sid, res);
+        res
+    }
+
     async fn put_predicted<'a>(&mut self, entity: Traced<Predicted<TEntity>>)
     where
         Traced<Predicted<TEntity>>: 'a,
@@ -173,6 +222,26 @@ where
         trace!(target: "box_resolver", "eliminate({}) -> ()", show_entity);
     }
 
+    async fn drop_unconfirmed<'a>(&mut self, eid: TEntity::StableId, sid: TEntity::Version)
+    where
+        <TEntity as EntitySnapshot>::Version: 'a,
+        <TEntity as Stable>::StableId: 'a,
+    {
+        trace!(target: "box_resolver", "drop_unconfirmed({}, {})", eid, sid);
+        self.inner.drop_unconfirmed(eid, sid).await;
+        trace!(target: "box_resolver", "drop_unconfirmed({}, {}) -> ()", eid, sid);
+    }
+
+    async fn prune_predictions<'a>(&mut self, eid: TEntity::StableId, sids: Vec<TEntity::Version>)
+    where
+        <TEntity as EntitySnapshot>::Version: 'a,
+        <TEntity as Stable>::StableId: 'a,
+    {
+        trace!(target: "box_resolver", "prune_predictions({}, {:?})", eid, sids);
+        self.inner.prune_predictions(eid, sids).await;
+        trace!(target: "box_resolver", "prune_predictions({}) -> ()", eid);
+    }
+
     async fn may_exist<'a>(&self, sid: TEntity::Version) -> bool
     where
         <TEntity as EntitySnapshot>::Version: 'a,
@@ -196,15 +265,17 @@ where
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use std::sync::Arc;
+    use std::num::NonZeroUsize;
 
     use derive_more::Display;
     use rand::{thread_rng, RngCore};
     use serde::{Deserialize, Serialize};
 
+    use crate::box_resolver::persistence::cached::CachedEntityRepo;
     use crate::box_resolver::persistence::inmemory::InMemoryEntityRepo;
     use crate::box_resolver::persistence::rocksdb::EntityRepoRocksDB;
-    use crate::data::Stable;
+    use crate::data::{Stable, Tradable};
+    use crate::rocks::RocksConfig;
     use crate::{
         box_resolver::persistence::EntityRepo,
         data::{
@@ -214,7 +285,7 @@ pub(crate) mod tests {
     };
 
     #[repr(transparent)]
-    #[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
     pub struct TokenId(u64);
 
     impl Into<[u8; 60]> for TokenId {
@@ -269,6 +340,18 @@ pub(crate) mod tests {
         }
     }
 
+    /// All test entities trade the same pair.
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    pub struct TestPair;
+
+    impl Tradable for TestEntity {
+        type PairId = TestPair;
+
+        fn pair_id(&self) -> Self::PairId {
+            TestPair
+        }
+    }
+
     #[tokio::test]
     async fn test_inmem_may_exist() {
         let client = InMemoryEntityRepo::new();
@@ -305,12 +388,55 @@ pub(crate) mod tests {
         test_entity_repo_eliminate(client).await;
     }
 
+    #[tokio::test]
+    async fn test_inmem_drop_unconfirmed() {
+        let client = InMemoryEntityRepo::new();
+        test_entity_repo_drop_unconfirmed(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_inmem_prune_predictions() {
+        let client = InMemoryEntityRepo::new();
+        test_entity_repo_prune_predictions(client).await;
+    }
+
     #[tokio::test]
     async fn test_rocksdb_may_exist() {
         let client = rocks_db_client();
         test_entity_repo_may_exist(client).await;
     }
 
+    #[tokio::test]
+    async fn test_rocksdb_may_exist_filter() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let mut client = EntityRepoRocksDB::new(conf.clone());
+        let (box_ids, token_ids, n) = gen_box_and_token_ids();
+        let entities: Vec<_> = (0..n)
+            .map(|i| TestEntity {
+                token_id: token_ids[i],
+                box_id: box_ids[i],
+            })
+            .collect();
+        for entity in &entities {
+            client.put_confirmed(Confirmed(entity.clone())).await;
+        }
+        <EntityRepoRocksDB as EntityRepo<TestEntity>>::invalidate(&mut client, box_ids[0], token_ids[0])
+            .await;
+        client.eliminate(entities[1].clone()).await;
+        assert!(client.may_exist_false_positive_rate().unwrap() < 0.01);
+
+        // Invalidated and eliminated states are kept, so they can still be fetched by version.
+        // Filter is rebuilt from stored states on restart.
+        let restarted = EntityRepoRocksDB::new(conf);
+        for c in [&client, &restarted] {
+            for (sid, entity) in box_ids.iter().zip(&entities) {
+                assert!(<EntityRepoRocksDB as EntityRepo<TestEntity>>::may_exist(c, *sid).await);
+                assert_eq!(c.get_state(*sid).await, Some(entity.clone()));
+            }
+            assert!(!<EntityRepoRocksDB as EntityRepo<TestEntity>>::may_exist(c, BoxId::random()).await);
+        }
+    }
+
     #[tokio::test]
     async fn test_rocksdb_predicted() {
         let client = rocks_db_client();
@@ -341,11 +467,81 @@ pub(crate) mod tests {
         test_entity_repo_eliminate(client).await;
     }
 
+    #[tokio::test]
+    async fn test_rocksdb_drop_unconfirmed() {
+        let client = rocks_db_client();
+        test_entity_repo_drop_unconfirmed(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_rocksdb_prune_predictions() {
+        let client = rocks_db_client();
+        test_entity_repo_prune_predictions(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_cached_predicted() {
+        let client = cached_rocks_db_client();
+        test_entity_repo_predicted(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_cached_confirmed() {
+        let client = cached_rocks_db_client();
+        test_entity_repo_confirmed(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_cached_unconfirmed() {
+        let client = cached_rocks_db_client();
+        test_entity_repo_unconfirmed(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_cached_invalidate() {
+        let client = cached_rocks_db_client();
+        test_entity_repo_invalidate(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_cached_eliminate() {
+        let client = cached_rocks_db_client();
+        test_entity_repo_eliminate(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_cached_drop_unconfirmed() {
+        let client = cached_rocks_db_client();
+        test_entity_repo_drop_unconfirmed(client).await;
+    }
+
+    #[tokio::test]
+    async fn test_cached_prune_predictions() {
+        let client = cached_rocks_db_client();
+        test_entity_repo_prune_predictions(client).await;
+    }
+
+    #[cfg(feature = "send")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_inmem_concurrent() {
+        let client = InMemoryEntityRepo::new();
+        test_entity_repo_concurrent(client).await;
+    }
+
+    #[cfg(feature = "send")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_rocksdb_concurrent() {
+        let client = rocks_db_client();
+        test_entity_repo_concurrent(client).await;
+    }
+
     pub fn rocks_db_client() -> EntityRepoRocksDB {
         let rnd = rand::thread_rng().next_u32();
-        EntityRepoRocksDB {
-            db: Arc::new(rocksdb::OptimisticTransactionDB::open_default(format!("./tmp/{}", rnd)).unwrap()),
-        }
+        EntityRepoRocksDB::new(RocksConfig::new(format!("./tmp/{}", rnd)))
+    }
+
+    fn cached_rocks_db_client() -> CachedEntityRepo<TestEntity, EntityRepoRocksDB> {
+        CachedEntityRepo::wrap(rocks_db_client(), NonZeroUsize::new(8).unwrap())
     }
 
     async fn test_entity_repo_may_exist<C: EntityRepo<TestEntity>>(mut client: C) {
@@ -400,6 +596,17 @@ pub(crate) mod tests {
             let e: Confirmed<TestEntity> = client.get_last_confirmed(token_ids[i]).await.unwrap();
             assert_eq!(e.0, entities[i].0);
         }
+        client
+            .put_unconfirmed(Unconfirmed(TestEntity {
+                token_id: TokenId::random(),
+                box_id: BoxId::random(),
+            }))
+            .await;
+        let all = client.get_all_confirmed().await;
+        assert_eq!(all.len(), n);
+        for e in entities {
+            assert!(all.iter().any(|c| c.0 == e.0));
+        }
     }
 
     async fn test_entity_repo_unconfirmed<C: EntityRepo<TestEntity>>(mut client: C) {
@@ -479,6 +686,100 @@ pub(crate) mod tests {
         }
     }
 
+    async fn test_entity_repo_drop_unconfirmed<C: EntityRepo<TestEntity>>(mut client: C) {
+        let token_id = TokenId::random();
+        let confirmed = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let unconfirmed = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        client.put_confirmed(Confirmed(confirmed.clone())).await;
+        client.put_unconfirmed(Unconfirmed(unconfirmed.clone())).await;
+        let observed_at = client.get_observed_at(unconfirmed.box_id).await;
+        assert!(observed_at.is_some());
+
+        // Only the last unconfirmed state is dropped.
+        <C as EntityRepo<TestEntity>>::drop_unconfirmed(&mut client, token_id, confirmed.box_id).await;
+        let e: Option<Unconfirmed<TestEntity>> = client.get_last_unconfirmed(token_id).await;
+        assert_eq!(e.map(|Unconfirmed(e)| e), Some(unconfirmed.clone()));
+
+        <C as EntityRepo<TestEntity>>::drop_unconfirmed(&mut client, token_id, unconfirmed.box_id).await;
+        let e: Option<Unconfirmed<TestEntity>> = client.get_last_unconfirmed(token_id).await;
+        assert!(e.is_none());
+        let e: Option<Confirmed<TestEntity>> = client.get_last_confirmed(token_id).await;
+        assert_eq!(e.map(|Confirmed(e)| e), Some(confirmed));
+    }
+
+    async fn test_entity_repo_prune_predictions<C: EntityRepo<TestEntity>>(mut client: C) {
+        let token_id = TokenId::random();
+        let states: Vec<_> = (0..3)
+            .map(|_| TestEntity {
+                token_id,
+                box_id: BoxId::random(),
+            })
+            .collect();
+        client.put_confirmed(Confirmed(states[0].clone())).await;
+        client
+            .put_predicted(Traced::new(Predicted(states[1].clone()), Some(states[0].box_id)))
+            .await;
+        client
+            .put_predicted(Traced::new(Predicted(states[2].clone()), Some(states[0].box_id)))
+            .await;
+
+        // Last predicted state is kept as it is not the head of pruned predictions.
+        <C as EntityRepo<TestEntity>>::prune_predictions(&mut client, token_id, vec![states[1].box_id]).await;
+        let e: Option<Predicted<TestEntity>> = client.get_last_predicted(token_id).await;
+        assert_eq!(e.map(|Predicted(e)| e), Some(states[2].clone()));
+        assert_eq!(
+            <C as EntityRepo<TestEntity>>::get_prediction_predecessor(&client, states[1].box_id).await,
+            None
+        );
+
+        <C as EntityRepo<TestEntity>>::prune_predictions(&mut client, token_id, vec![states[2].box_id]).await;
+        let e: Option<Predicted<TestEntity>> = client.get_last_predicted(token_id).await;
+        assert!(e.is_none());
+        // Observation times of pruned states are dropped along with them.
+        for st in &states[1..] {
+            assert_eq!(
+                <C as EntityRepo<TestEntity>>::get_observed_at(&client, st.box_id).await,
+                None
+            );
+        }
+    }
+
+    #[cfg(feature = "send")]
+    async fn test_entity_repo_concurrent<C: EntityRepo<TestEntity> + Send + 'static>(client: C) {
+        use std::sync::Arc;
+
+        let (box_ids, token_ids, _) = gen_box_and_token_ids();
+        let client = Arc::new(tokio::sync::Mutex::new(client));
+        let mut writers = vec![];
+        for (&token_id, &box_id) in token_ids.iter().zip(&box_ids) {
+            let client = Arc::clone(&client);
+            let entity = Confirmed(TestEntity { token_id, box_id });
+            writers.push(tokio::spawn(async move {
+                client.lock().await.put_confirmed(entity).await;
+            }));
+        }
+        for handle in writers {
+            handle.await.unwrap();
+        }
+        let mut readers = vec![];
+        for &token_id in &token_ids {
+            let client = Arc::clone(&client);
+            readers.push(tokio::spawn(async move {
+                let e: Option<Confirmed<TestEntity>> = client.lock().await.get_last_confirmed(token_id).await;
+                e.map(|Confirmed(e)| e.box_id)
+            }));
+        }
+        for (i, handle) in readers.into_iter().enumerate() {
+            assert_eq!(handle.await.unwrap(), Some(box_ids[i]));
+        }
+    }
+
     fn gen_box_and_token_ids() -> (Vec<BoxId>, Vec<TokenId>, usize) {
         let box_ids: Vec<_> = (0..30).into_iter().map(|_| BoxId::random()).collect();
         let token_ids: Vec<_> = (0..30).into_iter().map(|_| TokenId::random()).collect();
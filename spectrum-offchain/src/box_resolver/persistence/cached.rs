@@ -0,0 +1,317 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use lru::LruCache;
+use parking_lot::Mutex;
+
+use crate::box_resolver::persistence::EntityRepo;
+use crate::data::event::{Confirmed, Predicted, Traced, Unconfirmed};
+use crate::data::{EntitySnapshot, Stable};
+use crate::maybe_send::MaybeSend;
+
+/// Hit/miss counters of [CachedEntityRepo].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct CacheState<T: EntitySnapshot> {
+    last_predicted: LruCache<T::StableId, Option<Predicted<T>>>,
+    last_confirmed: LruCache<T::StableId, Option<Confirmed<T>>>,
+    last_unconfirmed: LruCache<T::StableId, Option<Unconfirmed<T>>>,
+    links: LruCache<T::Version, Option<T::Version>>,
+    states: LruCache<T::Version, Option<T>>,
+}
+
+impl<T: EntitySnapshot> CacheState<T> {
+    fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            last_predicted: LruCache::new(capacity),
+            last_confirmed: LruCache::new(capacity),
+            last_unconfirmed: LruCache::new(capacity),
+            links: LruCache::new(capacity),
+            states: LruCache::new(capacity),
+        }
+    }
+
+    fn evict_latest(&mut self, id: &T::StableId) {
+        self.last_predicted.pop(id);
+        self.last_confirmed.pop(id);
+        self.last_unconfirmed.pop(id);
+    }
+
+    fn evict_version(&mut self, sid: &T::Version) {
+        self.links.pop(sid);
+        self.states.pop(sid);
+    }
+}
+
+/// Read-through cache of latest states and prediction links on top of [EntityRepo] `R`.
+/// Cached entries are evicted on every write affecting them, so all writes
+/// must go through this wrapper.
+pub struct CachedEntityRepo<T: EntitySnapshot, R> {
+    inner: R,
+    cache: Mutex<CacheState<T>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<T: EntitySnapshot, R> CachedEntityRepo<T, R> {
+    /// Wrap `repo` keeping up to `capacity` entries of each kind in memory.
+    pub fn wrap(repo: R, capacity: NonZeroUsize) -> Self {
+        Self {
+            inner: repo,
+            cache: Mutex::new(CacheState::new(capacity)),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lookup<K, V, F>(&self, select: F, key: &K) -> Option<V>
+    where
+        F: FnOnce(&mut CacheState<T>) -> &mut LruCache<K, V>,
+        K: std::hash::Hash + Eq,
+        V: Clone,
+    {
+        let mut cache = self.cache.lock();
+        let res = select(&mut cache).get(key).cloned();
+        if res.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<T, R> EntityRepo<T> for CachedEntityRepo<T, R>
+where
+    T: EntitySnapshot + Clone + MaybeSend,
+    T::StableId: MaybeSend,
+    T::Version: MaybeSend,
+    R: EntityRepo<T> + MaybeSend,
+{
+    async fn get_prediction_predecessor<'a>(&self, id: T::Version) -> Option<T::Version>
+    where
+        <T as EntitySnapshot>::Version: 'a,
+    {
+        if let Some(res) = self.lookup(|c| &mut c.links, &id) {
+            return res;
+        }
+        let res = self.inner.get_prediction_predecessor(id).await;
+        self.cache.lock().links.put(id, res);
+        res
+    }
+
+    async fn get_last_predicted<'a>(&self, id: T::StableId) -> Option<Predicted<T>>
+    where
+        <T as Stable>::StableId: 'a,
+    {
+        if let Some(res) = self.lookup(|c| &mut c.last_predicted, &id) {
+            return res;
+        }
+        let res = self.inner.get_last_predicted(id).await;
+        self.cache.lock().last_predicted.put(id, res.clone());
+        res
+    }
+
+    async fn get_last_confirmed<'a>(&self, id: T::StableId) -> Option<Confirmed<T>>
+    where
+        <T as Stable>::StableId: 'a,
+    {
+        if let Some(res) = self.lookup(|c| &mut c.last_confirmed, &id) {
+            return res;
+        }
+        let res = self.inner.get_last_confirmed(id).await;
+        self.cache.lock().last_confirmed.put(id, res.clone());
+        res
+    }
+
+    async fn get_last_unconfirmed<'a>(&self, id: T::StableId) -> Option<Unconfirmed<T>>
+    where
+        <T as Stable>::StableId: 'a,
+    {
+        if let Some(res) = self.lookup(|c| &mut c.last_unconfirmed, &id) {
+            return res;
+        }
+        let res = self.inner.get_last_unconfirmed(id).await;
+        self.cache.lock().last_unconfirmed.put(id, res.clone());
+        res
+    }
+
+    async fn get_all_confirmed(&self) -> Vec<Confirmed<T>> {
+        self.inner.get_all_confirmed().await
+    }
+
+    async fn get_observed_at<'a>(&self, sid: T::Version) -> Option<u64>
+    where
+        <T as EntitySnapshot>::Version: 'a,
+    {
+        self.inner.get_observed_at(sid).await
+    }
+
+    async fn put_predicted<'a>(&mut self, entity: Traced<Predicted<T>>)
+    where
+        Traced<Predicted<T>>: 'a,
+    {
+        let eid = entity.state.stable_id();
+        let sid = entity.state.version();
+        self.inner.put_predicted(entity).await;
+        let mut cache = self.cache.lock();
+        cache.evict_latest(&eid);
+        cache.evict_version(&sid);
+    }
+
+    async fn put_confirmed<'a>(&mut self, entity: Confirmed<T>)
+    where
+        Traced<Predicted<T>>: 'a,
+    {
+        let eid = entity.stable_id();
+        let sid = entity.version();
+        self.inner.put_confirmed(entity).await;
+        let mut cache = self.cache.lock();
+        cache.evict_latest(&eid);
+        cache.evict_version(&sid);
+    }
+
+    async fn put_unconfirmed<'a>(&mut self, entity: Unconfirmed<T>)
+    where
+        Traced<Predicted<T>>: 'a,
+    {
+        let eid = entity.stable_id();
+        let sid = entity.version();
+        self.inner.put_unconfirmed(entity).await;
+        let mut cache = self.cache.lock();
+        cache.evict_latest(&eid);
+        cache.evict_version(&sid);
+    }
+
+    async fn invalidate<'a>(&mut self, sid: T::Version, eid: T::StableId)
+    where
+        <T as EntitySnapshot>::Version: 'a,
+        <T as Stable>::StableId: 'a,
+    {
+        self.inner.invalidate(sid, eid).await;
+        let mut cache = self.cache.lock();
+        cache.evict_latest(&eid);
+        cache.evict_version(&sid);
+    }
+
+    async fn eliminate<'a>(&mut self, entity: T)
+    where
+        T: 'a,
+    {
+        let eid = entity.stable_id();
+        let sid = entity.version();
+        self.inner.eliminate(entity).await;
+        let mut cache = self.cache.lock();
+        cache.evict_latest(&eid);
+        cache.evict_version(&sid);
+    }
+
+    async fn drop_unconfirmed<'a>(&mut self, eid: T::StableId, sid: T::Version)
+    where
+        <T as EntitySnapshot>::Version: 'a,
+        <T as Stable>::StableId: 'a,
+    {
+        self.inner.drop_unconfirmed(eid, sid).await;
+        self.cache.lock().evict_latest(&eid);
+    }
+
+    async fn prune_predictions<'a>(&mut self, eid: T::StableId, sids: Vec<T::Version>)
+    where
+        <T as EntitySnapshot>::Version: 'a,
+        <T as Stable>::StableId: 'a,
+    {
+        self.inner.prune_predictions(eid, sids.clone()).await;
+        let mut cache = self.cache.lock();
+        cache.evict_latest(&eid);
+        for sid in sids {
+            cache.links.pop(&sid);
+        }
+    }
+
+    async fn may_exist<'a>(&self, sid: T::Version) -> bool
+    where
+        <T as EntitySnapshot>::Version: 'a,
+    {
+        if let Some(Some(_)) = self.cache.lock().states.peek(&sid) {
+            return true;
+        }
+        self.inner.may_exist(sid).await
+    }
+
+    async fn get_state<'a>(&self, sid: T::Version) -> Option<T>
+    where
+        <T as EntitySnapshot>::Version: 'a,
+    {
+        if let Some(res) = self.lookup(|c| &mut c.states, &sid) {
+            return res;
+        }
+        let res = self.inner.get_state(sid).await;
+        self.cache.lock().states.put(sid, res.clone());
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use crate::box_resolver::persistence::cached::{CacheStats, CachedEntityRepo};
+    use crate::box_resolver::persistence::inmemory::InMemoryEntityRepo;
+    use crate::box_resolver::persistence::tests::*;
+    use crate::box_resolver::persistence::EntityRepo;
+    use crate::data::event::{Confirmed, Predicted, Traced};
+
+    #[tokio::test]
+    async fn repeated_reads_hit_cache() {
+        let mut repo = CachedEntityRepo::wrap(InMemoryEntityRepo::new(), NonZeroUsize::new(16).unwrap());
+        let token_id = TokenId::random();
+        let entity = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        repo.put_confirmed(Confirmed(entity.clone())).await;
+        for _ in 0..3 {
+            let res: Option<Confirmed<TestEntity>> = repo.get_last_confirmed(token_id).await;
+            assert_eq!(res.map(|Confirmed(e)| e), Some(entity.clone()));
+        }
+        assert_eq!(repo.stats(), CacheStats { hits: 2, misses: 1 });
+    }
+
+    #[tokio::test]
+    async fn writes_evict_cached_entries() {
+        let mut repo = CachedEntityRepo::wrap(InMemoryEntityRepo::new(), NonZeroUsize::new(16).unwrap());
+        let token_id = TokenId::random();
+        let e1 = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        let e2 = TestEntity {
+            token_id,
+            box_id: BoxId::random(),
+        };
+        repo.put_confirmed(Confirmed(e1.clone())).await;
+        let pred: Option<Predicted<TestEntity>> = repo.get_last_predicted(token_id).await;
+        assert!(pred.is_none());
+        assert_eq!(repo.get_prediction_predecessor(e2.box_id).await, None);
+        repo.put_predicted(Traced::new(Predicted(e2.clone()), Some(e1.box_id)))
+            .await;
+        let pred: Option<Predicted<TestEntity>> = repo.get_last_predicted(token_id).await;
+        assert_eq!(pred, Some(Predicted(e2.clone())));
+        assert_eq!(repo.get_prediction_predecessor(e2.box_id).await, Some(e1.box_id));
+        assert_eq!(repo.stats(), CacheStats { hits: 0, misses: 4 });
+    }
+}
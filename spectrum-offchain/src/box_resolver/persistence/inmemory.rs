@@ -4,23 +4,36 @@ use std::fmt::Debug;
 use async_trait::async_trait;
 use log::warn;
 
-use crate::box_resolver::persistence::EntityRepo;
+use crate::box_resolver::history::{EntityHistory, VersionRecord};
+use crate::box_resolver::persistence::{unix_time_millis, EntityRepo};
 use crate::data::event::{Confirmed, Predicted, Traced, Unconfirmed};
 use crate::data::{EntitySnapshot, Stable};
+use crate::maybe_send::MaybeSend;
 
+/// In-memory entity repo, `TTxHash` is the type of transaction hashes kept in the history of entities.
 #[derive(Debug)]
-pub struct InMemoryEntityRepo<T: EntitySnapshot> {
+pub struct InMemoryEntityRepo<T: EntitySnapshot, TTxHash = ()> {
     store: HashMap<T::Version, T>,
     index: HashMap<InMemoryIndexKey, T::Version>,
     links: HashMap<T::Version, T::Version>,
+    observed_at: HashMap<T::Version, u64>,
+    history: HashMap<InMemoryIndexKey, Vec<VersionRecord<T::Version, TTxHash>>>,
 }
 
 impl<T: EntitySnapshot> InMemoryEntityRepo<T> {
     pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T: EntitySnapshot, TTxHash> Default for InMemoryEntityRepo<T, TTxHash> {
+    fn default() -> Self {
         Self {
             store: HashMap::new(),
             links: HashMap::new(),
             index: HashMap::new(),
+            observed_at: HashMap::new(),
+            history: HashMap::new(),
         }
     }
 }
@@ -32,12 +45,15 @@ const PREDICTION_LINK_PREFIX: u8 = 1u8;
 const LAST_PREDICTED_PREFIX: u8 = 2u8;
 const LAST_CONFIRMED_PREFIX: u8 = 3u8;
 const LAST_UNCONFIRMED_PREFIX: u8 = 4u8;
+const HISTORY_PREFIX: u8 = 5u8;
 
-#[async_trait(?Send)]
-impl<T> EntityRepo<T> for InMemoryEntityRepo<T>
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<T, TTxHash> EntityRepo<T> for InMemoryEntityRepo<T, TTxHash>
 where
-    T: EntitySnapshot + Clone + Send + 'static,
-    <T as EntitySnapshot>::Version: Copy + Send + Debug + 'static,
+    T: EntitySnapshot + Clone + Send + MaybeSend + 'static,
+    TTxHash: Send + MaybeSend + 'static,
+    <T as EntitySnapshot>::Version: Copy + Send + MaybeSend + Debug + 'static,
     <T as Stable>::StableId: Copy + Send + Into<[u8; 60]> + 'static,
 {
     async fn get_prediction_predecessor<'a>(&self, id: T::Version) -> Option<T::Version>
@@ -80,6 +96,22 @@ where
             .map(|e| Unconfirmed(e.clone()))
     }
 
+    async fn get_all_confirmed(&self) -> Vec<Confirmed<T>> {
+        self.index
+            .iter()
+            .filter(|(key, _)| key[0] == LAST_CONFIRMED_PREFIX)
+            .filter_map(|(_, sid)| self.store.get(sid))
+            .map(|e| Confirmed(e.clone()))
+            .collect()
+    }
+
+    async fn get_observed_at<'a>(&self, sid: T::Version) -> Option<u64>
+    where
+        <T as EntitySnapshot>::Version: 'a,
+    {
+        self.observed_at.get(&sid).copied()
+    }
+
     async fn put_predicted<'a>(
         &mut self,
         Traced {
@@ -94,6 +126,7 @@ where
         if let Some(prev_sid) = prev_state_id {
             self.links.insert(entity.version(), prev_sid);
         }
+        self.observed_at.insert(entity.version(), unix_time_millis());
         self.store.insert(entity.version(), entity);
     }
 
@@ -112,6 +145,7 @@ where
     {
         let index_key = index_key(LAST_UNCONFIRMED_PREFIX, entity.stable_id());
         self.index.insert(index_key, entity.version());
+        self.observed_at.insert(entity.version(), unix_time_millis());
         self.store.insert(entity.version(), entity);
     }
 
@@ -134,6 +168,7 @@ where
         self.index.remove(&last_unconfirmed_index_key);
         self.links.remove(&sid);
         self.store.remove(&sid);
+        self.observed_at.remove(&sid);
     }
 
     async fn eliminate<'a>(&mut self, entity: T)
@@ -150,6 +185,36 @@ where
         self.index.remove(&last_unconfirmed_index_key);
         self.links.remove(&sid);
         self.store.remove(&sid);
+        self.observed_at.remove(&sid);
+    }
+
+    async fn drop_unconfirmed<'a>(&mut self, eid: T::StableId, sid: T::Version)
+    where
+        <T as EntitySnapshot>::Version: 'a,
+        <T as Stable>::StableId: 'a,
+    {
+        let last_unconfirmed_index_key = index_key(LAST_UNCONFIRMED_PREFIX, eid);
+        if self.index.get(&last_unconfirmed_index_key) == Some(&sid) {
+            self.index.remove(&last_unconfirmed_index_key);
+            self.observed_at.remove(&sid);
+        }
+    }
+
+    async fn prune_predictions<'a>(&mut self, eid: T::StableId, sids: Vec<T::Version>)
+    where
+        <T as EntitySnapshot>::Version: 'a,
+        <T as Stable>::StableId: 'a,
+    {
+        let last_predicted_index_key = index_key(LAST_PREDICTED_PREFIX, eid);
+        if let Some(head_sid) = sids.first() {
+            if self.index.get(&last_predicted_index_key) == Some(head_sid) {
+                self.index.remove(&last_predicted_index_key);
+            }
+        }
+        for sid in sids {
+            self.links.remove(&sid);
+            self.observed_at.remove(&sid);
+        }
     }
 
     async fn may_exist<'a>(&self, sid: T::Version) -> bool
@@ -167,6 +232,58 @@ where
     }
 }
 
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<T, TTxHash> EntityHistory<T, TTxHash> for InMemoryEntityRepo<T, TTxHash>
+where
+    T: EntitySnapshot + Send + MaybeSend + 'static,
+    <T as EntitySnapshot>::Version: Send + MaybeSend + 'static,
+    <T as Stable>::StableId: Copy + Send + Into<[u8; 60]> + 'static,
+    TTxHash: Clone + Send + MaybeSend + 'static,
+{
+    async fn record_version(&mut self, eid: T::StableId, record: VersionRecord<T::Version, TTxHash>) {
+        let history = self.history.entry(index_key(HISTORY_PREFIX, eid)).or_default();
+        if history.iter().all(|r| r.version != record.version) {
+            history.push(record);
+        }
+    }
+
+    async fn get_history(
+        &self,
+        eid: T::StableId,
+        from: T::Version,
+        to: T::Version,
+    ) -> Vec<VersionRecord<T::Version, TTxHash>> {
+        let history = self
+            .history
+            .get(&index_key(HISTORY_PREFIX, eid))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let position = |version| history.iter().position(|r| r.version == version);
+        match (position(from), position(to)) {
+            (Some(from_ix), Some(to_ix)) if from_ix <= to_ix => history[from_ix..=to_ix].to_vec(),
+            _ => vec![],
+        }
+    }
+
+    async fn get_last_versions(&self, eid: T::StableId, n: usize) -> Vec<VersionRecord<T::Version, TTxHash>> {
+        let history = self
+            .history
+            .get(&index_key(HISTORY_PREFIX, eid))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        history[history.len().saturating_sub(n)..].to_vec()
+    }
+
+    async fn rollback_history(&mut self, eid: T::StableId, version: T::Version) {
+        if let Some(history) = self.history.get_mut(&index_key(HISTORY_PREFIX, eid)) {
+            if let Some(ix) = history.iter().position(|r| r.version == version) {
+                history.truncate(ix);
+            }
+        }
+    }
+}
+
 pub fn index_key<T: Into<[u8; 60]>>(prefix: u8, id: T) -> InMemoryIndexKey {
     let mut arr = [prefix; 61];
     let raw_id: [u8; 60] = id.into();
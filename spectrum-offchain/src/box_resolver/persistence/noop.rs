@@ -7,7 +7,8 @@ use crate::data::{EntitySnapshot, Stable};
 #[derive(Debug)]
 pub struct NoopEntityRepo;
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 impl<T> EntityRepo<T> for NoopEntityRepo
 where
     T: EntitySnapshot + Clone + Send + 'static,
@@ -42,6 +43,17 @@ where
         None
     }
 
+    async fn get_all_confirmed(&self) -> Vec<Confirmed<T>> {
+        Vec::new()
+    }
+
+    async fn get_observed_at<'a>(&self, _sid: T::Version) -> Option<u64>
+    where
+        <T as EntitySnapshot>::Version: 'a,
+    {
+        None
+    }
+
     async fn put_predicted<'a>(&mut self, _entity: Traced<Predicted<T>>)
     where
         Traced<Predicted<T>>: 'a,
@@ -73,6 +85,20 @@ where
     {
     }
 
+    async fn drop_unconfirmed<'a>(&mut self, _eid: T::StableId, _sid: T::Version)
+    where
+        <T as EntitySnapshot>::Version: 'a,
+        <T as Stable>::StableId: 'a,
+    {
+    }
+
+    async fn prune_predictions<'a>(&mut self, _eid: T::StableId, _sids: Vec<T::Version>)
+    where
+        <T as EntitySnapshot>::Version: 'a,
+        <T as Stable>::StableId: 'a,
+    {
+    }
+
     async fn may_exist<'a>(&self, _sid: T::Version) -> bool
     where
         <T as EntitySnapshot>::Version: 'a,
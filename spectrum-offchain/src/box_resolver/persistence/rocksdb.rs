@@ -3,26 +3,128 @@ use std::sync::Arc;
 
 use async_std::task::spawn_blocking;
 use async_trait::async_trait;
-use log::warn;
+use log::{info, warn};
+use parking_lot::Mutex;
+use rocksdb::{Direction, IteratorMode, WriteBatch};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::binary::prefixed_key;
-use crate::box_resolver::persistence::EntityRepo;
+use crate::binary::{prefixed_key, raw_prefixed_key};
+use crate::bloom_filter::{BloomFilter, BloomFilterConfig};
+use crate::box_resolver::history::{EntityHistory, VersionRecord};
+use crate::box_resolver::persistence::{unix_time_millis, EntityRepo};
 use crate::box_resolver::{Predicted, Traced};
 use crate::data::event::{Confirmed, Unconfirmed};
 use crate::data::{EntitySnapshot, Stable};
-use crate::rocks::RocksConfig;
+use crate::rocks;
+use crate::rocks::schema::{check, migrate, Migration, Schema};
+use crate::rocks::{RocksConfig, RocksDB};
 
 pub struct EntityRepoRocksDB {
-    pub db: Arc<rocksdb::OptimisticTransactionDB>,
+    pub db: Arc<RocksDB>,
+    /// Column family the entities are kept in.
+    pub cf: String,
+    /// In-memory filter of keys of stored states answering [EntityRepo::may_exist] without disk reads.
+    /// Not maintained by secondary instances, as they don't observe writes of the primary.
+    filter: Option<Arc<Mutex<BloomFilter>>>,
+}
+
+const SCHEMA: Schema = Schema {
+    store: "entity_repo",
+    version: 2,
+    migrations: &[Migration {
+        from_version: 1,
+        description: "stamp last unconfirmed and predicted states with observation time",
+        apply: stamp_observation_time,
+    }],
+};
+
+/// States stored before observation times were recorded would be discarded as expired
+/// by the first read, so they are considered observed at the time of the upgrade instead.
+fn stamp_observation_time(db: &RocksDB, cf: &str, batch: &mut WriteBatch) -> Result<(), rocksdb::Error> {
+    let cf = db.cf_handle(cf).unwrap();
+    let observed_at_bytes = bincode::serialize(&unix_time_millis()).unwrap();
+    for prefix in [LAST_UNCONFIRMED_PREFIX, LAST_PREDICTED_PREFIX] {
+        let prefix = bincode::serialize(prefix).unwrap();
+        for item in db.iterator_cf(&cf, IteratorMode::From(&prefix, Direction::Forward)) {
+            let (key, sid_bytes) = item?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let observed_at_key = raw_prefixed_key(OBSERVED_AT_PREFIX, &sid_bytes);
+            if db.get_cf(&cf, &observed_at_key)?.is_none() {
+                batch.put_cf(&cf, observed_at_key, &observed_at_bytes);
+            }
+        }
+    }
+    Ok(())
 }
 
 impl EntityRepoRocksDB {
     pub fn new(conf: RocksConfig) -> Self {
+        Self::with_filter(conf, BloomFilterConfig::default())
+    }
+
+    /// Open the store sizing the filter of known versions with `filter_conf`.
+    /// The filter is rebuilt from the stored states.
+    pub fn with_filter(conf: RocksConfig, filter_conf: BloomFilterConfig) -> Self {
+        let db = rocks::open(&conf);
+        let cf = conf.column_family().to_string();
+        migrate(&db, &cf, &SCHEMA).unwrap();
+        let filter = build_filter(&db, &cf, filter_conf);
+        info!(
+            target: "entity_repo",
+            "Filter of known versions built from {} states, estimated false-positive rate {:.5}",
+            filter.len(),
+            filter.false_positive_rate()
+        );
         Self {
-            db: Arc::new(rocksdb::OptimisticTransactionDB::open_default(conf.db_path).unwrap()),
+            db,
+            cf,
+            filter: Some(Arc::new(Mutex::new(filter))),
+        }
+    }
+
+    /// Estimated false-positive rate of [EntityRepo::may_exist].
+    /// Not available for secondary instances which check versions on disk.
+    pub fn may_exist_false_positive_rate(&self) -> Option<f64> {
+        self.filter
+            .as_ref()
+            .map(|filter| filter.lock().false_positive_rate())
+    }
+
+    /// Open read-only secondary instance of the store which may be in use by another process.
+    /// Writes through a secondary instance fail.
+    /// Run [rocks::catch_up_stream] on `db` to observe updates made by the primary instance.
+    pub fn open_as_secondary(conf: RocksConfig, secondary_path: &str) -> Self {
+        let db = rocks::open_as_secondary(&conf, secondary_path);
+        let cf = conf.column_family().to_string();
+        check(&db, &cf, &SCHEMA).unwrap();
+        Self { db, cf, filter: None }
+    }
+}
+
+/// Build filter of keys of all states stored in the column family `cf` of the `db`.
+fn build_filter(db: &RocksDB, cf: &str, conf: BloomFilterConfig) -> BloomFilter {
+    let cf = db.cf_handle(cf).unwrap();
+    let prefix = bincode::serialize(STATE_PREFIX).unwrap();
+    let mut filter = BloomFilter::new(conf);
+    for kv in db.iterator_cf(&cf, IteratorMode::From(&prefix, Direction::Forward)) {
+        let (key, _) = kv.unwrap();
+        if !key.starts_with(&prefix) {
+            break;
         }
+        filter.insert(&*key);
+    }
+    filter
+}
+
+/// Add state `key` to the `filter`.
+/// Called before the state is written, so that the filter never misses stored states.
+/// States are never deleted, so the filter doesn't need to support removals.
+fn filter_insert(filter: &Option<Arc<Mutex<BloomFilter>>>, key: &[u8]) {
+    if let Some(filter) = filter {
+        filter.lock().insert(key);
     }
 }
 
@@ -31,8 +133,13 @@ const PREDICTION_LINK_PREFIX: &str = "prediction:link";
 const LAST_PREDICTED_PREFIX: &str = "predicted:last";
 const LAST_CONFIRMED_PREFIX: &str = "confirmed:last";
 const LAST_UNCONFIRMED_PREFIX: &str = "unconfirmed:last";
+const OBSERVED_AT_PREFIX: &str = "observed:at";
+const HISTORY_LEN_PREFIX: &str = "history:len";
+const HISTORY_ENTRY_PREFIX: &str = "history:entry";
+const HISTORY_POS_PREFIX: &str = "history:pos";
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 impl<TEntity> EntityRepo<TEntity> for EntityRepoRocksDB
 where
     TEntity: EntitySnapshot + Clone + Serialize + DeserializeOwned + Send + 'static,
@@ -47,11 +154,13 @@ where
         <TEntity as EntitySnapshot>::Version: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let link_key = prefixed_key(PREDICTION_LINK_PREFIX, &sid);
         spawn_blocking(move || {
-            db.get(link_key)
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, link_key)
                 .unwrap()
-                .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                .and_then(|bytes| decode(PREDICTION_LINK_PREFIX, &bytes))
         })
         .await
     }
@@ -61,23 +170,25 @@ where
         <TEntity as Stable>::StableId: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let index_key = prefixed_key(LAST_PREDICTED_PREFIX, &id);
         spawn_blocking(move || {
-            db.get(index_key)
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, index_key)
                 .unwrap()
-                .and_then(|bytes| bincode::deserialize::<'_, TEntity::Version>(&bytes).ok())
+                .and_then(|bytes| decode::<TEntity::Version>(LAST_PREDICTED_PREFIX, &bytes))
                 .and_then(|sid| {
                     if db
-                        .get(prefixed_key(PREDICTION_LINK_PREFIX, &sid))
+                        .get_cf(&cf, prefixed_key(PREDICTION_LINK_PREFIX, &sid))
                         .unwrap()
                         .is_some()
                     {
-                        db.get(prefixed_key(STATE_PREFIX, &sid)).unwrap()
+                        db.get_cf(&cf, prefixed_key(STATE_PREFIX, &sid)).unwrap()
                     } else {
                         None
                     }
                 })
-                .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                .and_then(|bytes| decode(STATE_PREFIX, &bytes))
                 .map(Predicted)
         })
         .await
@@ -88,13 +199,15 @@ where
         <TEntity as Stable>::StableId: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let index_key = prefixed_key(LAST_CONFIRMED_PREFIX, &id);
         spawn_blocking(move || {
-            db.get(index_key)
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, index_key)
                 .unwrap()
-                .and_then(|bytes| bincode::deserialize::<'_, TEntity::Version>(&bytes).ok())
-                .and_then(|sid| db.get(prefixed_key(STATE_PREFIX, &sid)).unwrap())
-                .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                .and_then(|bytes| decode::<TEntity::Version>(LAST_CONFIRMED_PREFIX, &bytes))
+                .and_then(|sid| db.get_cf(&cf, prefixed_key(STATE_PREFIX, &sid)).unwrap())
+                .and_then(|bytes| decode(STATE_PREFIX, &bytes))
                 .map(Confirmed)
         })
         .await
@@ -108,18 +221,60 @@ where
         <TEntity as Stable>::StableId: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let index_key = prefixed_key(LAST_UNCONFIRMED_PREFIX, &id);
         spawn_blocking(move || {
-            db.get(index_key)
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, index_key)
                 .unwrap()
-                .and_then(|bytes| bincode::deserialize::<'_, TEntity::Version>(&bytes).ok())
-                .and_then(|sid| db.get(prefixed_key(STATE_PREFIX, &sid)).unwrap())
-                .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                .and_then(|bytes| decode::<TEntity::Version>(LAST_UNCONFIRMED_PREFIX, &bytes))
+                .and_then(|sid| db.get_cf(&cf, prefixed_key(STATE_PREFIX, &sid)).unwrap())
+                .and_then(|bytes| decode(STATE_PREFIX, &bytes))
                 .map(Unconfirmed)
         })
         .await
     }
 
+    async fn get_all_confirmed(&self) -> Vec<Confirmed<TEntity>> {
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            let prefix = bincode::serialize(LAST_CONFIRMED_PREFIX).unwrap();
+            let mut entities = Vec::new();
+            for kv in db.iterator_cf(&cf, IteratorMode::From(&prefix, Direction::Forward)) {
+                let (key, value) = kv.unwrap();
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+                if let Some(entity) = decode::<TEntity::Version>(LAST_CONFIRMED_PREFIX, &value)
+                    .and_then(|sid| db.get_cf(&cf, prefixed_key(STATE_PREFIX, &sid)).unwrap())
+                    .and_then(|bytes| decode(STATE_PREFIX, &bytes))
+                {
+                    entities.push(Confirmed(entity));
+                }
+            }
+            entities
+        })
+        .await
+    }
+
+    async fn get_observed_at<'a>(&self, sid: <TEntity as EntitySnapshot>::Version) -> Option<u64>
+    where
+        <TEntity as EntitySnapshot>::Version: 'a,
+    {
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        let observed_at_key = prefixed_key(OBSERVED_AT_PREFIX, &sid);
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, observed_at_key)
+                .unwrap()
+                .and_then(|bytes| decode(OBSERVED_AT_PREFIX, &bytes))
+        })
+        .await
+    }
+
     async fn put_predicted<'a>(
         &mut self,
         Traced {
@@ -130,20 +285,27 @@ where
         Traced<Predicted<TEntity>>: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let state_id_bytes = bincode::serialize(&entity.version()).unwrap();
         let state_key = prefixed_key(STATE_PREFIX, &entity.version());
         let state_bytes = bincode::serialize(&entity).unwrap();
         let index_key = prefixed_key(LAST_PREDICTED_PREFIX, &entity.stable_id());
         let link_key = prefixed_key(PREDICTION_LINK_PREFIX, &entity.version());
+        let observed_at_key = prefixed_key(OBSERVED_AT_PREFIX, &entity.version());
+        let observed_at_bytes = bincode::serialize(&unix_time_millis()).unwrap();
+        let filter = self.filter.clone();
         spawn_blocking(move || {
-            let tx = db.transaction();
-            tx.put(state_key, state_bytes).unwrap();
-            tx.put(index_key, state_id_bytes).unwrap();
+            let cf = db.cf_handle(&cf).unwrap();
+            filter_insert(&filter, &state_key);
+            let mut batch = WriteBatch::default();
+            batch.put_cf(&cf, state_key, state_bytes);
+            batch.put_cf(&cf, index_key, state_id_bytes);
+            batch.put_cf(&cf, observed_at_key, observed_at_bytes);
             if let Some(prev_sid) = prev_state_id {
                 let prev_state_id_bytes = bincode::serialize(&prev_sid).unwrap();
-                tx.put(link_key, prev_state_id_bytes).unwrap();
+                batch.put_cf(&cf, link_key, prev_state_id_bytes);
             }
-            tx.commit().unwrap();
+            db.write(batch).unwrap();
         })
         .await
     }
@@ -153,15 +315,19 @@ where
         Traced<Predicted<TEntity>>: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let state_id_bytes = bincode::serialize(&entity.version()).unwrap();
         let state_key = prefixed_key(STATE_PREFIX, &entity.version());
         let state_bytes = bincode::serialize(&entity).unwrap();
         let index_key = prefixed_key(LAST_CONFIRMED_PREFIX, &entity.stable_id());
+        let filter = self.filter.clone();
         spawn_blocking(move || {
-            let tx = db.transaction();
-            tx.put(state_key, state_bytes).unwrap();
-            tx.put(index_key, state_id_bytes).unwrap();
-            tx.commit().unwrap();
+            let cf = db.cf_handle(&cf).unwrap();
+            filter_insert(&filter, &state_key);
+            let mut batch = WriteBatch::default();
+            batch.put_cf(&cf, state_key, state_bytes);
+            batch.put_cf(&cf, index_key, state_id_bytes);
+            db.write(batch).unwrap();
         })
         .await
     }
@@ -171,15 +337,22 @@ where
         Traced<Predicted<TEntity>>: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let state_id_bytes = bincode::serialize(&entity.version()).unwrap();
         let state_key = prefixed_key(STATE_PREFIX, &entity.version());
         let state_bytes = bincode::serialize(&entity).unwrap();
         let index_key = prefixed_key(LAST_UNCONFIRMED_PREFIX, &entity.stable_id());
+        let observed_at_key = prefixed_key(OBSERVED_AT_PREFIX, &entity.version());
+        let observed_at_bytes = bincode::serialize(&unix_time_millis()).unwrap();
+        let filter = self.filter.clone();
         spawn_blocking(move || {
-            let tx = db.transaction();
-            tx.put(state_key, state_bytes).unwrap();
-            tx.put(index_key, state_id_bytes).unwrap();
-            tx.commit().unwrap();
+            let cf = db.cf_handle(&cf).unwrap();
+            filter_insert(&filter, &state_key);
+            let mut batch = WriteBatch::default();
+            batch.put_cf(&cf, state_key, state_bytes);
+            batch.put_cf(&cf, index_key, state_id_bytes);
+            batch.put_cf(&cf, observed_at_key, observed_at_bytes);
+            db.write(batch).unwrap();
         })
         .await
     }
@@ -199,22 +372,26 @@ where
             )
             .await;
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let link_key = prefixed_key(PREDICTION_LINK_PREFIX, &sid);
         let last_confirmed_index_key = prefixed_key(LAST_CONFIRMED_PREFIX, &eid);
         let last_unconfirmed_index_key = prefixed_key(LAST_UNCONFIRMED_PREFIX, &eid);
+        let observed_at_key = prefixed_key(OBSERVED_AT_PREFIX, &sid);
         spawn_blocking(move || {
-            let tx = db.transaction();
+            let cf = db.cf_handle(&cf).unwrap();
+            let mut batch = WriteBatch::default();
             if let Some(predecessor) = predecessor {
                 warn!(target: "offchain", "invalidate box: rollback to {:?}", predecessor);
                 warn!("invalidate box: rollback to {:?}", predecessor);
                 let predecessor_bytes = bincode::serialize(&predecessor).unwrap();
-                tx.put(last_confirmed_index_key, predecessor_bytes).unwrap();
+                batch.put_cf(&cf, last_confirmed_index_key, predecessor_bytes);
             } else {
-                tx.delete(last_confirmed_index_key).unwrap();
+                batch.delete_cf(&cf, last_confirmed_index_key);
             }
-            tx.delete(link_key).unwrap();
-            tx.delete(last_unconfirmed_index_key).unwrap();
-            tx.commit().unwrap();
+            batch.delete_cf(&cf, link_key);
+            batch.delete_cf(&cf, last_unconfirmed_index_key);
+            batch.delete_cf(&cf, observed_at_key);
+            db.write(batch).unwrap();
         })
         .await
     }
@@ -228,15 +405,83 @@ where
 
         let last_confirmed_index_key = prefixed_key(LAST_CONFIRMED_PREFIX, &entity.stable_id());
         let last_unconfirmed_index_key = prefixed_key(LAST_UNCONFIRMED_PREFIX, &entity.stable_id());
+        let observed_at_key = prefixed_key(OBSERVED_AT_PREFIX, &entity.version());
 
         let db = self.db.clone();
+        let cf = self.cf.clone();
         spawn_blocking(move || {
-            let tx = db.transaction();
-            tx.delete(link_key).unwrap();
-            tx.delete(last_predicted_index_key).unwrap();
-            tx.delete(last_confirmed_index_key).unwrap();
-            tx.delete(last_unconfirmed_index_key).unwrap();
-            tx.commit().unwrap();
+            let cf = db.cf_handle(&cf).unwrap();
+            let mut batch = WriteBatch::default();
+            batch.delete_cf(&cf, link_key);
+            batch.delete_cf(&cf, last_predicted_index_key);
+            batch.delete_cf(&cf, last_confirmed_index_key);
+            batch.delete_cf(&cf, last_unconfirmed_index_key);
+            batch.delete_cf(&cf, observed_at_key);
+            db.write(batch).unwrap();
+        })
+        .await
+    }
+
+    async fn drop_unconfirmed<'a>(
+        &mut self,
+        eid: <TEntity as Stable>::StableId,
+        sid: <TEntity as EntitySnapshot>::Version,
+    ) where
+        <TEntity as EntitySnapshot>::Version: 'a,
+        <TEntity as Stable>::StableId: 'a,
+    {
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        let state_id_bytes = bincode::serialize(&sid).unwrap();
+        let last_unconfirmed_index_key = prefixed_key(LAST_UNCONFIRMED_PREFIX, &eid);
+        let observed_at_key = prefixed_key(OBSERVED_AT_PREFIX, &sid);
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            if db.get_cf(&cf, &last_unconfirmed_index_key).unwrap() == Some(state_id_bytes) {
+                let mut batch = WriteBatch::default();
+                batch.delete_cf(&cf, last_unconfirmed_index_key);
+                batch.delete_cf(&cf, observed_at_key);
+                db.write(batch).unwrap();
+            }
+        })
+        .await
+    }
+
+    async fn prune_predictions<'a>(
+        &mut self,
+        eid: <TEntity as Stable>::StableId,
+        sids: Vec<<TEntity as EntitySnapshot>::Version>,
+    ) where
+        <TEntity as EntitySnapshot>::Version: 'a,
+        <TEntity as Stable>::StableId: 'a,
+    {
+        let last_predicted_index_key = prefixed_key(LAST_PREDICTED_PREFIX, &eid);
+        let head_sid_bytes = sids.first().map(|sid| bincode::serialize(sid).unwrap());
+        let link_keys: Vec<_> = sids
+            .iter()
+            .map(|sid| prefixed_key(PREDICTION_LINK_PREFIX, sid))
+            .collect();
+        let observed_at_keys: Vec<_> = sids
+            .iter()
+            .map(|sid| prefixed_key(OBSERVED_AT_PREFIX, sid))
+            .collect();
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            let mut batch = WriteBatch::default();
+            if let Some(head_sid_bytes) = head_sid_bytes {
+                if db.get_cf(&cf, &last_predicted_index_key).unwrap() == Some(head_sid_bytes) {
+                    batch.delete_cf(&cf, last_predicted_index_key);
+                }
+            }
+            for link_key in link_keys {
+                batch.delete_cf(&cf, link_key);
+            }
+            for observed_at_key in observed_at_keys {
+                batch.delete_cf(&cf, observed_at_key);
+            }
+            db.write(batch).unwrap();
         })
         .await
     }
@@ -245,9 +490,17 @@ where
     where
         <TEntity as EntitySnapshot>::Version: 'a,
     {
-        let db = self.db.clone();
         let state_key = prefixed_key(STATE_PREFIX, &sid);
-        spawn_blocking(move || db.key_may_exist(state_key)).await
+        if let Some(filter) = &self.filter {
+            return filter.lock().may_contain(state_key.as_slice());
+        }
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            db.key_may_exist_cf(&cf, state_key)
+        })
+        .await
     }
 
     async fn get_state<'a>(&self, sid: <TEntity as EntitySnapshot>::Version) -> Option<TEntity>
@@ -255,12 +508,219 @@ where
         <TEntity as EntitySnapshot>::Version: 'a,
     {
         let db = self.db.clone();
+        let cf = self.cf.clone();
         let state_key = prefixed_key(STATE_PREFIX, &sid);
         spawn_blocking(move || {
-            db.get(state_key)
+            let cf = db.cf_handle(&cf).unwrap();
+            db.get_cf(&cf, state_key)
                 .unwrap()
-                .and_then(|bytes| bincode::deserialize(&bytes).ok())
+                .and_then(|bytes| decode(STATE_PREFIX, &bytes))
         })
         .await
     }
 }
+
+/// Decode value stored under a key with the given `prefix`.
+/// Values which fail to decode are reported and treated as missing.
+fn decode<T: DeserializeOwned>(prefix: &str, bytes: &[u8]) -> Option<T> {
+    bincode::deserialize(bytes)
+        .map_err(|err| warn!(target: "offchain", "Failed to decode value under '{}': {}", prefix, err))
+        .ok()
+}
+
+/// Key of the `pos`-th entry in the history of the entity `eid`.
+/// Position is encoded in big endian so that entries are ordered by position.
+fn history_entry_key<T: Serialize>(eid: &T, pos: u64) -> Vec<u8> {
+    let mut key = prefixed_key(HISTORY_ENTRY_PREFIX, eid);
+    key.extend_from_slice(&pos.to_be_bytes());
+    key
+}
+
+/// Key of the position of `version` in the history of the entity `eid`.
+fn history_pos_key<T: Serialize, V: Serialize>(eid: &T, version: &V) -> Vec<u8> {
+    prefixed_key(HISTORY_POS_PREFIX, &(eid, version))
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<TEntity, TTxHash> EntityHistory<TEntity, TTxHash> for EntityRepoRocksDB
+where
+    TEntity: EntitySnapshot + Send + 'static,
+    <TEntity as EntitySnapshot>::Version: Serialize + DeserializeOwned + Send + 'static,
+    <TEntity as Stable>::StableId: Serialize + Send + 'static,
+    TTxHash: Serialize + DeserializeOwned + Send + 'static,
+{
+    async fn record_version(
+        &mut self,
+        eid: <TEntity as Stable>::StableId,
+        record: VersionRecord<<TEntity as EntitySnapshot>::Version, TTxHash>,
+    ) {
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        let len_key = prefixed_key(HISTORY_LEN_PREFIX, &eid);
+        let pos_key = history_pos_key(&eid, &record.version);
+        let record_bytes = bincode::serialize(&record).unwrap();
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            if db.get_cf(&cf, &pos_key).unwrap().is_some() {
+                return;
+            }
+            let len: u64 = db
+                .get_cf(&cf, &len_key)
+                .unwrap()
+                .and_then(|bytes| decode(HISTORY_LEN_PREFIX, &bytes))
+                .unwrap_or(0);
+            let mut batch = WriteBatch::default();
+            batch.put_cf(&cf, history_entry_key(&eid, len), record_bytes);
+            batch.put_cf(&cf, pos_key, bincode::serialize(&len).unwrap());
+            batch.put_cf(&cf, len_key, bincode::serialize(&(len + 1)).unwrap());
+            db.write(batch).unwrap();
+        })
+        .await
+    }
+
+    async fn get_history(
+        &self,
+        eid: <TEntity as Stable>::StableId,
+        from: <TEntity as EntitySnapshot>::Version,
+        to: <TEntity as EntitySnapshot>::Version,
+    ) -> Vec<VersionRecord<<TEntity as EntitySnapshot>::Version, TTxHash>> {
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        let from_pos_key = history_pos_key(&eid, &from);
+        let to_pos_key = history_pos_key(&eid, &to);
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            let get_pos = |key| {
+                db.get_cf(&cf, key)
+                    .unwrap()
+                    .and_then(|bytes| decode::<u64>(HISTORY_POS_PREFIX, &bytes))
+            };
+            match (get_pos(from_pos_key), get_pos(to_pos_key)) {
+                (Some(from_pos), Some(to_pos)) => (from_pos..=to_pos)
+                    .map_while(|pos| {
+                        db.get_cf(&cf, history_entry_key(&eid, pos))
+                            .unwrap()
+                            .and_then(|bytes| decode(HISTORY_ENTRY_PREFIX, &bytes))
+                    })
+                    .collect(),
+                _ => vec![],
+            }
+        })
+        .await
+    }
+
+    async fn get_last_versions(
+        &self,
+        eid: <TEntity as Stable>::StableId,
+        n: usize,
+    ) -> Vec<VersionRecord<<TEntity as EntitySnapshot>::Version, TTxHash>> {
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        let len_key = prefixed_key(HISTORY_LEN_PREFIX, &eid);
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            let len: u64 = db
+                .get_cf(&cf, len_key)
+                .unwrap()
+                .and_then(|bytes| decode(HISTORY_LEN_PREFIX, &bytes))
+                .unwrap_or(0);
+            (len.saturating_sub(n as u64)..len)
+                .filter_map(|pos| {
+                    db.get_cf(&cf, history_entry_key(&eid, pos))
+                        .unwrap()
+                        .and_then(|bytes| decode(HISTORY_ENTRY_PREFIX, &bytes))
+                })
+                .collect()
+        })
+        .await
+    }
+
+    async fn rollback_history(
+        &mut self,
+        eid: <TEntity as Stable>::StableId,
+        version: <TEntity as EntitySnapshot>::Version,
+    ) {
+        let db = self.db.clone();
+        let cf = self.cf.clone();
+        let len_key = prefixed_key(HISTORY_LEN_PREFIX, &eid);
+        let pos_key = history_pos_key(&eid, &version);
+        spawn_blocking(move || {
+            let cf = db.cf_handle(&cf).unwrap();
+            let get_u64 = |key: &[u8]| {
+                db.get_cf(&cf, key)
+                    .unwrap()
+                    .and_then(|bytes| decode::<u64>(HISTORY_POS_PREFIX, &bytes))
+            };
+            if let (Some(pos), Some(len)) = (get_u64(&pos_key), get_u64(&len_key)) {
+                let mut batch = WriteBatch::default();
+                for p in pos..len {
+                    let entry_key = history_entry_key(&eid, p);
+                    let entry = db.get_cf(&cf, &entry_key).unwrap().and_then(|bytes| {
+                        decode::<VersionRecord<TEntity::Version, TTxHash>>(HISTORY_ENTRY_PREFIX, &bytes)
+                    });
+                    if let Some(entry) = entry {
+                        batch.delete_cf(&cf, history_pos_key(&eid, &entry.version));
+                    }
+                    batch.delete_cf(&cf, entry_key);
+                }
+                batch.put_cf(&cf, len_key, bincode::serialize(&pos).unwrap());
+                db.write(batch).unwrap();
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{thread_rng, RngCore};
+
+    use crate::binary::prefixed_key;
+    use crate::box_resolver::persistence::rocksdb::{EntityRepoRocksDB, OBSERVED_AT_PREFIX};
+    use crate::box_resolver::persistence::tests::{BoxId, TestEntity, TokenId};
+    use crate::box_resolver::persistence::EntityRepo;
+    use crate::box_resolver::{Predicted, Traced};
+    use crate::data::event::{Confirmed, Unconfirmed};
+    use crate::rocks::schema::SCHEMA_VERSION_KEY;
+    use crate::rocks::RocksConfig;
+
+    #[tokio::test]
+    async fn states_stored_before_observation_times_are_stamped_on_upgrade() {
+        let conf = RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()));
+        let mut repo = EntityRepoRocksDB::new(conf.clone());
+        let token_id = TokenId::random();
+        let states: Vec<_> = (0..3)
+            .map(|_| TestEntity {
+                token_id,
+                box_id: BoxId::random(),
+            })
+            .collect();
+        repo.put_confirmed(Confirmed(states[0].clone())).await;
+        repo.put_unconfirmed(Unconfirmed(states[1].clone())).await;
+        repo.put_predicted(Traced::new(Predicted(states[2].clone()), Some(states[1].box_id)))
+            .await;
+        {
+            // Roll the store back to the layout of version 1.
+            let cf = repo.db.cf_handle(&repo.cf).unwrap();
+            for state in &states[1..] {
+                repo.db
+                    .delete_cf(&cf, prefixed_key(OBSERVED_AT_PREFIX, &state.box_id))
+                    .unwrap();
+            }
+            repo.db
+                .put_cf(&cf, SCHEMA_VERSION_KEY, bincode::serialize(&1u32).unwrap())
+                .unwrap();
+        }
+        let upgraded = EntityRepoRocksDB::new(conf);
+        for state in &states[1..] {
+            assert!(EntityRepo::<TestEntity>::get_observed_at(&upgraded, state.box_id)
+                .await
+                .is_some());
+        }
+        assert_eq!(
+            EntityRepo::<TestEntity>::get_observed_at(&upgraded, states[0].box_id).await,
+            None
+        );
+    }
+}
@@ -1,63 +1,613 @@
 use std::fmt::Display;
+use std::hash::Hasher;
 use std::sync::Arc;
 
 use futures::{Stream, StreamExt};
-use log::trace;
+use log::{trace, warn};
 use tokio::sync::Mutex;
 
+use crate::box_resolver::history::{EntityHistory, LedgerOrigin, VersionRecord};
 use crate::box_resolver::persistence::EntityRepo;
 use crate::combinators::Ior;
-use crate::data::event::{Channel, Confirmed, Predicted, StateUpdate, Unconfirmed};
+use crate::data::event::{Channel, Confirmed, Predicted, StateUpdate, Traced, Unconfirmed};
 use crate::data::EntitySnapshot;
 use crate::partitioning::Partitioned;
 
-pub fn pool_tracking_stream<'a, const N: usize, S, Repo, Pool>(
+/// Track states of pools from the `upstream`.
+/// Updates of pools from partitions not assigned to this process are skipped.
+pub fn pool_tracking_stream<'a, const N: usize, S, Repo, Pool, H>(
     upstream: S,
-    pools: Partitioned<N, Pool::StableId, Arc<Mutex<Repo>>>,
+    pools: Partitioned<N, Pool::StableId, Arc<Mutex<Repo>>, H>,
 ) -> impl Stream<Item = ()> + 'a
 where
     S: Stream<Item = Channel<StateUpdate<Pool>>> + 'a,
     Pool: EntitySnapshot + 'a,
     Pool::StableId: Display,
     Repo: EntityRepo<Pool> + 'a,
+    H: Hasher + Default + 'a,
 {
     let pools = Arc::new(pools);
     upstream.then(move |upd_in_mode| {
         let pools = Arc::clone(&pools);
         async move {
-            let is_confirmed = matches!(upd_in_mode, Channel::Ledger(_));
-            let (Channel::Ledger(Confirmed(upd))
-            | Channel::Mempool(Unconfirmed(upd))
-            | Channel::TxSubmit(Predicted(upd))) = upd_in_mode;
-            match upd {
-                StateUpdate::Transition(Ior::Right(new_state))
-                | StateUpdate::Transition(Ior::Both(_, new_state))
-                | StateUpdate::TransitionRollback(Ior::Right(new_state))
-                | StateUpdate::TransitionRollback(Ior::Both(_, new_state)) => {
-                    let pool_ref = new_state.stable_id();
-                    let pools_mux = pools.get(pool_ref);
-                    let mut repo = pools_mux.lock().await;
-                    if is_confirmed {
-                        trace!("Observing new confirmed state of pool {}", pool_ref);
-                        repo.put_confirmed(Confirmed(new_state)).await
-                    } else {
-                        trace!("Observing new unconfirmed state of pool {}", pool_ref);
-                        repo.put_unconfirmed(Unconfirmed(new_state)).await
-                    }
-                }
-                StateUpdate::Transition(Ior::Left(st)) => {
-                    let pools_mux = pools.get(st.stable_id());
-                    let mut repo = pools_mux.lock().await;
-                    repo.eliminate(st).await
-                }
-                StateUpdate::TransitionRollback(Ior::Left(st)) => {
-                    let pool_ref = st.stable_id();
-                    trace!(target: "offchain", "Rolling back state of pool {}", pool_ref);
-                    let pools_mux = pools.get(pool_ref);
-                    let mut repo = pools_mux.lock().await;
-                    repo.invalidate(st.version(), st.stable_id()).await
+            if let Some(pools_mux) = assigned_repo(&pools, &upd_in_mode) {
+                let mut repo = pools_mux.lock().await;
+                apply_update(upd_in_mode, &mut *repo).await
+            }
+        }
+    })
+}
+
+/// Track states of pools from the `upstream` recording history of their confirmed versions.
+/// Updates observed in the ledger are expected to come along with the point they were observed at,
+/// updates from other channels carry no origin.
+/// Updates of pools from partitions not assigned to this process are skipped.
+pub fn pool_tracking_stream_with_history<'a, const N: usize, S, Repo, Pool, TxHash, H>(
+    upstream: S,
+    pools: Partitioned<N, Pool::StableId, Arc<Mutex<Repo>>, H>,
+) -> impl Stream<Item = ()> + 'a
+where
+    S: Stream<Item = (Channel<StateUpdate<Pool>>, Option<LedgerOrigin<TxHash>>)> + 'a,
+    Pool: EntitySnapshot + 'a,
+    Pool::StableId: Display,
+    Repo: EntityRepo<Pool> + EntityHistory<Pool, TxHash> + 'a,
+    TxHash: 'a,
+    H: Hasher + Default + 'a,
+{
+    let pools = Arc::new(pools);
+    upstream.then(move |(upd_in_mode, origin)| {
+        let pools = Arc::clone(&pools);
+        async move {
+            if let Some(pools_mux) = assigned_repo(&pools, &upd_in_mode) {
+                let mut repo = pools_mux.lock().await;
+                if let Channel::Ledger(Confirmed(upd)) = &upd_in_mode {
+                    record_confirmed_update(upd, origin, &mut *repo).await;
                 }
+                apply_update(upd_in_mode, &mut *repo).await
             }
         }
     })
 }
+
+/// Get repo of the pool the update is related to unless the pool belongs to an unassigned partition.
+fn assigned_repo<const N: usize, Pool, Repo, H>(
+    pools: &Partitioned<N, Pool::StableId, Arc<Mutex<Repo>>, H>,
+    upd_in_mode: &Channel<StateUpdate<Pool>>,
+) -> Option<Arc<Mutex<Repo>>>
+where
+    Pool: EntitySnapshot,
+    Pool::StableId: Display,
+    H: Hasher + Default,
+{
+    let pool_ref = match upd_in_mode.erased() {
+        StateUpdate::Transition(ior) | StateUpdate::TransitionRollback(ior) => match ior {
+            Ior::Left(st) | Ior::Right(st) | Ior::Both(_, st) => st.stable_id(),
+        },
+    };
+    if !pools.is_assigned(pool_ref) {
+        trace!(target: "offchain", "Skipping update of pool {} from unassigned partition", pool_ref);
+        return None;
+    }
+    Some(Arc::clone(pools.get(pool_ref)))
+}
+
+async fn apply_update<Pool, Repo>(upd_in_mode: Channel<StateUpdate<Pool>>, repo: &mut Repo)
+where
+    Pool: EntitySnapshot,
+    Pool::StableId: Display,
+    Repo: EntityRepo<Pool>,
+{
+    match upd_in_mode {
+        Channel::Ledger(Confirmed(upd)) => apply_confirmed_update(upd, repo).await,
+        Channel::Mempool(Unconfirmed(upd)) => apply_unconfirmed_update(upd, repo).await,
+        Channel::TxSubmit(Predicted(upd)) => apply_predicted_update(upd, repo).await,
+    }
+}
+
+/// Reflect update observed in the ledger at `origin` in the history of the pool.
+/// Rolled back versions are removed from the history along with the ones following them.
+async fn record_confirmed_update<Pool, Repo, TxHash>(
+    upd: &StateUpdate<Pool>,
+    origin: Option<LedgerOrigin<TxHash>>,
+    repo: &mut Repo,
+) where
+    Pool: EntitySnapshot,
+    Pool::StableId: Display,
+    Repo: EntityHistory<Pool, TxHash>,
+{
+    match upd {
+        StateUpdate::Transition(Ior::Right(new_state)) | StateUpdate::Transition(Ior::Both(_, new_state)) => {
+            match origin {
+                Some(LedgerOrigin { slot, tx_hash }) => {
+                    let record = VersionRecord {
+                        version: new_state.version(),
+                        slot,
+                        tx_hash,
+                    };
+                    repo.record_version(new_state.stable_id(), record).await
+                }
+                None => warn!(
+                    target: "offchain",
+                    "Origin of confirmed state {} of pool {} is unknown, history is not recorded",
+                    new_state.version(),
+                    new_state.stable_id()
+                ),
+            }
+        }
+        StateUpdate::TransitionRollback(Ior::Left(st))
+        | StateUpdate::TransitionRollback(Ior::Both(st, _)) => {
+            repo.rollback_history(st.stable_id(), st.version()).await
+        }
+        StateUpdate::Transition(Ior::Left(_)) | StateUpdate::TransitionRollback(Ior::Right(_)) => {}
+    }
+}
+
+/// Apply update observed in the ledger.
+async fn apply_confirmed_update<Pool, Repo>(upd: StateUpdate<Pool>, repo: &mut Repo)
+where
+    Pool: EntitySnapshot,
+    Pool::StableId: Display,
+    Repo: EntityRepo<Pool>,
+{
+    match upd {
+        StateUpdate::Transition(Ior::Right(new_state)) | StateUpdate::Transition(Ior::Both(_, new_state)) => {
+            trace!(target: "offchain", "Observing new confirmed state of pool {}", new_state.stable_id());
+            repo.put_confirmed(Confirmed(new_state)).await
+        }
+        StateUpdate::Transition(Ior::Left(st)) => {
+            trace!(target: "offchain", "Pool {} is eliminated", st.stable_id());
+            repo.eliminate(st).await
+        }
+        StateUpdate::TransitionRollback(Ior::Left(st)) => {
+            trace!(target: "offchain", "Rolling back state of pool {}", st.stable_id());
+            repo.invalidate(st.version(), st.stable_id()).await
+        }
+        StateUpdate::TransitionRollback(Ior::Both(st, revived_state)) => {
+            trace!(target: "offchain", "Rolling back state of pool {}", st.stable_id());
+            repo.invalidate(st.version(), st.stable_id()).await;
+            repo.put_confirmed(Confirmed(revived_state)).await
+        }
+        StateUpdate::TransitionRollback(Ior::Right(revived_state)) => {
+            trace!(target: "offchain", "Reviving confirmed state of pool {}", revived_state.stable_id());
+            repo.put_confirmed(Confirmed(revived_state)).await
+        }
+    }
+}
+
+/// Apply update observed in the mempool.
+/// Rollbacks here mean that the transaction left the mempool without making it to the ledger.
+async fn apply_unconfirmed_update<Pool, Repo>(upd: StateUpdate<Pool>, repo: &mut Repo)
+where
+    Pool: EntitySnapshot,
+    Pool::StableId: Display,
+    Repo: EntityRepo<Pool>,
+{
+    match upd {
+        StateUpdate::Transition(Ior::Right(new_state)) | StateUpdate::Transition(Ior::Both(_, new_state)) => {
+            trace!(target: "offchain", "Observing new unconfirmed state of pool {}", new_state.stable_id());
+            repo.put_unconfirmed(Unconfirmed(new_state)).await
+        }
+        StateUpdate::Transition(Ior::Left(st)) => {
+            // Pool is eliminated only once the transaction consuming it is confirmed.
+            trace!(target: "offchain", "Pool {} is consumed in mempool", st.stable_id());
+        }
+        StateUpdate::TransitionRollback(Ior::Left(st)) => {
+            trace!(target: "offchain", "Unconfirmed state of pool {} is gone", st.stable_id());
+            repo.drop_unconfirmed(st.stable_id(), st.version()).await
+        }
+        StateUpdate::TransitionRollback(Ior::Both(st, revived_state)) => {
+            trace!(target: "offchain", "Unconfirmed state of pool {} is gone", st.stable_id());
+            repo.drop_unconfirmed(st.stable_id(), st.version()).await;
+            repo.put_unconfirmed(Unconfirmed(revived_state)).await
+        }
+        StateUpdate::TransitionRollback(Ior::Right(revived_state)) => {
+            trace!(target: "offchain", "Reviving unconfirmed state of pool {}", revived_state.stable_id());
+            repo.put_unconfirmed(Unconfirmed(revived_state)).await
+        }
+    }
+}
+
+/// Apply update predicted on transaction submission.
+/// Rollbacks here mean that the submitted transaction was rejected.
+async fn apply_predicted_update<Pool, Repo>(upd: StateUpdate<Pool>, repo: &mut Repo)
+where
+    Pool: EntitySnapshot,
+    Pool::StableId: Display,
+    Repo: EntityRepo<Pool>,
+{
+    match upd {
+        StateUpdate::Transition(Ior::Both(prev_state, new_state)) => {
+            trace!(target: "offchain", "Observing new predicted state of pool {}", new_state.stable_id());
+            let prev_state_id = Some(prev_state.version());
+            repo.put_predicted(Traced::new(Predicted(new_state), prev_state_id))
+                .await
+        }
+        StateUpdate::Transition(Ior::Right(new_state)) => {
+            // Predictions are only resolved when linked to the anchoring state,
+            // so the new state is linked to the current one if the pool is already known.
+            let pid = new_state.stable_id();
+            let anchoring_sid = match repo.get_last_unconfirmed(pid).await {
+                Some(Unconfirmed(st)) => Some(st.version()),
+                None => repo
+                    .get_last_confirmed(pid)
+                    .await
+                    .map(|Confirmed(st)| st.version()),
+            };
+            if let Some(prev_state_id) = anchoring_sid {
+                trace!(target: "offchain", "Observing new predicted state of pool {}", pid);
+                repo.put_predicted(Traced::new(Predicted(new_state), Some(prev_state_id)))
+                    .await
+            } else {
+                // Pool created by a submitted transaction has nothing to anchor to,
+                // so it is picked up once its creation is observed in mempool or ledger.
+                trace!(target: "offchain", "Predicted creation of pool {} is skipped", pid);
+            }
+        }
+        StateUpdate::Transition(Ior::Left(st)) => {
+            // Pool is eliminated only once the transaction consuming it is confirmed.
+            trace!(target: "offchain", "Pool {} is predicted to be consumed", st.stable_id());
+        }
+        StateUpdate::TransitionRollback(Ior::Left(st)) => {
+            trace!(target: "offchain", "Predicted state of pool {} is discarded", st.stable_id());
+            repo.prune_predictions(st.stable_id(), vec![st.version()]).await
+        }
+        StateUpdate::TransitionRollback(Ior::Both(st, revived_state)) => {
+            trace!(target: "offchain", "Predicted state of pool {} is discarded", st.stable_id());
+            repo.prune_predictions(st.stable_id(), vec![st.version()]).await;
+            // Link of the revived state is kept, so it is enough to point to it again.
+            repo.put_predicted(Traced::new(Predicted(revived_state), None))
+                .await
+        }
+        StateUpdate::TransitionRollback(Ior::Right(revived_state)) => {
+            trace!(target: "offchain", "Reviving predicted state of pool {}", revived_state.stable_id());
+            repo.put_predicted(Traced::new(Predicted(revived_state), None))
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::{stream, StreamExt};
+    use tokio::sync::Mutex;
+
+    use crate::box_resolver::history::{EntityHistory, LedgerOrigin};
+    use crate::box_resolver::persistence::inmemory::InMemoryEntityRepo;
+    use crate::box_resolver::persistence::tests::*;
+    use crate::box_resolver::persistence::EntityRepo;
+    use crate::box_resolver::process::{pool_tracking_stream, pool_tracking_stream_with_history};
+    use crate::box_resolver::resolve_entity_state;
+    use crate::combinators::Ior;
+    use crate::data::event::{Channel, Confirmed, Predicted, StateUpdate, Unconfirmed};
+    use crate::partitioning::Partitioned;
+
+    type Repo = Arc<Mutex<InMemoryEntityRepo<TestEntity, u64>>>;
+
+    fn states(n: usize) -> (TokenId, Vec<TestEntity>) {
+        let token_id = TokenId::random();
+        let states = (0..n)
+            .map(|_| TestEntity {
+                token_id,
+                box_id: BoxId::random(),
+            })
+            .collect();
+        (token_id, states)
+    }
+
+    async fn track(repo: &Repo, updates: Vec<Channel<StateUpdate<TestEntity>>>) {
+        let pools = Partitioned::new([Arc::clone(repo)]);
+        pool_tracking_stream::<1, _, _, TestEntity, _>(stream::iter(updates), pools)
+            .collect::<Vec<_>>()
+            .await;
+    }
+
+    async fn last_confirmed(repo: &Repo, id: TokenId) -> Option<TestEntity> {
+        let res: Option<Confirmed<TestEntity>> = repo.lock().await.get_last_confirmed(id).await;
+        res.map(|Confirmed(st)| st)
+    }
+
+    async fn last_unconfirmed(repo: &Repo, id: TokenId) -> Option<TestEntity> {
+        let res: Option<Unconfirmed<TestEntity>> = repo.lock().await.get_last_unconfirmed(id).await;
+        res.map(|Unconfirmed(st)| st)
+    }
+
+    async fn last_predicted(repo: &Repo, id: TokenId) -> Option<TestEntity> {
+        let res: Option<Predicted<TestEntity>> = repo.lock().await.get_last_predicted(id).await;
+        res.map(|Predicted(st)| st)
+    }
+
+    async fn predecessor(repo: &Repo, sid: BoxId) -> Option<BoxId> {
+        EntityRepo::<TestEntity>::get_prediction_predecessor(&*repo.lock().await, sid).await
+    }
+
+    #[tokio::test]
+    async fn ledger_transition() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(2);
+        track(
+            &repo,
+            vec![Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone())))],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[0].clone()));
+        track(
+            &repo,
+            vec![Channel::ledger(StateUpdate::Transition(Ior::Both(
+                s[0].clone(),
+                s[1].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[1].clone()));
+        track(
+            &repo,
+            vec![Channel::ledger(StateUpdate::Transition(Ior::Left(s[1].clone())))],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, None);
+    }
+
+    #[tokio::test]
+    async fn ledger_rollback() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(2);
+        track(
+            &repo,
+            vec![
+                Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone()))),
+                Channel::ledger(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+                Channel::ledger(StateUpdate::TransitionRollback(Ior::Both(
+                    s[1].clone(),
+                    s[0].clone(),
+                ))),
+            ],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[0].clone()));
+        track(
+            &repo,
+            vec![Channel::ledger(StateUpdate::TransitionRollback(Ior::Left(
+                s[0].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, None);
+        track(
+            &repo,
+            vec![Channel::ledger(StateUpdate::TransitionRollback(Ior::Right(
+                s[1].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[1].clone()));
+    }
+
+    #[tokio::test]
+    async fn ledger_updates_are_recorded_in_history() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(3);
+        let origin = |slot| Some(LedgerOrigin { slot, tx_hash: slot });
+        let updates = vec![
+            (
+                Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone()))),
+                origin(1),
+            ),
+            (
+                Channel::ledger(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+                origin(2),
+            ),
+            (
+                Channel::mempool(StateUpdate::Transition(Ior::Both(s[1].clone(), s[2].clone()))),
+                None,
+            ),
+            (
+                Channel::ledger(StateUpdate::Transition(Ior::Both(s[1].clone(), s[2].clone()))),
+                origin(3),
+            ),
+            (
+                Channel::ledger(StateUpdate::TransitionRollback(Ior::Both(
+                    s[2].clone(),
+                    s[1].clone(),
+                ))),
+                None,
+            ),
+        ];
+        let pools = Partitioned::new([Arc::clone(&repo)]);
+        pool_tracking_stream_with_history::<1, _, _, TestEntity, u64, _>(stream::iter(updates), pools)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[1].clone()));
+        let history =
+            EntityHistory::<TestEntity, u64>::get_last_versions(&*repo.lock().await, token_id, 10).await;
+        assert_eq!(
+            history.iter().map(|r| (r.version, r.slot)).collect::<Vec<_>>(),
+            vec![(s[0].box_id, 1), (s[1].box_id, 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn mempool_transition() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(2);
+        track(
+            &repo,
+            vec![
+                Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone()))),
+                Channel::mempool(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+            ],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[0].clone()));
+        assert_eq!(last_unconfirmed(&repo, token_id).await, Some(s[1].clone()));
+        // Pool consumed in mempool is not eliminated until the ledger says so.
+        track(
+            &repo,
+            vec![Channel::mempool(StateUpdate::Transition(Ior::Left(s[1].clone())))],
+        )
+        .await;
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[0].clone()));
+        assert_eq!(last_unconfirmed(&repo, token_id).await, Some(s[1].clone()));
+    }
+
+    #[tokio::test]
+    async fn mempool_rollback() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(3);
+        track(
+            &repo,
+            vec![
+                Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone()))),
+                Channel::mempool(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+                Channel::mempool(StateUpdate::Transition(Ior::Both(s[1].clone(), s[2].clone()))),
+                Channel::mempool(StateUpdate::TransitionRollback(Ior::Both(
+                    s[2].clone(),
+                    s[1].clone(),
+                ))),
+            ],
+        )
+        .await;
+        assert_eq!(last_unconfirmed(&repo, token_id).await, Some(s[1].clone()));
+        // Confirmed state is untouched when a mempool transaction is gone.
+        track(
+            &repo,
+            vec![Channel::mempool(StateUpdate::TransitionRollback(Ior::Left(
+                s[1].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_unconfirmed(&repo, token_id).await, None);
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[0].clone()));
+        track(
+            &repo,
+            vec![Channel::mempool(StateUpdate::TransitionRollback(Ior::Right(
+                s[2].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_unconfirmed(&repo, token_id).await, Some(s[2].clone()));
+    }
+
+    #[tokio::test]
+    async fn tx_submit_transition() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(2);
+        track(
+            &repo,
+            vec![
+                Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone()))),
+                Channel::tx_submit(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+            ],
+        )
+        .await;
+        assert_eq!(last_predicted(&repo, token_id).await, Some(s[1].clone()));
+        assert_eq!(last_unconfirmed(&repo, token_id).await, None);
+        assert_eq!(predecessor(&repo, s[1].box_id).await, Some(s[0].box_id));
+        let resolved = resolve_entity_state::<TestEntity, _>(token_id, Arc::clone(&repo)).await;
+        assert_eq!(resolved, Some(s[1].clone()));
+        // Pool consumed by a submitted transaction is not eliminated until the ledger says so.
+        track(
+            &repo,
+            vec![Channel::tx_submit(StateUpdate::Transition(Ior::Left(
+                s[1].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_predicted(&repo, token_id).await, Some(s[1].clone()));
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[0].clone()));
+    }
+
+    #[tokio::test]
+    async fn predicted_states_without_predecessor_are_linked_to_anchoring_state() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(3);
+        track(
+            &repo,
+            vec![Channel::tx_submit(StateUpdate::Transition(Ior::Right(
+                s[0].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_predicted(&repo, token_id).await, None);
+        track(
+            &repo,
+            vec![
+                Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone()))),
+                Channel::mempool(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+                Channel::tx_submit(StateUpdate::Transition(Ior::Right(s[2].clone()))),
+            ],
+        )
+        .await;
+        assert_eq!(predecessor(&repo, s[2].box_id).await, Some(s[1].box_id));
+        let resolved = resolve_entity_state::<TestEntity, _>(token_id, Arc::clone(&repo)).await;
+        assert_eq!(resolved, Some(s[2].clone()));
+    }
+
+    #[tokio::test]
+    async fn tx_submit_rollback() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let (token_id, s) = states(3);
+        track(
+            &repo,
+            vec![
+                Channel::ledger(StateUpdate::Transition(Ior::Right(s[0].clone()))),
+                Channel::tx_submit(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+                Channel::tx_submit(StateUpdate::Transition(Ior::Both(s[1].clone(), s[2].clone()))),
+                Channel::tx_submit(StateUpdate::TransitionRollback(Ior::Both(
+                    s[2].clone(),
+                    s[1].clone(),
+                ))),
+            ],
+        )
+        .await;
+        assert_eq!(last_predicted(&repo, token_id).await, Some(s[1].clone()));
+        assert_eq!(predecessor(&repo, s[2].box_id).await, None);
+        assert_eq!(predecessor(&repo, s[1].box_id).await, Some(s[0].box_id));
+        track(
+            &repo,
+            vec![Channel::tx_submit(StateUpdate::TransitionRollback(Ior::Left(
+                s[1].clone(),
+            )))],
+        )
+        .await;
+        assert_eq!(last_predicted(&repo, token_id).await, None);
+        assert_eq!(last_confirmed(&repo, token_id).await, Some(s[0].clone()));
+        let resolved = resolve_entity_state::<TestEntity, _>(token_id, Arc::clone(&repo)).await;
+        assert_eq!(resolved, Some(s[0].clone()));
+        // Revived prediction keeps its link.
+        track(
+            &repo,
+            vec![
+                Channel::tx_submit(StateUpdate::Transition(Ior::Both(s[0].clone(), s[1].clone()))),
+                Channel::tx_submit(StateUpdate::TransitionRollback(Ior::Right(s[1].clone()))),
+            ],
+        )
+        .await;
+        assert_eq!(last_predicted(&repo, token_id).await, Some(s[1].clone()));
+        assert_eq!(predecessor(&repo, s[1].box_id).await, Some(s[0].box_id));
+    }
+
+    #[tokio::test]
+    async fn updates_of_unassigned_partitions_are_skipped() {
+        let repo: Repo = Arc::new(Mutex::new(InMemoryEntityRepo::default()));
+        let pools = Partitioned::new([Arc::clone(&repo), Arc::clone(&repo)]).assign(0..1);
+        let (assigned, unassigned): (Vec<_>, Vec<_>) = (0..16)
+            .map(|_| states(1).1.remove(0))
+            .partition(|st| pools.is_assigned(st.token_id));
+        let updates = assigned
+            .iter()
+            .chain(&unassigned)
+            .map(|st| Channel::ledger(StateUpdate::Transition(Ior::Right(st.clone()))))
+            .collect::<Vec<_>>();
+        pool_tracking_stream(stream::iter(updates), pools)
+            .collect::<Vec<_>>()
+            .await;
+        for st in assigned {
+            assert_eq!(last_confirmed(&repo, st.token_id).await, Some(st));
+        }
+        for st in unassigned {
+            assert_eq!(last_confirmed(&repo, st.token_id).await, None);
+        }
+    }
+}
@@ -35,6 +35,32 @@ pub trait SpecializedOrder {
     fn get_pool_ref(&self) -> Self::TPoolId;
 }
 
+/// An order which is run against several entities at once,
+/// e.g. a route across two pools or a pool along with an oracle or a config entity.
+pub trait MultiEntityOrder: UniqueOrder {
+    type TEntityId: Copy + Eq + Hash;
+
+    /// Entities the order is run against, in the order their states are passed to the order runner.
+    fn get_entity_refs(&self) -> Vec<Self::TEntityId>;
+}
+
+/// An order which names only a pair, the pool to run it against is chosen at execution time.
+pub trait UnspecializedOrder: UniqueOrder {
+    type TPairId: Copy + Eq + Hash;
+
+    fn get_pair_ref(&self) -> Self::TPairId;
+    fn get_pool_selection(&self) -> PoolSelection;
+}
+
+/// Strategy of choosing a pool for an [UnspecializedOrder] among the pools of its pair.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PoolSelection {
+    /// Pool giving the best quote when the order is simulated against each of the candidates.
+    BestQuote,
+    /// First pool the order can be run against.
+    FirstExecutable,
+}
+
 #[derive(Debug, Hash, Clone, Eq, PartialEq)]
 pub enum OrderUpdate<TNewOrd, TElimOrd> {
     Created(TNewOrd),
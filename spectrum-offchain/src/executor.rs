@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::marker::PhantomData;
@@ -14,13 +15,18 @@ use tokio::sync::Mutex;
 use type_equalities::{trivial_eq, IsEqual};
 
 use crate::backlog::HotBacklog;
+use crate::box_resolver::blacklist::EntityBlacklist;
+use crate::box_resolver::pair_index::PairIndex;
 use crate::box_resolver::persistence::EntityRepo;
-use crate::box_resolver::resolve_entity_state;
+use crate::box_resolver::{resolve_entity_state_with_ttl, resolve_entity_states_with_ttl, DEFAULT_STATE_TTL};
 use crate::data::event::{Predicted, Traced};
-use crate::data::order::SpecializedOrder;
-use crate::data::EntitySnapshot;
+use crate::data::order::{
+    MultiEntityOrder, PoolSelection, SpecializedOrder, UniqueOrder, UnspecializedOrder,
+};
+use crate::data::{EntitySnapshot, Tradable};
 use crate::executor::RunOrderError::{Fatal, NonFatal};
 use crate::executor::TxSubmissionError::{OrderUtxoIsSpent, PoolUtxoIsSpent, UnknownError};
+use crate::maybe_send::MaybeSend;
 use crate::network::Network;
 use crate::tx_prover::TxProver;
 
@@ -60,7 +66,64 @@ pub trait RunOrder<Order, Ctx, Tx>: Sized {
     ) -> Result<(Tx, Predicted<Self>), RunOrderError<Order>>;
 }
 
-#[async_trait(? Send)]
+/// Estimate how good running an order against `Self` is for the order.
+pub trait QuoteOrder<Order> {
+    /// Quality of the execution, the greater the better.
+    type Quote: Ord;
+    fn quote(&self, order: &Order, next_state: &Self) -> Self::Quote;
+}
+
+/// Pool an order is run against along with the resulting transaction and the next state of the pool.
+pub type RunOutcome<Pool, Tx> = (Pool, Tx, Predicted<Pool>);
+
+/// Simulate `order` against each of the `candidates` and pick one of them according to `selection`.
+/// Returns the chosen pool along with the outcome of running the order against it,
+/// or `None` if the order cannot be run against any of the candidates.
+pub fn select_pool<Ord, Pool, Ctx, Tx>(
+    order: &Ord,
+    candidates: Vec<Pool>,
+    selection: PoolSelection,
+    ctx: Ctx,
+) -> Option<RunOutcome<Pool, Tx>>
+where
+    Ord: Clone,
+    Pool: RunOrder<Ord, Ctx, Tx> + QuoteOrder<Ord> + Clone,
+    Ctx: Clone,
+{
+    let mut best: Option<(Pool::Quote, RunOutcome<Pool, Tx>)> = None;
+    for pool in candidates {
+        match pool.clone().try_run(order.clone(), ctx.clone()) {
+            Ok((tx, next_state)) => {
+                if selection == PoolSelection::FirstExecutable {
+                    return Some((pool, tx, next_state));
+                }
+                let quote = pool.quote(order, &next_state.0);
+                if best.as_ref().map_or(true, |(best_quote, _)| quote > *best_quote) {
+                    best = Some((quote, (pool, tx, next_state)));
+                }
+            }
+            Err(RunOrderError::NonFatal(err, _) | RunOrderError::Fatal(err, _)) => {
+                trace!("Order cannot be run against candidate pool: {}", err);
+            }
+        }
+    }
+    best.map(|(_, outcome)| outcome)
+}
+
+pub trait RunMultiEntityOrder<Order, Ctx, Tx>: Sized {
+    /// Try to run `order` against the given set of entities `Self`,
+    /// ordered as [MultiEntityOrder::get_entity_refs].
+    /// Returns transaction and the next states of the entities modified by it in the case of success.
+    /// Returns `RunOrderError<TOrd>` otherwise.
+    fn try_run(
+        entities: Vec<Self>,
+        order: Order,
+        ctx: Ctx,
+    ) -> Result<(Tx, Vec<Predicted<Self>>), RunOrderError<Order>>;
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 pub trait Executor {
     /// Execute next available order.
     /// Drives execution to completion (submit tx or handle error).
@@ -68,26 +131,39 @@ pub trait Executor {
 }
 
 /// A generic executor suitable for cases when single order is applied to a single entity (pool).
-pub struct HotOrderExecutor<Net, Backlog, Pools, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err> {
+pub struct HotOrderExecutor<
+    Net,
+    Backlog,
+    Pools,
+    Blacklist,
+    Prover,
+    Ctx,
+    Ord,
+    Pool: EntitySnapshot,
+    TxCandidate,
+    Tx,
+    Err,
+> {
     network: Net,
     backlog: Arc<Mutex<Backlog>>,
     pool_repo: Arc<Mutex<Pools>>,
+    guard: ExecutionGuard<Blacklist, Ord, Pool>,
     prover: Prover,
     ctx: Ctx,
-    pd1: PhantomData<Ord>,
-    pd2: PhantomData<Pool>,
+    state_ttl: Duration,
     pd3: PhantomData<TxCandidate>,
     pd4: PhantomData<Tx>,
     pd5: PhantomData<Err>,
 }
 
-impl<Net, Backlog, Pools, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
-    HotOrderExecutor<Net, Backlog, Pools, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
+impl<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool: EntitySnapshot, TxCandidate, Tx, Err>
+    HotOrderExecutor<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
 {
     pub fn new(
         network: Net,
         backlog: Arc<Mutex<Backlog>>,
         pool_repo: Arc<Mutex<Pools>>,
+        blacklist: Blacklist,
         prover: Prover,
         ctx: Ctx,
     ) -> Self {
@@ -95,15 +171,21 @@ impl<Net, Backlog, Pools, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
             network,
             backlog,
             pool_repo,
+            guard: ExecutionGuard::new(blacklist),
             prover,
             ctx,
-            pd1: Default::default(),
-            pd2: Default::default(),
+            state_ttl: DEFAULT_STATE_TTL,
             pd3: Default::default(),
             pd4: Default::default(),
             pd5: Default::default(),
         }
     }
+
+    /// Discard unconfirmed and predicted states of entities which didn't make it to the ledger
+    /// within `state_ttl`, [DEFAULT_STATE_TTL] by default.
+    pub fn with_state_ttl(self, state_ttl: Duration) -> Self {
+        Self { state_ttl, ..self }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -159,63 +241,218 @@ fn process_tx_rejected_error<'a, Err: Display, PoolVersion: Display, OrderVersio
     }
 }
 
-#[async_trait(? Send)]
-impl<Net, Backlog, Pools, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err> Executor
-    for HotOrderExecutor<Net, Backlog, Pools, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
+/// Blacklist bookkeeping shared by executors.
+/// Orders to be run against blacklisted entities are suspended until the entities leave the blacklist.
+/// Like the hot backlog itself, suspended orders are kept in memory only.
+/// Orders which fail to run are accounted against the entities, while failed submissions are accounted
+/// only if they are not explained by spent inputs, so that orders spent meanwhile
+/// don't get healthy entities quarantined.
+struct ExecutionGuard<Blacklist, Ord, Entity: EntitySnapshot> {
+    blacklist: Blacklist,
+    /// Suspended orders along with the entities they are to be run against.
+    suspended: Vec<(Ord, Vec<Entity::StableId>)>,
+}
+
+impl<Blacklist, Ord, Entity: EntitySnapshot> ExecutionGuard<Blacklist, Ord, Entity> {
+    fn new(blacklist: Blacklist) -> Self {
+        Self {
+            blacklist,
+            suspended: vec![],
+        }
+    }
+}
+
+impl<Blacklist, Ord, Entity> ExecutionGuard<Blacklist, Ord, Entity>
 where
-    Ord: SpecializedOrder + Clone + Display,
-    <Ord as SpecializedOrder>::TOrderId: Clone + Display,
-    Pool: EntitySnapshot + RunOrder<Ord, Ctx, TxCandidate> + Clone,
-    Pool::StableId: Copy,
-    Ord::TPoolId: IsEqual<Pool::StableId> + Display,
-    Net: Network<Tx, Err>,
-    Backlog: HotBacklog<Ord>,
-    Pools: EntityRepo<Pool>,
-    Prover: TxProver<TxCandidate, Tx>,
-    Ctx: Clone,
-    Err: Display,
-    Tx: Serialize,
+    Blacklist: EntityBlacklist<Entity>,
+    Ord: UniqueOrder,
+    Ord::TOrderId: Display,
+    Entity: EntitySnapshot,
+{
+    async fn is_blacklisted(&self, id: &Entity::StableId) -> bool {
+        self.blacklist.is_blacklisted(id).await
+    }
+
+    /// Return suspended orders whose entities are no longer blacklisted to the `backlog`.
+    /// Orders removed from the `backlog` while suspended are dropped.
+    async fn release_suspended<Backlog: HotBacklog<Ord>>(&mut self, backlog: &Mutex<Backlog>) {
+        let mut still_suspended = vec![];
+        for (ord, ids) in std::mem::take(&mut self.suspended) {
+            if backlog.lock().await.is_removed(ord.get_self_ref()) {
+                info!(
+                    "Suspended order {} is dropped as it was removed",
+                    ord.get_self_ref()
+                );
+            } else if self.find_blacklisted(&ids).await.is_some() {
+                still_suspended.push((ord, ids));
+            } else {
+                info!("Order {} is resumed", ord.get_self_ref());
+                backlog.lock().await.put(ord);
+            }
+        }
+        self.suspended = still_suspended;
+    }
+
+    /// Suspend `ord` if any of the entities `ids` it is to be run against is blacklisted.
+    /// Returns the order back otherwise.
+    async fn suspend_if_blacklisted(&mut self, ord: Ord, ids: &[Entity::StableId]) -> Option<Ord> {
+        if let Some(id) = self.find_blacklisted(ids).await {
+            info!(
+                "Suspending order {} while entity {} is blacklisted",
+                ord.get_self_ref(),
+                id
+            );
+            self.suspended.push((ord, ids.to_vec()));
+            return None;
+        }
+        Some(ord)
+    }
+
+    async fn find_blacklisted(&self, ids: &[Entity::StableId]) -> Option<Entity::StableId> {
+        for id in ids {
+            if self.blacklist.is_blacklisted(id).await {
+                return Some(*id);
+            }
+        }
+        None
+    }
+
+    /// Account failed attempt to run an order against the entities `ids`.
+    fn on_run_failed(&self, ids: &[Entity::StableId]) {
+        for id in ids {
+            self.blacklist.report_failure(*id);
+        }
+    }
+
+    /// Handle outcome of the submission of a tx running `ord` against `entities`.
+    /// On success, `next_states` of the entities are persisted as predicted ones.
+    /// On failure, states found to be spent are invalidated and the order is returned to the `backlog`
+    /// to be retried against fresh states, unless the order is spent as well.
+    async fn on_submitted<Entities, Backlog, Err>(
+        &self,
+        result: Result<(), Err>,
+        ord: Ord,
+        entities: Vec<Entity>,
+        next_states: Vec<Predicted<Entity>>,
+        entity_repo: &mut Entities,
+        backlog: &Mutex<Backlog>,
+    ) where
+        Entities: EntityRepo<Entity>,
+        Backlog: HotBacklog<Ord>,
+        Err: Display,
+    {
+        match result {
+            Ok(()) => {
+                for entity in &entities {
+                    self.blacklist.report_success(entity.stable_id());
+                }
+                // All predicted states are written while holding the lock on the repo,
+                // so that they are observed together.
+                for next_state in next_states {
+                    let prev_state_id = entities
+                        .iter()
+                        .find(|entity| entity.stable_id() == next_state.0.stable_id())
+                        .map(|entity| entity.version());
+                    entity_repo
+                        .put_predicted(Traced {
+                            state: next_state,
+                            prev_state_id,
+                        })
+                        .await;
+                }
+            }
+            Err(err) => {
+                let err = err.to_string();
+                let (mut entity_is_spent, mut order_is_spent) = (false, false);
+                for entity in &entities {
+                    let errors = process_tx_rejected_error(&err, entity.version(), ord.get_self_ref());
+                    warn!(
+                        "Failed to submit TX against entity {}. Errors {:?}",
+                        entity.stable_id(),
+                        errors
+                    );
+                    if errors.contains(&PoolUtxoIsSpent) {
+                        entity_repo.invalidate(entity.version(), entity.stable_id()).await;
+                        entity_is_spent = true;
+                    }
+                    order_is_spent |= errors.contains(&OrderUtxoIsSpent);
+                }
+                if !entity_is_spent && !order_is_spent {
+                    for entity in &entities {
+                        self.blacklist.report_failure(entity.stable_id());
+                    }
+                }
+                if entity_is_spent && !order_is_spent {
+                    backlog.lock().await.put(ord);
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err> Executor
+    for HotOrderExecutor<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
+where
+    Ord: SpecializedOrder + Clone + Display + MaybeSend,
+    <Ord as SpecializedOrder>::TOrderId: Clone + Display + MaybeSend,
+    Pool: EntitySnapshot + RunOrder<Ord, Ctx, TxCandidate> + Clone + MaybeSend,
+    Pool::StableId: Copy + MaybeSend,
+    Pool::Version: MaybeSend,
+    Ord::TPoolId: IsEqual<Pool::StableId> + Display + MaybeSend,
+    Net: Network<Tx, Err> + MaybeSend,
+    Backlog: HotBacklog<Ord> + MaybeSend,
+    Pools: EntityRepo<Pool> + MaybeSend,
+    Blacklist: EntityBlacklist<Pool> + MaybeSend,
+    Prover: TxProver<TxCandidate, Tx> + MaybeSend,
+    Ctx: Clone + MaybeSend,
+    TxCandidate: MaybeSend,
+    Err: Display + MaybeSend,
+    Tx: Serialize + MaybeSend,
 {
     async fn try_execute_next(&mut self) -> bool {
+        self.guard.release_suspended(&self.backlog).await;
         let next_ord = {
             let mut backlog = self.backlog.lock().await;
             backlog.try_pop()
         };
         if let Some(ord) = next_ord {
             let entity_id = ord.get_pool_ref();
+            let Some(ord) = self
+                .guard
+                .suspend_if_blacklisted(ord, &[trivial_eq().coerce(entity_id)])
+                .await
+            else {
+                return true;
+            };
             info!("Running order {} against pool {}", ord.get_self_ref(), entity_id);
-            if let Some(entity) =
-                resolve_entity_state(trivial_eq().coerce(entity_id), Arc::clone(&self.pool_repo)).await
+            if let Some(entity) = resolve_entity_state_with_ttl(
+                trivial_eq().coerce(entity_id),
+                self.state_ttl,
+                Arc::clone(&self.pool_repo),
+            )
+            .await
             {
-                let pool_id = entity.stable_id();
-                let pool_state_id = entity.version();
                 match entity.clone().try_run(ord.clone(), self.ctx.clone()) {
                     Ok((tx_candidate, next_entity_state)) => {
                         let mut entity_repo = self.pool_repo.lock().await;
                         let tx = self.prover.prove(tx_candidate);
-                        if let Err(err) = self.network.submit_tx(tx).await {
-                            let errors =
-                                process_tx_rejected_error(err, entity.clone().version(), ord.get_self_ref());
-                            warn!("Failed to submit TX. Errors {:?}", errors);
-                            if !errors.is_empty() {
-                                if errors.contains(&PoolUtxoIsSpent) && errors.contains(&OrderUtxoIsSpent) {
-                                    entity_repo.invalidate(pool_state_id, pool_id).await;
-                                } else if errors.contains(&PoolUtxoIsSpent) {
-                                    entity_repo.invalidate(pool_state_id, pool_id).await;
-                                    self.backlog.lock().await.put(ord);
-                                }
-                            }
-                        } else {
-                            entity_repo
-                                .put_predicted(Traced {
-                                    state: next_entity_state,
-                                    prev_state_id: Some(pool_state_id),
-                                })
-                                .await;
-                        }
+                        let result = self.network.submit_tx(tx).await;
+                        self.guard
+                            .on_submitted(
+                                result,
+                                ord,
+                                vec![entity],
+                                vec![next_entity_state],
+                                &mut *entity_repo,
+                                &self.backlog,
+                            )
+                            .await;
                     }
                     Err(RunOrderError::NonFatal(err, _) | RunOrderError::Fatal(err, _)) => {
                         info!("Order dropped due to fatal error: {}", err);
+                        self.guard.on_run_failed(&[trivial_eq().coerce(entity_id)]);
                     }
                 }
                 return true;
@@ -226,6 +463,374 @@ where
     }
 }
 
+/// A generic executor suitable for cases when single order is applied to several entities at once.
+pub struct MultiEntityOrderExecutor<
+    Net,
+    Backlog,
+    Entities,
+    Blacklist,
+    Prover,
+    Ctx,
+    Ord,
+    Entity: EntitySnapshot,
+    TxCandidate,
+    Tx,
+    Err,
+> {
+    network: Net,
+    backlog: Arc<Mutex<Backlog>>,
+    entity_repo: Arc<Mutex<Entities>>,
+    guard: ExecutionGuard<Blacklist, Ord, Entity>,
+    prover: Prover,
+    ctx: Ctx,
+    state_ttl: Duration,
+    pd: PhantomData<(TxCandidate, Tx, Err)>,
+}
+
+impl<Net, Backlog, Entities, Blacklist, Prover, Ctx, Ord, Entity: EntitySnapshot, TxCandidate, Tx, Err>
+    MultiEntityOrderExecutor<
+        Net,
+        Backlog,
+        Entities,
+        Blacklist,
+        Prover,
+        Ctx,
+        Ord,
+        Entity,
+        TxCandidate,
+        Tx,
+        Err,
+    >
+{
+    pub fn new(
+        network: Net,
+        backlog: Arc<Mutex<Backlog>>,
+        entity_repo: Arc<Mutex<Entities>>,
+        blacklist: Blacklist,
+        prover: Prover,
+        ctx: Ctx,
+    ) -> Self {
+        Self {
+            network,
+            backlog,
+            entity_repo,
+            guard: ExecutionGuard::new(blacklist),
+            prover,
+            ctx,
+            state_ttl: DEFAULT_STATE_TTL,
+            pd: PhantomData,
+        }
+    }
+
+    /// Discard unconfirmed and predicted states of entities which didn't make it to the ledger
+    /// within `state_ttl`, [DEFAULT_STATE_TTL] by default.
+    pub fn with_state_ttl(self, state_ttl: Duration) -> Self {
+        Self { state_ttl, ..self }
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<Net, Backlog, Entities, Blacklist, Prover, Ctx, Ord, Entity, TxCandidate, Tx, Err> Executor
+    for MultiEntityOrderExecutor<
+        Net,
+        Backlog,
+        Entities,
+        Blacklist,
+        Prover,
+        Ctx,
+        Ord,
+        Entity,
+        TxCandidate,
+        Tx,
+        Err,
+    >
+where
+    Ord: MultiEntityOrder + Clone + Display + MaybeSend,
+    Ord::TOrderId: Display + MaybeSend,
+    Ord::TEntityId: IsEqual<Entity::StableId> + MaybeSend,
+    Entity: EntitySnapshot + RunMultiEntityOrder<Ord, Ctx, TxCandidate> + Clone + MaybeSend,
+    Entity::StableId: Copy + MaybeSend,
+    Entity::Version: MaybeSend,
+    Net: Network<Tx, Err> + MaybeSend,
+    Backlog: HotBacklog<Ord> + MaybeSend,
+    Entities: EntityRepo<Entity> + MaybeSend,
+    Blacklist: EntityBlacklist<Entity> + MaybeSend,
+    Prover: TxProver<TxCandidate, Tx> + MaybeSend,
+    Ctx: Clone + MaybeSend,
+    TxCandidate: MaybeSend,
+    Err: Display + MaybeSend,
+    Tx: Serialize + MaybeSend,
+{
+    async fn try_execute_next(&mut self) -> bool {
+        self.guard.release_suspended(&self.backlog).await;
+        let next_ord = {
+            let mut backlog = self.backlog.lock().await;
+            backlog.try_pop()
+        };
+        if let Some(ord) = next_ord {
+            let entity_ids: Vec<Entity::StableId> = ord
+                .get_entity_refs()
+                .into_iter()
+                .map(|eid| trivial_eq().coerce(eid))
+                .collect();
+            let Some(ord) = self.guard.suspend_if_blacklisted(ord, &entity_ids).await else {
+                return true;
+            };
+            info!(
+                "Running order {} against entities {:?}",
+                ord.get_self_ref(),
+                entity_ids
+            );
+            if let Some(entities) = resolve_entity_states_with_ttl(
+                entity_ids.clone(),
+                self.state_ttl,
+                Arc::clone(&self.entity_repo),
+            )
+            .await
+            {
+                match Entity::try_run(entities.clone(), ord.clone(), self.ctx.clone()) {
+                    Ok((tx_candidate, next_states)) => {
+                        let mut entity_repo = self.entity_repo.lock().await;
+                        let tx = self.prover.prove(tx_candidate);
+                        let result = self.network.submit_tx(tx).await;
+                        self.guard
+                            .on_submitted(
+                                result,
+                                ord,
+                                entities,
+                                next_states,
+                                &mut *entity_repo,
+                                &self.backlog,
+                            )
+                            .await;
+                    }
+                    Err(RunOrderError::NonFatal(err, _) | RunOrderError::Fatal(err, _)) => {
+                        info!("Order dropped due to fatal error: {}", err);
+                        self.guard.on_run_failed(&entity_ids);
+                    }
+                }
+                return true;
+            }
+            info!("Entities {:?} not found in storage", entity_ids);
+        }
+        false
+    }
+}
+
+/// A generic executor of orders naming a pair, which are run against the best pool of the pair.
+pub struct UnspecializedOrderExecutor<
+    Net,
+    Backlog,
+    Pools,
+    Blacklist,
+    Prover,
+    Ctx,
+    Ord,
+    Pool,
+    TxCandidate,
+    Tx,
+    Err,
+> where
+    Pool: EntitySnapshot + Tradable,
+{
+    network: Net,
+    backlog: Arc<Mutex<Backlog>>,
+    pool_repo: Arc<Mutex<Pools>>,
+    pair_index: PairIndex<Pool::PairId, Pool::StableId>,
+    guard: ExecutionGuard<Blacklist, Ord, Pool>,
+    /// Orders no pool was available for, along with the pools of their pairs which were
+    /// available at the time. Orders are returned to the backlog once the available pools change.
+    unmatched: Vec<(Ord, HashSet<Pool::StableId>)>,
+    prover: Prover,
+    ctx: Ctx,
+    state_ttl: Duration,
+    pd: PhantomData<(TxCandidate, Tx, Err)>,
+}
+
+impl<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
+    UnspecializedOrderExecutor<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
+where
+    Pool: EntitySnapshot + Tradable,
+{
+    /// Create executor choosing pools among the ones in `pair_index`,
+    /// see [pair_index_tracking](crate::box_resolver::pair_index::pair_index_tracking).
+    /// Pools persisted before startup are picked up if the index is seeded
+    /// with [PairIndex::load_confirmed].
+    pub fn new(
+        network: Net,
+        backlog: Arc<Mutex<Backlog>>,
+        pool_repo: Arc<Mutex<Pools>>,
+        pair_index: PairIndex<Pool::PairId, Pool::StableId>,
+        blacklist: Blacklist,
+        prover: Prover,
+        ctx: Ctx,
+    ) -> Self {
+        Self {
+            network,
+            backlog,
+            pool_repo,
+            pair_index,
+            guard: ExecutionGuard::new(blacklist),
+            unmatched: vec![],
+            prover,
+            ctx,
+            state_ttl: DEFAULT_STATE_TTL,
+            pd: PhantomData,
+        }
+    }
+
+    /// Discard unconfirmed and predicted states of entities which didn't make it to the ledger
+    /// within `state_ttl`, [DEFAULT_STATE_TTL] by default.
+    pub fn with_state_ttl(self, state_ttl: Duration) -> Self {
+        Self { state_ttl, ..self }
+    }
+}
+
+impl<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
+    UnspecializedOrderExecutor<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err>
+where
+    Ord: UnspecializedOrder + Display,
+    Ord::TOrderId: Display,
+    Ord::TPairId: IsEqual<Pool::PairId>,
+    Pool: EntitySnapshot + Tradable,
+    Backlog: HotBacklog<Ord>,
+    Blacklist: EntityBlacklist<Pool>,
+{
+    /// Known pools of the pair of `ord` which are not blacklisted.
+    async fn available_pools(&self, ord: &Ord) -> HashSet<Pool::StableId> {
+        let mut available_pools = HashSet::new();
+        for pool_id in self.pair_index.get(trivial_eq().coerce(ord.get_pair_ref())) {
+            if !self.guard.is_blacklisted(&pool_id).await {
+                available_pools.insert(pool_id);
+            }
+        }
+        available_pools
+    }
+
+    /// Return unmatched orders whose pairs got pools added, removed or cleared from the blacklist
+    /// to the backlog. Orders removed from the backlog meanwhile are dropped.
+    async fn release_unmatched(&mut self) {
+        let mut still_unmatched = vec![];
+        for (ord, pools) in std::mem::take(&mut self.unmatched) {
+            if self.backlog.lock().await.is_removed(ord.get_self_ref()) {
+                info!(
+                    "Unmatched order {} is dropped as it was removed",
+                    ord.get_self_ref()
+                );
+            } else if self.available_pools(&ord).await == pools {
+                still_unmatched.push((ord, pools));
+            } else {
+                info!("Order {} is resumed", ord.get_self_ref());
+                self.backlog.lock().await.put(ord);
+            }
+        }
+        self.unmatched = still_unmatched;
+    }
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl<Net, Backlog, Pools, Blacklist, Prover, Ctx, Ord, Pool, TxCandidate, Tx, Err> Executor
+    for UnspecializedOrderExecutor<
+        Net,
+        Backlog,
+        Pools,
+        Blacklist,
+        Prover,
+        Ctx,
+        Ord,
+        Pool,
+        TxCandidate,
+        Tx,
+        Err,
+    >
+where
+    Ord: UnspecializedOrder + Clone + Display + MaybeSend,
+    Ord::TOrderId: Display + MaybeSend,
+    Ord::TPairId: IsEqual<Pool::PairId> + Display + MaybeSend,
+    Pool: EntitySnapshot + Tradable + RunOrder<Ord, Ctx, TxCandidate> + QuoteOrder<Ord> + Clone + MaybeSend,
+    Pool::StableId: Copy + MaybeSend,
+    Pool::Version: MaybeSend,
+    Pool::PairId: MaybeSend,
+    Net: Network<Tx, Err> + MaybeSend,
+    Backlog: HotBacklog<Ord> + MaybeSend,
+    Pools: EntityRepo<Pool> + MaybeSend,
+    Blacklist: EntityBlacklist<Pool> + MaybeSend,
+    Prover: TxProver<TxCandidate, Tx> + MaybeSend,
+    Ctx: Clone + MaybeSend,
+    TxCandidate: MaybeSend,
+    Err: Display + MaybeSend,
+    Tx: Serialize + MaybeSend,
+{
+    async fn try_execute_next(&mut self) -> bool {
+        self.guard.release_suspended(&self.backlog).await;
+        self.release_unmatched().await;
+        let next_ord = {
+            let mut backlog = self.backlog.lock().await;
+            backlog.try_pop()
+        };
+        if let Some(ord) = next_ord {
+            let pair_id = ord.get_pair_ref();
+            let available_pools = self.available_pools(&ord).await;
+            let mut candidates = vec![];
+            for pool_id in available_pools.iter().copied() {
+                if let Some(pool) =
+                    resolve_entity_state_with_ttl(pool_id, self.state_ttl, Arc::clone(&self.pool_repo)).await
+                {
+                    candidates.push(pool);
+                }
+            }
+            if candidates.is_empty() {
+                info!(
+                    "No pools of pair {} available for order {}, holding it until pools change",
+                    pair_id,
+                    ord.get_self_ref()
+                );
+                self.unmatched.push((ord, available_pools));
+                return true;
+            }
+            info!(
+                "Choosing pool for order {} among {} pools of pair {}",
+                ord.get_self_ref(),
+                candidates.len(),
+                pair_id
+            );
+            match select_pool(&ord, candidates, ord.get_pool_selection(), self.ctx.clone()) {
+                Some((entity, tx_candidate, next_entity_state)) => {
+                    info!(
+                        "Running order {} against pool {}",
+                        ord.get_self_ref(),
+                        entity.stable_id()
+                    );
+                    let mut entity_repo = self.pool_repo.lock().await;
+                    let tx = self.prover.prove(tx_candidate);
+                    let result = self.network.submit_tx(tx).await;
+                    self.guard
+                        .on_submitted(
+                            result,
+                            ord,
+                            vec![entity],
+                            vec![next_entity_state],
+                            &mut *entity_repo,
+                            &self.backlog,
+                        )
+                        .await;
+                }
+                None => {
+                    info!(
+                        "Order {} dropped: it cannot be run against any pool of pair {}",
+                        ord.get_self_ref(),
+                        pair_id
+                    );
+                }
+            }
+            return true;
+        }
+        false
+    }
+}
+
 const THROTTLE_IDLE_MILLIS: u64 = 100;
 const THROTTLE_PREM_MILLIS: u64 = 1000;
 
@@ -252,3 +857,378 @@ pub fn executor_stream<'a, TExecutor: Executor + 'a>(
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fmt::{Display, Formatter};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use rand::{thread_rng, RngCore};
+    use tokio::sync::Mutex;
+
+    use crate::backlog::data::{OrderWeight, Weighted};
+    use crate::backlog::{HotBacklog, HotPriorityBacklog};
+    use crate::box_resolver::blacklist::{
+        DynamicBlacklist, DynamicBlacklistConfig, EntityBlacklist, StaticBlacklist,
+    };
+    use crate::box_resolver::pair_index::PairIndex;
+    use crate::box_resolver::persistence::inmemory::InMemoryEntityRepo;
+    use crate::box_resolver::persistence::tests::{BoxId, TestEntity, TokenId};
+    use crate::box_resolver::persistence::EntityRepo;
+    use crate::data::event::{Confirmed, Predicted};
+    use crate::data::order::{PoolSelection, SpecializedOrder, UniqueOrder, UnspecializedOrder};
+    use crate::data::{EntitySnapshot, Stable, Tradable};
+    use crate::executor::{
+        select_pool, ExecutionGuard, Executor, HotOrderExecutor, QuoteOrder, RunOrder, RunOrderError,
+        UnspecializedOrderExecutor,
+    };
+    use crate::network::Network;
+    use crate::tx_prover::TxProver;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Pool {
+        id: u8,
+        price: u64,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Swap {
+        min_price: u64,
+    }
+
+    impl RunOrder<Swap, (), ()> for Pool {
+        fn try_run(self, order: Swap, _ctx: ()) -> Result<((), Predicted<Self>), RunOrderError<Swap>> {
+            if self.price >= order.min_price {
+                Ok(((), Predicted(Pool { price: 0, ..self })))
+            } else {
+                Err(RunOrderError::NonFatal("price is too low".to_string(), order))
+            }
+        }
+    }
+
+    impl QuoteOrder<Swap> for Pool {
+        type Quote = u64;
+        fn quote(&self, _order: &Swap, _next_state: &Self) -> Self::Quote {
+            self.price
+        }
+    }
+
+    fn candidates() -> Vec<Pool> {
+        vec![
+            Pool { id: 0, price: 3 },
+            Pool { id: 1, price: 5 },
+            Pool { id: 2, price: 9 },
+            Pool { id: 3, price: 7 },
+        ]
+    }
+
+    #[test]
+    fn pool_with_best_quote_is_selected() {
+        let order = Swap { min_price: 4 };
+        let selected = select_pool(&order, candidates(), PoolSelection::BestQuote, ());
+        assert_eq!(selected.map(|(pool, _, _)| pool.id), Some(2));
+        let selected = select_pool(&order, candidates(), PoolSelection::FirstExecutable, ());
+        assert_eq!(selected.map(|(pool, _, _)| pool.id), Some(1));
+    }
+
+    #[test]
+    fn nothing_is_selected_if_order_cannot_be_run() {
+        let order = Swap { min_price: 10 };
+        assert!(select_pool(&order, candidates(), PoolSelection::BestQuote, ()).is_none());
+        assert!(select_pool::<_, Pool, _, ()>(&order, vec![], PoolSelection::FirstExecutable, ()).is_none());
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+    struct Deposit {
+        id: u64,
+        pool_id: TokenId,
+    }
+
+    impl SpecializedOrder for Deposit {
+        type TOrderId = u64;
+        type TPoolId = TokenId;
+
+        fn get_self_ref(&self) -> Self::TOrderId {
+            self.id
+        }
+
+        fn get_pool_ref(&self) -> Self::TPoolId {
+            self.pool_id
+        }
+    }
+
+    impl Weighted for Deposit {
+        fn weight(&self) -> OrderWeight {
+            OrderWeight::from(self.id)
+        }
+    }
+
+    impl Display for Deposit {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Deposit({})", self.id)
+        }
+    }
+
+    /// Deposits always fail to run.
+    impl RunOrder<Deposit, (), u64> for TestEntity {
+        fn try_run(self, order: Deposit, _ctx: ()) -> Result<(u64, Predicted<Self>), RunOrderError<Deposit>> {
+            Err(RunOrderError::NonFatal("deposit is rejected".to_string(), order))
+        }
+    }
+
+    /// Blacklist quarantining entities after the first failure.
+    fn dynamic_blacklist() -> DynamicBlacklist<TestEntity> {
+        fs::create_dir_all("./tmp").unwrap();
+        DynamicBlacklist::new(
+            DynamicBlacklistConfig {
+                max_failures: 1,
+                quarantine_secs: 3600,
+                entries: HashSet::new(),
+            },
+            PathBuf::from(format!("./tmp/blacklist-{}", thread_rng().next_u32())),
+        )
+    }
+
+    #[tokio::test]
+    async fn orders_against_blacklisted_pools_are_suspended_until_pools_are_cleared() {
+        let blacklist = dynamic_blacklist();
+        let backlog = Mutex::new(HotPriorityBacklog::<Deposit>::new(10.into()));
+        let mut guard = ExecutionGuard::<_, _, TestEntity>::new(blacklist.clone());
+        let ord = Deposit {
+            id: 1,
+            pool_id: TokenId::random(),
+        };
+        blacklist.add(ord.pool_id);
+        assert!(guard.suspend_if_blacklisted(ord, &[ord.pool_id]).await.is_none());
+        guard.release_suspended(&backlog).await;
+        assert!(backlog.lock().await.try_pop().is_none());
+        blacklist.remove(ord.pool_id);
+        guard.release_suspended(&backlog).await;
+        assert_eq!(backlog.lock().await.try_pop(), Some(ord));
+    }
+
+    #[tokio::test]
+    async fn suspended_orders_removed_from_backlog_are_dropped() {
+        let blacklist = dynamic_blacklist();
+        let backlog = Mutex::new(HotPriorityBacklog::<Deposit>::new(10.into()));
+        let mut guard = ExecutionGuard::<_, _, TestEntity>::new(blacklist.clone());
+        let ord = Deposit {
+            id: 1,
+            pool_id: TokenId::random(),
+        };
+        blacklist.add(ord.pool_id);
+        assert!(guard.suspend_if_blacklisted(ord, &[ord.pool_id]).await.is_none());
+        backlog.lock().await.remove(ord.id);
+        blacklist.remove(ord.pool_id);
+        guard.release_suspended(&backlog).await;
+        assert!(backlog.lock().await.try_pop().is_none());
+        assert!(guard.suspended.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pools_failing_to_run_orders_are_blacklisted() {
+        let blacklist = dynamic_blacklist();
+        let network = TestNetwork::default();
+        let backlog = Arc::new(Mutex::new(HotPriorityBacklog::<Deposit>::new(10.into())));
+        let pool = TestEntity {
+            token_id: TokenId::random(),
+            box_id: BoxId::random(),
+        };
+        let pool_repo = Arc::new(Mutex::new(InMemoryEntityRepo::<TestEntity>::new()));
+        pool_repo
+            .lock()
+            .await
+            .put_confirmed(Confirmed(pool.clone()))
+            .await;
+        let ord = Deposit {
+            id: 1,
+            pool_id: pool.token_id,
+        };
+        backlog.lock().await.put(ord);
+        let mut executor = HotOrderExecutor::<_, _, _, _, _, _, _, _, _, _, String>::new(
+            network.clone(),
+            Arc::clone(&backlog),
+            pool_repo,
+            blacklist.clone(),
+            TestProver,
+            (),
+        );
+        assert!(executor.try_execute_next().await);
+        assert!(network.submitted.lock().unwrap().is_empty());
+        assert!(EntityBlacklist::<TestEntity>::is_blacklisted(&blacklist, &pool.token_id).await);
+    }
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+    struct PairSwap {
+        id: u64,
+        pair: u8,
+    }
+
+    impl Display for PairSwap {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "PairSwap({})", self.id)
+        }
+    }
+
+    impl UniqueOrder for PairSwap {
+        type TOrderId = u64;
+
+        fn get_self_ref(&self) -> Self::TOrderId {
+            self.id
+        }
+    }
+
+    impl UnspecializedOrder for PairSwap {
+        type TPairId = u8;
+
+        fn get_pair_ref(&self) -> Self::TPairId {
+            self.pair
+        }
+
+        fn get_pool_selection(&self) -> PoolSelection {
+            PoolSelection::BestQuote
+        }
+    }
+
+    impl Weighted for PairSwap {
+        fn weight(&self) -> OrderWeight {
+            OrderWeight::from(self.id)
+        }
+    }
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct PairPool {
+        id: TokenId,
+        version: BoxId,
+        pair: u8,
+    }
+
+    impl PairPool {
+        fn random(pair: u8) -> Self {
+            Self {
+                id: TokenId::random(),
+                version: BoxId::random(),
+                pair,
+            }
+        }
+    }
+
+    impl Stable for PairPool {
+        type StableId = TokenId;
+
+        fn stable_id(&self) -> Self::StableId {
+            self.id
+        }
+        fn is_quasi_permanent(&self) -> bool {
+            true
+        }
+    }
+
+    impl EntitySnapshot for PairPool {
+        type Version = BoxId;
+
+        fn version(&self) -> Self::Version {
+            self.version
+        }
+    }
+
+    impl Tradable for PairPool {
+        type PairId = u8;
+
+        fn pair_id(&self) -> Self::PairId {
+            self.pair
+        }
+    }
+
+    /// Transactions are represented by ids of orders they execute.
+    impl RunOrder<PairSwap, (), u64> for PairPool {
+        fn try_run(
+            self,
+            order: PairSwap,
+            _ctx: (),
+        ) -> Result<(u64, Predicted<Self>), RunOrderError<PairSwap>> {
+            Ok((
+                order.id,
+                Predicted(PairPool {
+                    version: BoxId::random(),
+                    ..self
+                }),
+            ))
+        }
+    }
+
+    impl QuoteOrder<PairSwap> for PairPool {
+        type Quote = u64;
+        fn quote(&self, _order: &PairSwap, _next_state: &Self) -> Self::Quote {
+            0
+        }
+    }
+
+    struct TestProver;
+
+    impl TxProver<u64, u64> for TestProver {
+        fn prove(&self, candidate: u64) -> u64 {
+            candidate
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct TestNetwork {
+        submitted: Arc<std::sync::Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Network<u64, String> for TestNetwork {
+        async fn submit_tx(&mut self, tx: u64) -> Result<(), String> {
+            self.submitted.lock().unwrap().push(tx);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn order_without_pools_does_not_block_backlog() {
+        let network = TestNetwork::default();
+        let backlog = Arc::new(Mutex::new(HotPriorityBacklog::<PairSwap>::new(10.into())));
+        let pool_repo = Arc::new(Mutex::new(InMemoryEntityRepo::<PairPool>::new()));
+        let pair_index = PairIndex::new();
+        let pool = PairPool::random(1);
+        pool_repo
+            .lock()
+            .await
+            .put_confirmed(Confirmed(pool.clone()))
+            .await;
+        pair_index.add(pool.pair, pool.id);
+        let unmatched_ord = PairSwap { id: 2, pair: 0 };
+        let matched_ord = PairSwap { id: 1, pair: 1 };
+        backlog.lock().await.put(unmatched_ord);
+        backlog.lock().await.put(matched_ord);
+        let mut executor = UnspecializedOrderExecutor::new(
+            network.clone(),
+            Arc::clone(&backlog),
+            Arc::clone(&pool_repo),
+            pair_index.clone(),
+            StaticBlacklist::new(HashSet::new()),
+            TestProver,
+            (),
+        );
+        assert!(executor.try_execute_next().await);
+        assert!(executor.try_execute_next().await);
+        assert_eq!(*network.submitted.lock().unwrap(), vec![matched_ord.id]);
+        assert!(!executor.try_execute_next().await);
+        let pool = PairPool::random(0);
+        pool_repo
+            .lock()
+            .await
+            .put_confirmed(Confirmed(pool.clone()))
+            .await;
+        pair_index.add(pool.pair, pool.id);
+        assert!(executor.try_execute_next().await);
+        assert_eq!(
+            *network.submitted.lock().unwrap(),
+            vec![matched_ord.id, unmatched_ord.id]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 pub mod backlog;
 pub mod binary;
+pub mod bloom_filter;
 pub mod box_resolver;
 pub mod circular_filter;
 pub mod combinators;
@@ -8,9 +9,11 @@ pub mod event_sink;
 pub mod executor;
 pub mod ledger;
 pub mod maker;
+pub mod maybe_send;
 pub mod network;
 pub mod partitioning;
-pub(crate) mod rocks;
+pub mod reconnect;
+pub mod rocks;
 pub mod streaming;
 pub mod tx_hash;
 pub mod tx_prover;
@@ -0,0 +1,18 @@
+/// `Send + Sync` when the `send` feature is enabled, no-op otherwise.
+///
+/// Async traits of this crate ([EntityRepo](crate::box_resolver::persistence::EntityRepo),
+/// [Executor](crate::executor::Executor), etc.) are `?Send` by default so that they can be used
+/// on a current-thread runtime with non-thread-safe dependencies. Enabling `send` makes their
+/// futures `Send`, which requires all types captured by them to be thread-safe.
+#[cfg(feature = "send")]
+pub trait MaybeSend: Send + Sync {}
+
+#[cfg(feature = "send")]
+impl<T: Send + Sync> MaybeSend for T {}
+
+/// `Send + Sync` when the `send` feature is enabled, no-op otherwise.
+#[cfg(not(feature = "send"))]
+pub trait MaybeSend {}
+
+#[cfg(not(feature = "send"))]
+impl<T> MaybeSend for T {}
@@ -1,15 +1,18 @@
-use std::collections::hash_map::DefaultHasher;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+use std::ops::Range;
 
 /// Partitioned resource `R`.
 /// `K` - partitioning key;
-/// `N` - number of partitions.
+/// `N` - number of partitions;
+/// `H` - hasher used to assign keys to partitions.
 #[derive(Clone)]
-pub struct Partitioned<const N: usize, K, R> {
+pub struct Partitioned<const N: usize, K, R, H = SipHasher24> {
     inner: [R; N],
-    pd: PhantomData<K>,
+    /// Partitions run by this process.
+    assigned: Range<usize>,
+    pd: PhantomData<(K, H)>,
 }
 
 impl<const N: usize, K, R> Partitioned<N, K, R>
@@ -17,33 +20,382 @@ where
     K: Hash,
 {
     pub fn new(partitions: [R; N]) -> Self {
-        Self {
-            inner: partitions,
-            pd: PhantomData::default(),
-        }
+        Self::with_hasher(partitions)
     }
 
     pub fn new_unsafe(partitions: Vec<R>) -> Self
     where
         R: Debug,
     {
+        Self::with_hasher(<[R; N]>::try_from(partitions).unwrap())
+    }
+}
+
+impl<const N: usize, K, R, H> Partitioned<N, K, R, H>
+where
+    K: Hash,
+    H: Hasher + Default,
+{
+    /// Create partitioned resource assigning keys to partitions with a custom hasher `H`.
+    pub fn with_hasher(partitions: [R; N]) -> Self {
         Self {
-            inner: <[R; N]>::try_from(partitions).unwrap(),
+            inner: partitions,
+            assigned: 0..N,
             pd: PhantomData::default(),
         }
     }
 
+    /// Run only the given range of partitions in this process,
+    /// so that keys can be split between several processes.
+    pub fn assign(self, partitions: Range<usize>) -> Self {
+        assert!(
+            partitions.start < partitions.end && partitions.end <= N,
+            "Invalid partitions {:?} out of {}",
+            partitions,
+            N
+        );
+        Self {
+            assigned: partitions,
+            ..self
+        }
+    }
+
+    /// Index of the partition the given `key` belongs to.
+    pub fn partition_of(&self, key: K) -> usize {
+        (hash_partitioning_key_with::<H, K>(key) % N as u64) as usize
+    }
+
+    /// Check whether the given `key` belongs to a partition run by this process.
+    pub fn is_assigned(&self, key: K) -> bool {
+        self.assigned.contains(&self.partition_of(key))
+    }
+
+    pub fn get(&self, key: K) -> &R {
+        &self.inner[self.partition_of(key)]
+    }
+
+    pub fn get_mut(&mut self, key: K) -> &mut R {
+        let ix = self.partition_of(key);
+        &mut self.inner[ix]
+    }
+}
+
+/// Partitioned resource `R` with the number of partitions defined at runtime.
+/// Keys are assigned to partitions with rendezvous hashing, so that
+/// changing the number of partitions moves the minimum number of keys.
+/// `K` - partitioning key;
+/// `H` - hasher used to assign keys to partitions.
+#[derive(Clone)]
+pub struct DynamicPartitioned<K, R, H = SipHasher24> {
+    inner: Vec<R>,
+    /// Partitions run by this process, all of them if not set.
+    assigned: Option<Range<usize>>,
+    pd: PhantomData<(K, H)>,
+}
+
+impl<K, R> DynamicPartitioned<K, R>
+where
+    K: Hash,
+{
+    pub fn new(partitions: Vec<R>) -> Self {
+        Self::with_hasher(partitions)
+    }
+
+    /// Create `n` partitions initializing the `i`-th one with `f(i)`.
+    pub fn from_fn<F: FnMut(usize) -> R>(n: usize, f: F) -> Self {
+        Self::new((0..n).map(f).collect())
+    }
+}
+
+impl<K, R, H> DynamicPartitioned<K, R, H>
+where
+    K: Hash,
+    H: Hasher + Default,
+{
+    /// Create partitioned resource assigning keys to partitions with a custom hasher `H`.
+    pub fn with_hasher(partitions: Vec<R>) -> Self {
+        assert!(!partitions.is_empty(), "At least one partition is required");
+        Self {
+            inner: partitions,
+            assigned: None,
+            pd: PhantomData,
+        }
+    }
+
+    /// Run only the given range of partitions in this process,
+    /// so that keys can be split between several processes.
+    pub fn assign(self, partitions: Range<usize>) -> Self {
+        assert!(
+            partitions.start < partitions.end && partitions.end <= self.inner.len(),
+            "Invalid partitions {:?} out of {}",
+            partitions,
+            self.inner.len()
+        );
+        Self {
+            assigned: Some(partitions),
+            ..self
+        }
+    }
+
+    /// Index of the partition the given `key` belongs to.
+    pub fn partition_of(&self, key: K) -> usize {
+        (0..self.inner.len())
+            .max_by_key(|ix| hash_partitioning_key_with::<H, _>((&key, *ix as u64)))
+            .unwrap()
+    }
+
+    /// Check whether the given `key` belongs to a partition run by this process.
+    pub fn is_assigned(&self, key: K) -> bool {
+        self.assigned
+            .as_ref()
+            .map_or(true, |assigned| assigned.contains(&self.partition_of(key)))
+    }
+
     pub fn get(&self, key: K) -> &R {
-        &self.inner[(hash_partitioning_key(key) % N as u64) as usize]
+        &self.inner[self.partition_of(key)]
     }
 
     pub fn get_mut(&mut self, key: K) -> &mut R {
-        &mut self.inner[(hash_partitioning_key(key) % N as u64) as usize]
+        let ix = self.partition_of(key);
+        &mut self.inner[ix]
+    }
+
+    /// Number of partitions.
+    pub fn num_partitions(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &R> {
+        self.inner.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut R> {
+        self.inner.iter_mut()
+    }
+
+    /// Change the number of partitions to `n` initializing new partitions with `f(i)`.
+    /// Only keys of removed partitions and keys falling into new ones change their partition.
+    /// Assigned range is narrowed to the remaining partitions.
+    pub fn resize_with<F: FnMut(usize) -> R>(&mut self, n: usize, f: F) {
+        assert!(n > 0, "At least one partition is required");
+        let len = self.inner.len();
+        if n < len {
+            self.inner.truncate(n);
+        } else {
+            self.inner.extend((len..n).map(f));
+        }
+        if let Some(assigned) = &mut self.assigned {
+            assigned.end = assigned.end.min(n);
+            assigned.start = assigned.start.min(assigned.end);
+        }
     }
 }
 
+/// Hash partitioning `key` with the default stable hasher.
 pub fn hash_partitioning_key<K: Hash>(key: K) -> u64 {
-    let mut hasher = DefaultHasher::new();
+    hash_partitioning_key_with::<SipHasher24, K>(key)
+}
+
+pub fn hash_partitioning_key_with<H: Hasher + Default, K: Hash>(key: K) -> u64 {
+    let mut hasher = H::default();
     key.hash(&mut hasher);
     hasher.finish()
 }
+
+/// SipHash-2-4 with fixed keys.
+/// Unlike `DefaultHasher` its output is guaranteed to stay the same across Rust releases,
+/// platforms and processes, so partition assignment can be shared and persisted.
+#[derive(Clone, Debug)]
+pub struct SipHasher24 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    /// Bytes of the current incomplete word.
+    tail: u64,
+    ntail: usize,
+    /// Total number of bytes written.
+    length: usize,
+}
+
+impl SipHasher24 {
+    pub const DEFAULT_KEYS: (u64, u64) = (0x0706050403020100, 0x0f0e0d0c0b0a0908);
+
+    pub fn new_with_keys(k0: u64, k1: u64) -> Self {
+        Self {
+            v0: k0 ^ 0x736f6d6570736575,
+            v1: k1 ^ 0x646f72616e646f6d,
+            v2: k0 ^ 0x6c7967656e657261,
+            v3: k1 ^ 0x7465646279746573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+impl Default for SipHasher24 {
+    fn default() -> Self {
+        let (k0, k1) = Self::DEFAULT_KEYS;
+        Self::new_with_keys(k0, k1)
+    }
+}
+
+impl Hasher for SipHasher24 {
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        state.compress(((self.length as u64) << 56) | self.tail);
+        state.v2 ^= 0xff;
+        for _ in 0..4 {
+            state.round();
+        }
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.tail |= (*byte as u64) << (8 * self.ntail);
+            self.ntail += 1;
+            if self.ntail == 8 {
+                self.compress(self.tail);
+                self.tail = 0;
+                self.ntail = 0;
+            }
+        }
+        self.length += bytes.len();
+    }
+
+    // Integers are hashed in a platform-independent way.
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16)
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32)
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128)
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hasher;
+
+    use crate::partitioning::{hash_partitioning_key, DynamicPartitioned, Partitioned, SipHasher24};
+
+    #[test]
+    fn siphash_reference_vectors() {
+        let hash = |len: u8| {
+            let mut hasher = SipHasher24::default();
+            hasher.write(&(0..len).collect::<Vec<_>>());
+            hasher.finish()
+        };
+        assert_eq!(hash(0), 0x726fdb47dd0e0e31);
+        assert_eq!(hash(15), 0xa129ca6149be45e5);
+    }
+
+    #[test]
+    fn partition_assignment_is_stable() {
+        // Must never change, otherwise keys are reassigned to other partitions.
+        assert_eq!(hash_partitioning_key(42u64), 0x2cbe815a255faf48);
+        assert_eq!(hash_partitioning_key(42u64), hash_partitioning_key(42usize));
+    }
+
+    #[test]
+    fn only_assigned_partitions_are_run() {
+        let partitioned = Partitioned::<4, u64, usize>::new([0, 1, 2, 3]).assign(1..3);
+        for key in 0..64u64 {
+            let ix = partitioned.partition_of(key);
+            assert_eq!(*partitioned.get(key), ix);
+            assert_eq!(partitioned.is_assigned(key), (1..3).contains(&ix));
+        }
+    }
+
+    #[test]
+    fn dynamic_partitions_are_iterated() {
+        let mut partitioned = DynamicPartitioned::<u64, usize>::from_fn(3, |ix| ix);
+        assert_eq!(partitioned.num_partitions(), 3);
+        for p in partitioned.iter_mut() {
+            *p += 10;
+        }
+        assert_eq!(partitioned.iter().copied().collect::<Vec<_>>(), vec![10, 11, 12]);
+        for key in 0..64u64 {
+            assert_eq!(*partitioned.get(key), partitioned.partition_of(key) + 10);
+        }
+    }
+
+    #[test]
+    fn resizing_moves_minimum_of_keys() {
+        let mut partitioned = DynamicPartitioned::<u64, usize>::from_fn(4, |ix| ix);
+        let before: Vec<_> = (0..1000u64).map(|key| partitioned.partition_of(key)).collect();
+        partitioned.resize_with(5, |ix| ix);
+        let after: Vec<_> = (0..1000u64).map(|key| partitioned.partition_of(key)).collect();
+        let moved: Vec<_> = before.iter().zip(&after).filter(|(b, a)| b != a).collect();
+        // Keys only move to the new partition, roughly 1/5 of them.
+        assert!(moved.iter().all(|(_, a)| **a == 4));
+        assert!(moved.len() > 100 && moved.len() < 300);
+        partitioned.resize_with(4, |ix| ix);
+        let restored: Vec<_> = (0..1000u64).map(|key| partitioned.partition_of(key)).collect();
+        assert_eq!(restored, before);
+    }
+
+    #[test]
+    fn assigned_range_is_narrowed_on_shrink() {
+        let mut partitioned = DynamicPartitioned::<u64, usize>::from_fn(4, |ix| ix).assign(2..4);
+        partitioned.resize_with(3, |ix| ix);
+        for key in 0..64u64 {
+            assert_eq!(partitioned.is_assigned(key), partitioned.partition_of(key) == 2);
+        }
+    }
+}
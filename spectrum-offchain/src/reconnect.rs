@@ -0,0 +1,201 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use futures_timer::Delay;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BackoffConfig {
+    /// Delay before the first reconnection attempt.
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub initial_delay: Duration,
+    /// Upper bound of the delay between attempts.
+    #[serde_as(as = "serde_with::DurationMilliSeconds<u64>")]
+    pub max_delay: Duration,
+    /// Factor the delay grows by after each failed attempt.
+    pub multiplier: u32,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+        }
+    }
+}
+
+/// Exponentially growing delay between connection attempts.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    conf: BackoffConfig,
+    next_delay: Duration,
+}
+
+impl Backoff {
+    pub fn new(conf: BackoffConfig) -> Self {
+        Self {
+            conf,
+            next_delay: conf.initial_delay,
+        }
+    }
+
+    /// Get delay before the next attempt and grow the following one.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next_delay;
+        self.next_delay = delay
+            .saturating_mul(self.conf.multiplier)
+            .min(self.conf.max_delay);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next_delay = self.conf.initial_delay;
+    }
+}
+
+/// Keep trying to establish a connection, waiting according to the `backoff` between attempts.
+/// The `backoff` is not reset once connected, callers reset it once the connection proves to be usable,
+/// so that a connection which breaks right away is retried with growing delays.
+pub async fn connect_with_backoff<Conn, Connect, Fut, Err>(
+    mut connect: Connect,
+    backoff: &mut Backoff,
+) -> Conn
+where
+    Connect: FnMut() -> Fut,
+    Fut: Future<Output = Result<Conn, Err>>,
+    Err: Display,
+{
+    loop {
+        match connect().await {
+            Ok(conn) => return conn,
+            Err(err) => {
+                let delay = backoff.next_delay();
+                warn!(target: "reconnect", "Connection failed: {}, retrying in {:?}", err, delay);
+                Delay::new(delay).await;
+            }
+        }
+    }
+}
+
+/// Connection which is (re)established lazily once it is needed.
+pub struct Supervised<Conn, Connect> {
+    conn: Option<Conn>,
+    connect: Connect,
+    backoff: Backoff,
+}
+
+impl<Conn, Connect, Fut, Err> Supervised<Conn, Connect>
+where
+    Connect: FnMut() -> Fut,
+    Fut: Future<Output = Result<Conn, Err>>,
+    Err: Display,
+{
+    pub fn new(connect: Connect, conf: BackoffConfig) -> Self {
+        Self {
+            conn: None,
+            connect,
+            backoff: Backoff::new(conf),
+        }
+    }
+
+    /// Get live connection, connecting with backoff if there is none.
+    pub async fn get(&mut self) -> &mut Conn {
+        if self.conn.is_none() {
+            let conn = connect_with_backoff(&mut self.connect, &mut self.backoff).await;
+            info!(target: "reconnect", "Connection established");
+            self.backoff.reset();
+            self.conn = Some(conn);
+        }
+        self.conn.as_mut().unwrap()
+    }
+
+    /// Discard broken connection so that a new one is established on the next access.
+    /// The discarded connection is returned so that it can be closed gracefully.
+    pub fn reset(&mut self) -> Option<Conn> {
+        self.conn.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::reconnect::{connect_with_backoff, Backoff, BackoffConfig, Supervised};
+
+    #[test]
+    fn backoff_grows_up_to_max_delay() {
+        let mut backoff = Backoff::new(BackoffConfig {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            multiplier: 2,
+        });
+        let delays = (0..5)
+            .map(|_| backoff.next_delay().as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn backoff_is_not_reset_on_connect() {
+        let mut backoff = Backoff::new(BackoffConfig {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(500),
+            multiplier: 2,
+        });
+        let mut attempts = 0;
+        let conn = connect_with_backoff(
+            || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt == 1 {
+                        Err("refused")
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+            &mut backoff,
+        )
+        .await;
+        assert_eq!(conn, 2);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(20));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn connection_is_reestablished_after_reset() {
+        let mut attempts = 0;
+        let mut conn = Supervised::new(
+            || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt % 2 == 1 {
+                        Err("refused")
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            },
+            BackoffConfig {
+                initial_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+                multiplier: 2,
+            },
+        );
+        assert_eq!(*conn.get().await, 2);
+        assert_eq!(*conn.get().await, 2);
+        assert_eq!(conn.reset(), Some(2));
+        assert_eq!(*conn.get().await, 4);
+    }
+}
@@ -1,6 +1,272 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::Duration;
+
+use futures::{stream, Stream};
+use futures_timer::Delay;
+use log::warn;
+use parking_lot::Mutex;
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamilyDescriptor, DBCompressionType, MultiThreaded, Options,
+    DEFAULT_COLUMN_FAMILY_NAME,
+};
 use serde::{Deserialize, Serialize};
 
+pub mod schema;
+
+/// RocksDB instance possibly shared by several stores.
+/// Atomic updates touching several keys are written in a single [rocksdb::WriteBatch].
+pub type RocksDB = rocksdb::DBWithThreadMode<MultiThreaded>;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RocksConfig {
     pub db_path: String,
+    /// Column family to keep data of the store in. Default column family is used if not set.
+    /// Stores sharing a DB must use column families of their own.
+    #[serde(default)]
+    pub column_family: Option<String>,
+    /// Capacity of the LRU block cache in bytes.
+    #[serde(default)]
+    pub block_cache_size: Option<usize>,
+    #[serde(default)]
+    pub compression: Option<Compression>,
+    /// Bits per key of bloom filters. Bloom filters are disabled if not set.
+    #[serde(default)]
+    pub bloom_filter_bits_per_key: Option<f64>,
+}
+
+impl RocksConfig {
+    /// Config with default tuning keeping data in the default column family.
+    pub fn new(db_path: String) -> Self {
+        Self {
+            db_path,
+            column_family: None,
+            block_cache_size: None,
+            compression: None,
+            bloom_filter_bits_per_key: None,
+        }
+    }
+
+    pub fn column_family(&self) -> &str {
+        self.column_family
+            .as_deref()
+            .unwrap_or(DEFAULT_COLUMN_FAMILY_NAME)
+    }
+
+    fn cf_options(&self) -> Options {
+        let mut opts = Options::default();
+        let mut table_opts = BlockBasedOptions::default();
+        if let Some(capacity) = self.block_cache_size {
+            table_opts.set_block_cache(&Cache::new_lru_cache(capacity));
+        }
+        if let Some(bits_per_key) = self.bloom_filter_bits_per_key {
+            table_opts.set_bloom_filter(bits_per_key, false);
+        }
+        opts.set_block_based_table_factory(&table_opts);
+        if let Some(compression) = self.compression {
+            opts.set_compression_type(compression.into());
+        }
+        opts
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl From<Compression> for DBCompressionType {
+    fn from(value: Compression) -> Self {
+        match value {
+            Compression::None => DBCompressionType::None,
+            Compression::Snappy => DBCompressionType::Snappy,
+            Compression::Lz4 => DBCompressionType::Lz4,
+            Compression::Zstd => DBCompressionType::Zstd,
+        }
+    }
+}
+
+/// DBs opened by the process along with the settings of column families of the stores they hold.
+#[derive(Default)]
+struct Registry {
+    open_dbs: HashMap<String, Weak<RocksDB>>,
+    /// Settings of the stores by DB path and column family.
+    cf_confs: HashMap<(String, String), RocksConfig>,
+}
+
+impl Registry {
+    fn cf_options(&self, db_path: &str, cf: &str) -> Options {
+        self.cf_confs
+            .get(&(db_path.to_string(), cf.to_string()))
+            .map(RocksConfig::cf_options)
+            .unwrap_or_default()
+    }
+}
+
+static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+/// Open DB at `conf.db_path` making sure the column family of the store exists in it.
+/// A DB is opened once per process and shared by all stores pointing to it.
+/// Each column family is opened with the options of its own store if the store is known
+/// by the time the DB is opened, and with default options otherwise.
+/// Use [open_all] to open the DB with the column families of all its stores at once.
+pub fn open(conf: &RocksConfig) -> Arc<RocksDB> {
+    open_all(std::slice::from_ref(conf))
+}
+
+/// Open DB shared by the stores with the given settings, each store keeping its data
+/// in its own column family configured according to its settings.
+/// All `confs` must point to the same DB.
+pub fn open_all(confs: &[RocksConfig]) -> Arc<RocksDB> {
+    let db_path = &confs.first().expect("At least one store is expected").db_path;
+    assert!(
+        confs.iter().all(|conf| conf.db_path == *db_path),
+        "Stores must point to the same DB"
+    );
+    let mut registry = REGISTRY.get_or_init(Default::default).lock();
+    for conf in confs {
+        registry
+            .cf_confs
+            .insert((db_path.clone(), conf.column_family().to_string()), conf.clone());
+    }
+    if let Some(db) = registry.open_dbs.get(db_path).and_then(Weak::upgrade) {
+        for conf in confs {
+            if db.cf_handle(conf.column_family()).is_none() {
+                db.create_cf(conf.column_family(), &conf.cf_options()).unwrap();
+            }
+        }
+        return db;
+    }
+    let mut db_opts = Options::default();
+    db_opts.create_if_missing(true);
+    db_opts.create_missing_column_families(true);
+    let cfs: BTreeSet<String> = rocksdb::DB::list_cf(&db_opts, db_path)
+        .unwrap_or_default()
+        .into_iter()
+        .chain([DEFAULT_COLUMN_FAMILY_NAME.to_string()])
+        .chain(confs.iter().map(|conf| conf.column_family().to_string()))
+        .collect();
+    let descriptors = cfs.into_iter().map(|name| {
+        let opts = registry.cf_options(db_path, &name);
+        ColumnFamilyDescriptor::new(name, opts)
+    });
+    let db = Arc::new(RocksDB::open_cf_descriptors(&db_opts, db_path, descriptors).unwrap());
+    registry.open_dbs.insert(db_path.clone(), Arc::downgrade(&db));
+    db
+}
+
+/// Open DB at `conf.db_path` as a read-only secondary instance keeping its own
+/// info logs in `secondary_path`. The DB may be in use by a primary instance in another process.
+/// Secondary instance sees writes of the primary only after it catches up with it,
+/// see [catch_up_stream].
+pub fn open_as_secondary(conf: &RocksConfig, secondary_path: &str) -> Arc<RocksDB> {
+    let mut db_opts = Options::default();
+    db_opts.set_max_open_files(-1);
+    let registry = REGISTRY.get_or_init(Default::default).lock();
+    let descriptors = RocksDB::list_cf(&db_opts, &conf.db_path)
+        .unwrap()
+        .into_iter()
+        .map(|name| {
+            let opts = if name == conf.column_family() {
+                conf.cf_options()
+            } else {
+                registry.cf_options(&conf.db_path, &name)
+            };
+            ColumnFamilyDescriptor::new(name, opts)
+        });
+    Arc::new(
+        RocksDB::open_cf_descriptors_as_secondary(
+            &db_opts,
+            conf.db_path.as_str(),
+            secondary_path,
+            descriptors,
+        )
+        .unwrap(),
+    )
+}
+
+/// Catch up the secondary `db` with its primary every `interval`.
+pub fn catch_up_stream(db: Arc<RocksDB>, interval: Duration) -> impl Stream<Item = ()> {
+    stream::unfold(db, move |db| async move {
+        Delay::new(interval).await;
+        if let Err(err) = db.try_catch_up_with_primary() {
+            warn!(target: "rocks", "Failed to catch up with primary: {}", err);
+        }
+        Some(((), db))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use rand::{thread_rng, RngCore};
+
+    use crate::rocks::{catch_up_stream, open, open_all, open_as_secondary, Compression, RocksConfig};
+
+    #[test]
+    fn stores_share_db_in_separate_column_families() {
+        let db_path = format!("./tmp/{}", thread_rng().next_u32());
+        let conf_a = RocksConfig {
+            column_family: Some("a".to_string()),
+            block_cache_size: Some(1 << 20),
+            compression: Some(Compression::Lz4),
+            bloom_filter_bits_per_key: Some(10.0),
+            ..RocksConfig::new(db_path.clone())
+        };
+        let conf_b = RocksConfig {
+            column_family: Some("b".to_string()),
+            ..RocksConfig::new(db_path)
+        };
+        let db_a = open(&conf_a);
+        let db_b = open(&conf_b);
+        assert!(Arc::ptr_eq(&db_a, &db_b));
+        let (cf_a, cf_b) = (db_a.cf_handle("a").unwrap(), db_b.cf_handle("b").unwrap());
+        db_a.put_cf(&cf_a, "key", "a").unwrap();
+        db_b.put_cf(&cf_b, "key", "b").unwrap();
+        assert_eq!(db_a.get_cf(&cf_a, "key").unwrap(), Some(b"a".to_vec()));
+        assert_eq!(db_b.get_cf(&cf_b, "key").unwrap(), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn stores_opened_together_share_db() {
+        let db_path = format!("./tmp/{}", thread_rng().next_u32());
+        let conf_a = RocksConfig {
+            column_family: Some("a".to_string()),
+            compression: Some(Compression::Zstd),
+            ..RocksConfig::new(db_path.clone())
+        };
+        let conf_b = RocksConfig {
+            column_family: Some("b".to_string()),
+            bloom_filter_bits_per_key: Some(10.0),
+            ..RocksConfig::new(db_path)
+        };
+        let db = open_all(&[conf_a.clone(), conf_b.clone()]);
+        assert!(db.cf_handle("a").is_some());
+        assert!(db.cf_handle("b").is_some());
+        assert!(Arc::ptr_eq(&db, &open(&conf_a)));
+        assert!(Arc::ptr_eq(&db, &open(&conf_b)));
+    }
+
+    #[tokio::test]
+    async fn secondary_reads_writes_of_primary() {
+        let conf = RocksConfig {
+            column_family: Some("a".to_string()),
+            ..RocksConfig::new(format!("./tmp/{}", thread_rng().next_u32()))
+        };
+        let primary = open(&conf);
+        let secondary = open_as_secondary(&conf, &format!("./tmp/{}", thread_rng().next_u32()));
+        let mut catch_up = Box::pin(catch_up_stream(Arc::clone(&secondary), Duration::from_millis(1)));
+        let cf = primary.cf_handle("a").unwrap();
+        primary.put_cf(&cf, "key", "value").unwrap();
+        catch_up.next().await;
+        let cf = secondary.cf_handle("a").unwrap();
+        assert_eq!(secondary.get_cf(&cf, "key").unwrap(), Some(b"value".to_vec()));
+        assert!(secondary.put_cf(&cf, "key", "other").is_err());
+    }
 }
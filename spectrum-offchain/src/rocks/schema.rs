@@ -0,0 +1,344 @@
+use std::cmp::Ordering;
+
+use log::{info, warn};
+use rocksdb::{AsColumnFamilyRef, IteratorMode, WriteBatch};
+
+use crate::rocks::RocksDB;
+
+/// Key under which schema version of a store is kept in its column family.
+/// Leading zero byte makes it sort before keys of the stores.
+pub const SCHEMA_VERSION_KEY: &[u8] = b"\x00schema:version";
+
+/// Key under which the name of the store owning a column family is kept.
+/// Stores sharing a column family would share its schema version, so this is rejected.
+pub const SCHEMA_STORE_KEY: &[u8] = b"\x00schema:store";
+
+/// Version assumed for non-empty DBs created before schema versioning was introduced.
+pub const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+/// Upgrade step of a store's schema from `from_version` to `from_version + 1`.
+pub struct Migration {
+    pub from_version: u32,
+    pub description: &'static str,
+    /// Upgrade data in the given column family of the DB.
+    /// Updates are staged in the given batch which is written along with the new schema version,
+    /// so that an interrupted migration is applied from scratch on the next start.
+    pub apply: fn(&RocksDB, &str, &mut WriteBatch) -> Result<(), rocksdb::Error>,
+}
+
+/// Layout of a RocksDB-backed store.
+pub struct Schema {
+    /// Name of the store used in logs and errors.
+    pub store: &'static str,
+    /// Schema version the code works with.
+    pub version: u32,
+    /// Upgrade steps from older versions.
+    pub migrations: &'static [Migration],
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaError {
+    #[error("{store}: stored schema version {stored} is newer than supported version {supported}")]
+    UnsupportedVersion {
+        store: &'static str,
+        stored: u32,
+        supported: u32,
+    },
+    #[error("{store}: no migration from schema version {from_version}")]
+    MissingMigration { store: &'static str, from_version: u32 },
+    #[error("{store}: stored schema version {stored} is older than {supported}, open the store as primary to migrate")]
+    OutdatedVersion {
+        store: &'static str,
+        stored: u32,
+        supported: u32,
+    },
+    #[error("{store}: malformed schema version")]
+    MalformedVersion { store: &'static str },
+    #[error("{store}: column family {cf} does not exist")]
+    MissingColumnFamily { store: &'static str, cf: String },
+    #[error("{store}: column family {cf} is used by store {owner}, configure a column family of its own")]
+    SharedColumnFamily {
+        store: &'static str,
+        cf: String,
+        owner: String,
+    },
+    #[error("db error: {0}")]
+    Db(#[from] rocksdb::Error),
+}
+
+/// Whether the key is one of the keys kept by the schema rather than by the store.
+pub fn is_schema_key(key: &[u8]) -> bool {
+    key == SCHEMA_VERSION_KEY || key == SCHEMA_STORE_KEY
+}
+
+/// Make sure column family `cf_name` is not owned by a store other than the one of the `schema`.
+/// Returns `true` if the column family is owned by the store already.
+fn check_owner(
+    db: &RocksDB,
+    cf: &impl AsColumnFamilyRef,
+    cf_name: &str,
+    schema: &Schema,
+) -> Result<bool, SchemaError> {
+    match db.get_cf(cf, SCHEMA_STORE_KEY)? {
+        Some(raw) => {
+            let owner = bincode::deserialize::<String>(&raw)
+                .map_err(|_| SchemaError::MalformedVersion { store: schema.store })?;
+            if owner == schema.store {
+                Ok(true)
+            } else {
+                Err(SchemaError::SharedColumnFamily {
+                    store: schema.store,
+                    cf: cf_name.to_string(),
+                    owner,
+                })
+            }
+        }
+        None => Ok(false),
+    }
+}
+
+/// Get schema version stored in the column family `cf` of the `db`.
+pub fn stored_version(db: &RocksDB, cf: &str) -> Result<Option<u32>, rocksdb::Error> {
+    let cf = db.cf_handle(cf).unwrap();
+    Ok(db
+        .get_cf(&cf, SCHEMA_VERSION_KEY)?
+        .and_then(|raw| bincode::deserialize(&raw).ok()))
+}
+
+/// Make sure column family `cf` of the `db` matches the version of the given `schema` without
+/// modifying it. Used by read-only instances which cannot migrate the store.
+/// Column families which carry no version yet are accepted if they are empty.
+pub fn check(db: &RocksDB, cf: &str, schema: &Schema) -> Result<(), SchemaError> {
+    let cf_name = cf;
+    let cf = db
+        .cf_handle(cf_name)
+        .ok_or_else(|| SchemaError::MissingColumnFamily {
+            store: schema.store,
+            cf: cf_name.to_string(),
+        })?;
+    check_owner(db, &cf, cf_name, schema)?;
+    let version = match db.get_cf(&cf, SCHEMA_VERSION_KEY)? {
+        Some(raw) => {
+            bincode::deserialize(&raw).map_err(|_| SchemaError::MalformedVersion { store: schema.store })?
+        }
+        None if db.iterator_cf(&cf, IteratorMode::Start).next().is_none() => schema.version,
+        None => LEGACY_SCHEMA_VERSION,
+    };
+    match version.cmp(&schema.version) {
+        Ordering::Greater => Err(SchemaError::UnsupportedVersion {
+            store: schema.store,
+            stored: version,
+            supported: schema.version,
+        }),
+        Ordering::Less => Err(SchemaError::OutdatedVersion {
+            store: schema.store,
+            stored: version,
+            supported: schema.version,
+        }),
+        Ordering::Equal => Ok(()),
+    }
+}
+
+/// Bring column family `cf` of the `db` to the version of the given `schema`
+/// applying pending migrations in order.
+/// Fresh column families are stamped with the current version.
+/// Fails if the stored version is newer than the one of the `schema`.
+pub fn migrate(db: &RocksDB, cf: &str, schema: &Schema) -> Result<(), SchemaError> {
+    let cf_name = cf;
+    let cf = db
+        .cf_handle(cf_name)
+        .ok_or_else(|| SchemaError::MissingColumnFamily {
+            store: schema.store,
+            cf: cf_name.to_string(),
+        })?;
+    let owned = check_owner(db, &cf, cf_name, schema)?;
+    let mut version = match db.get_cf(&cf, SCHEMA_VERSION_KEY)? {
+        Some(raw) => {
+            bincode::deserialize(&raw).map_err(|_| SchemaError::MalformedVersion { store: schema.store })?
+        }
+        None if db.iterator_cf(&cf, IteratorMode::Start).next().is_none() => {
+            let mut batch = WriteBatch::default();
+            batch.put_cf(&cf, SCHEMA_STORE_KEY, bincode::serialize(schema.store).unwrap());
+            batch.put_cf(
+                &cf,
+                SCHEMA_VERSION_KEY,
+                bincode::serialize(&schema.version).unwrap(),
+            );
+            db.write(batch)?;
+            return Ok(());
+        }
+        None => {
+            warn!(
+                target: "schema",
+                "{}: no schema version found, assuming {}",
+                schema.store,
+                LEGACY_SCHEMA_VERSION
+            );
+            LEGACY_SCHEMA_VERSION
+        }
+    };
+    if version > schema.version {
+        return Err(SchemaError::UnsupportedVersion {
+            store: schema.store,
+            stored: version,
+            supported: schema.version,
+        });
+    }
+    while version < schema.version {
+        let migration = schema
+            .migrations
+            .iter()
+            .find(|m| m.from_version == version)
+            .ok_or(SchemaError::MissingMigration {
+                store: schema.store,
+                from_version: version,
+            })?;
+        info!(
+            target: "schema",
+            "{}: migrating from version {} to {}: {}",
+            schema.store,
+            version,
+            version + 1,
+            migration.description
+        );
+        let mut batch = WriteBatch::default();
+        (migration.apply)(db, cf_name, &mut batch)?;
+        version += 1;
+        batch.put_cf(&cf, SCHEMA_VERSION_KEY, bincode::serialize(&version).unwrap());
+        db.write(batch)?;
+    }
+    if !owned {
+        db.put_cf(&cf, SCHEMA_STORE_KEY, bincode::serialize(schema.store).unwrap())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{thread_rng, RngCore};
+    use rocksdb::DEFAULT_COLUMN_FAMILY_NAME as CF;
+
+    use crate::rocks::schema::{check, migrate, stored_version, Migration, Schema, SchemaError};
+    use crate::rocks::{open, RocksConfig, RocksDB};
+
+    fn db() -> Arc<RocksDB> {
+        let rnd = thread_rng().next_u32();
+        open(&RocksConfig::new(format!("./tmp/{}", rnd)))
+    }
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            from_version: 1,
+            description: "add b",
+            apply: |_, _, batch| {
+                batch.put("b", "2");
+                Ok(())
+            },
+        },
+        Migration {
+            from_version: 2,
+            description: "rewrite b",
+            apply: |db, _, batch| {
+                let b = db.get("b")?.unwrap();
+                batch.put("b", [b, b"3".to_vec()].concat());
+                Ok(())
+            },
+        },
+    ];
+
+    const SCHEMA_V3: Schema = Schema {
+        store: "test",
+        version: 3,
+        migrations: MIGRATIONS,
+    };
+
+    #[test]
+    fn fresh_db_is_stamped_with_current_version() {
+        let db = db();
+        migrate(&db, CF, &SCHEMA_V3).unwrap();
+        assert_eq!(stored_version(&db, CF).unwrap(), Some(3));
+        assert_eq!(db.get("b").unwrap(), None);
+    }
+
+    #[test]
+    fn legacy_db_is_migrated_in_order() {
+        let db = db();
+        db.put("a", "1").unwrap();
+        migrate(&db, CF, &SCHEMA_V3).unwrap();
+        assert_eq!(stored_version(&db, CF).unwrap(), Some(3));
+        assert_eq!(db.get("b").unwrap(), Some(b"23".to_vec()));
+        // Migrations are not applied twice.
+        migrate(&db, CF, &SCHEMA_V3).unwrap();
+        assert_eq!(db.get("b").unwrap(), Some(b"23".to_vec()));
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let db = db();
+        migrate(&db, CF, &SCHEMA_V3).unwrap();
+        let schema_v2 = Schema {
+            store: "test",
+            version: 2,
+            migrations: &MIGRATIONS[..1],
+        };
+        assert!(matches!(
+            migrate(&db, CF, &schema_v2),
+            Err(SchemaError::UnsupportedVersion {
+                stored: 3,
+                supported: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn missing_migration_is_reported() {
+        let db = db();
+        db.put("a", "1").unwrap();
+        let schema = Schema {
+            store: "test",
+            version: 3,
+            migrations: &MIGRATIONS[..1],
+        };
+        assert!(matches!(
+            migrate(&db, CF, &schema),
+            Err(SchemaError::MissingMigration { from_version: 2, .. })
+        ));
+        assert_eq!(stored_version(&db, CF).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn check_does_not_migrate() {
+        let db = db();
+        db.put("a", "1").unwrap();
+        assert!(matches!(
+            check(&db, CF, &SCHEMA_V3),
+            Err(SchemaError::OutdatedVersion { stored: 1, .. })
+        ));
+        assert_eq!(stored_version(&db, CF).unwrap(), None);
+        migrate(&db, CF, &SCHEMA_V3).unwrap();
+        assert!(check(&db, CF, &SCHEMA_V3).is_ok());
+    }
+
+    #[test]
+    fn stores_sharing_column_family_are_rejected() {
+        let db = db();
+        migrate(&db, CF, &SCHEMA_V3).unwrap();
+        let other = Schema {
+            store: "other",
+            version: 1,
+            migrations: &[],
+        };
+        assert!(matches!(
+            migrate(&db, CF, &other),
+            Err(SchemaError::SharedColumnFamily { ref owner, .. }) if owner == "test"
+        ));
+        assert!(matches!(
+            check(&db, CF, &other),
+            Err(SchemaError::SharedColumnFamily { .. })
+        ));
+        assert!(migrate(&db, CF, &SCHEMA_V3).is_ok());
+    }
+}